@@ -1,4 +1,9 @@
+#[cfg(feature = "driver")]
 fn main() -> Result<(), wdk_build::ConfigError> {
     println!("Starting build process...");
     wdk_build::configure_wdk_binary_build()
 }
+
+// The host simulation is an ordinary std crate and needs no WDK configuration.
+#[cfg(not(feature = "driver"))]
+fn main() {}
@@ -0,0 +1,206 @@
+//! Command line of `wdk-mutex-test-ctl`, independent of how the device is reached.
+
+use std::{fs, io::Write, path::{Path, PathBuf}};
+
+use wdk_mutex_tests_protocol::{Status, Suite, TestFilter, TestOutcome};
+
+use crate::{report::{self, OutputFormat}, transcript, Client, Transport};
+
+pub const USAGE: &str = "\
+usage: wdk-mutex-test-ctl <command> [options]
+
+commands:
+  list            list the registered tests
+  run             run tests and report their outcomes
+  results         report the outcomes of the last run, such as the one at load
+  log             print and drain the driver's log
+  parse-log FILE  report the runs recorded in captured driver output, such as a DebugView log,
+                  without a device; tests that started but never finished count as failed
+
+options:
+  --name PATTERN     only tests with this name, or prefix ending in *
+  --tag TAG          only tests carrying this tag
+  --suite SUITE      only tests of this suite: KMutex, FastMutex, Grt or LockOrder
+  --format FORMAT    stdout format: table (default), json or junit
+  --junit FILE       also write the outcomes as JUnit XML to FILE
+  --json FILE        also write the outcomes as JSON to FILE
+  --log FILE         after run or results, drain the driver's log to FILE
+
+exit status: 0 if every selected test passed or was skipped, 1 if any failed or none ran, or
+for parse-log if a run never finished, 2 on bad usage or if the device could not be used";
+
+/// Every test that ran passed or was skipped.
+pub const EXIT_PASSED: i32 = 0;
+/// A test failed, or no test matched the filter.
+pub const EXIT_FAILED: i32 = 1;
+/// Bad usage, or the device could not be reached.
+pub const EXIT_ERROR: i32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    List,
+    Run,
+    Results,
+    Log,
+    /// Report from a transcript instead of the device.
+    ParseLog,
+}
+
+/// A parsed command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Command {
+    pub action: Action,
+    pub filter: TestFilter,
+    pub format: OutputFormat,
+    pub junit: Option<PathBuf>,
+    pub json: Option<PathBuf>,
+    pub log: Option<PathBuf>,
+    /// The transcript `parse-log` reads.
+    pub transcript: Option<PathBuf>,
+}
+
+impl Action {
+    /// Whether the device must be opened for writing, which only administrators may do. Only
+    /// running tests needs it, see `IOCTL_RUN_TESTS`.
+    pub fn needs_write(&self) -> bool {
+        *self == Action::Run
+    }
+}
+
+impl Command {
+    /// Parse the arguments following the program name.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut args = args.into_iter();
+        let action = match args.next().as_deref() {
+            Some("list") => Action::List,
+            Some("run") => Action::Run,
+            Some("results") => Action::Results,
+            Some("log") => Action::Log,
+            Some("parse-log") => Action::ParseLog,
+            Some(other) => return Err(format!("unknown command `{other}`")),
+            None => return Err("no command given".into()),
+        };
+
+        let mut command = Command {
+            action,
+            filter: TestFilter::default(),
+            format: OutputFormat::Table,
+            junit: None,
+            json: None,
+            log: None,
+            transcript: None,
+        };
+        if action == Action::ParseLog {
+            command.transcript = Some(args.next().ok_or("parse-log needs the file to read")?.into());
+        }
+        while let Some(option) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("{option} needs a value"));
+            match option.as_str() {
+                "--name" => command.filter.name = Some(value()?),
+                "--tag" => command.filter.tag = Some(value()?),
+                "--suite" => {
+                    let name = value()?;
+                    let suite = Suite::from_name(&name).ok_or_else(|| format!("unknown suite `{name}`"))?;
+                    command.filter.suite = Some(suite);
+                },
+                "--format" => {
+                    let name = value()?;
+                    command.format = OutputFormat::from_name(&name).ok_or_else(|| format!("unknown format `{name}`"))?;
+                },
+                "--junit" => command.junit = Some(value()?.into()),
+                "--json" => command.json = Some(value()?.into()),
+                "--log" => command.log = Some(value()?.into()),
+                _ => return Err(format!("unknown option `{option}`")),
+            }
+        }
+
+        if command.action == Action::ParseLog && command.log.is_some() {
+            return Err("--log needs the device, parse-log does not use it".into());
+        }
+
+        Ok(command)
+    }
+}
+
+/// Carry out `command` through `client`, writing the report to `out`, and return the exit status.
+pub fn execute<T: Transport>(command: &Command, client: &mut Client<T>, out: &mut impl Write) -> Result<i32, String> {
+    let outcomes = match command.action {
+        Action::ParseLog => return parse_log(command, out),
+        Action::List => {
+            let tests = client.list_tests(&command.filter).map_err(|e| e.to_string())?;
+            write_out(out, &report::tests(&tests, command.format))?;
+            return Ok(EXIT_PASSED);
+        },
+        Action::Log => {
+            let log = client.read_log().map_err(|e| e.to_string())?;
+            write_out(out, &log)?;
+            return Ok(EXIT_PASSED);
+        },
+        Action::Run => client.run_tests(&command.filter).map_err(|e| e.to_string())?,
+        Action::Results => {
+            let results = client.results().map_err(|e| e.to_string())?;
+            filtered(results, &command.filter)
+        },
+    };
+
+    write_reports(command, &outcomes, out)?;
+    if let Some(path) = &command.log {
+        let log = client.read_log().map_err(|e| e.to_string())?;
+        write_file(path, &log)?;
+    }
+
+    Ok(exit_status(&outcomes))
+}
+
+/// Carry out a `parse-log` command, which needs no device, writing the report to `out`, and
+/// return the exit status. A run that never finished fails even if every test recorded passed.
+pub fn parse_log(command: &Command, out: &mut impl Write) -> Result<i32, String> {
+    let path = command.transcript.as_deref().ok_or("parse-log needs the file to read")?;
+    let bytes = fs::read(path).map_err(|e| format!("reading {} failed: {e}", path.display()))?;
+    let transcript = transcript::parse(&String::from_utf8_lossy(&bytes));
+    let outcomes = filtered(transcript.outcomes, &command.filter);
+
+    write_reports(command, &outcomes, out)?;
+    if command.format == OutputFormat::Table {
+        if !transcript.metrics.is_empty() {
+            write_out(out, &report::metrics_table(&transcript.metrics))?;
+        }
+        if transcript.unfinished_runs > 0 {
+            write_out(out, &format!("{} of {} runs never finished.\n", transcript.unfinished_runs, transcript.runs))?;
+        }
+    }
+
+    Ok(if transcript.unfinished_runs > 0 { EXIT_FAILED } else { exit_status(&outcomes) })
+}
+
+/// Write `outcomes` to `out` in the chosen format and to the report files asked for.
+fn write_reports(command: &Command, outcomes: &[TestOutcome], out: &mut impl Write) -> Result<(), String> {
+    write_out(out, &report::outcomes(outcomes, command.format))?;
+    if let Some(path) = &command.junit {
+        write_file(path, &report::junit(outcomes))?;
+    }
+    if let Some(path) = &command.json {
+        write_file(path, &report::outcomes_json(outcomes))?;
+    }
+    Ok(())
+}
+
+fn exit_status(outcomes: &[TestOutcome]) -> i32 {
+    let failed = outcomes.iter().any(|o| o.status == Status::Failed);
+    if failed || outcomes.is_empty() { EXIT_FAILED } else { EXIT_PASSED }
+}
+
+/// The outcomes of tests selected by `filter`. Outcomes carry no tags, so a tag filter cannot be
+/// applied to them and is ignored.
+fn filtered(outcomes: Vec<TestOutcome>, filter: &TestFilter) -> Vec<TestOutcome> {
+    let filter = TestFilter { tag: None, ..filter.clone() };
+    outcomes.into_iter().filter(|o| filter.matches(&o.name, o.suite, &[])).collect()
+}
+
+fn write_out(out: &mut impl Write, s: &str) -> Result<(), String> {
+    out.write_all(s.as_bytes()).map_err(|e| format!("writing the report failed: {e}"))
+}
+
+fn write_file(path: &Path, s: &str) -> Result<(), String> {
+    fs::write(path, s).map_err(|e| format!("writing {} failed: {e}", path.display()))
+}
@@ -0,0 +1,78 @@
+//! [`Transport`] over the real `\\.\WdkMutexTest` device.
+
+use std::{io, iter::once, ptr::{null, null_mut}};
+
+use windows_sys::Win32::{Foundation::{CloseHandle, ERROR_MORE_DATA, GENERIC_READ, GENERIC_WRITE, HANDLE, INVALID_HANDLE_VALUE}, Storage::FileSystem::{CreateFileW, ReadFile, FILE_ATTRIBUTE_NORMAL, FILE_SHARE_READ, FILE_SHARE_WRITE, OPEN_EXISTING}, System::IO::DeviceIoControl};
+
+use crate::Transport;
+
+/// An open handle to the control device.
+pub struct Device(HANDLE);
+
+impl Device {
+    /// Open the device at `path`, usually [`crate::DEVICE_PATH`], for reading and, if `write`,
+    /// writing, see [`crate::cli::Action::needs_write`].
+    pub fn open(path: &str, write: bool) -> io::Result<Self> {
+        let path: Vec<u16> = path.encode_utf16().chain(once(0)).collect();
+        let access = if write { GENERIC_READ | GENERIC_WRITE } else { GENERIC_READ };
+        let handle = unsafe {
+            CreateFileW(
+                path.as_ptr(),
+                access,
+                FILE_SHARE_READ | FILE_SHARE_WRITE,
+                null(),
+                OPEN_EXISTING,
+                FILE_ATTRIBUTE_NORMAL,
+                null_mut(),
+            )
+        };
+        if handle == INVALID_HANDLE_VALUE {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self(handle))
+    }
+}
+
+impl Transport for Device {
+    fn ioctl(&mut self, code: u32, input: &[u8], output: &mut [u8]) -> io::Result<usize> {
+        let mut returned = 0u32;
+        let ok = unsafe {
+            DeviceIoControl(
+                self.0,
+                code,
+                input.as_ptr().cast(),
+                input.len() as u32,
+                output.as_mut_ptr().cast(),
+                output.len() as u32,
+                &mut returned,
+                null_mut(),
+            )
+        };
+        if ok == 0 {
+            let e = io::Error::last_os_error();
+            // STATUS_BUFFER_OVERFLOW, the header was returned and says how much to ask for
+            if e.raw_os_error() != Some(ERROR_MORE_DATA as i32) {
+                return Err(e);
+            }
+        }
+
+        Ok(returned as usize)
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut read = 0u32;
+        let ok = unsafe { ReadFile(self.0, buf.as_mut_ptr(), buf.len() as u32, &mut read, null_mut()) };
+        if ok == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(read as usize)
+    }
+}
+
+impl Drop for Device {
+    fn drop(&mut self) {
+        unsafe { CloseHandle(self.0) };
+    }
+}
@@ -0,0 +1,96 @@
+//! An in-process stand-in for the driver's control device, for testing the controller on hosts
+//! without it.
+//!
+//! It decodes requests with the same protocol crate as the driver and follows the driver's buffer
+//! rules, so a response larger than the output buffer returns only its header. Tests "run" by
+//! reporting the outcome they were set up with.
+
+use std::io;
+
+use wdk_mutex_tests_protocol::{Request, Response, Status, TestFilter, TestInfo, TestOutcome, RESPONSE_HEADER_LEN};
+
+use crate::Transport;
+
+/// A test registered with a [`FakeDevice`], and how it goes when run.
+#[derive(Debug, Clone)]
+pub struct FakeTest {
+    pub info: TestInfo,
+    pub status: Status,
+    pub reason: String,
+    pub duration_us: u64,
+}
+
+/// A control device backed by a fixed list of tests.
+#[derive(Debug, Default)]
+pub struct FakeDevice {
+    pub tests: Vec<FakeTest>,
+    /// Outcomes of the last run, returned by `GetResults`.
+    pub last_run: Vec<TestOutcome>,
+    /// What a `ReadFile` drains, one line per test run.
+    pub log: Vec<u8>,
+    /// Every request received, in order.
+    pub requests: Vec<Request>,
+}
+
+impl FakeDevice {
+    pub fn new(tests: Vec<FakeTest>) -> Self {
+        Self { tests, ..Self::default() }
+    }
+
+    fn run(&mut self, filter: &TestFilter) -> Vec<TestOutcome> {
+        let outcomes: Vec<_> = self
+            .tests
+            .iter()
+            .filter(|t| {
+                let tags: Vec<_> = t.info.tags.iter().map(String::as_str).collect();
+                filter.matches(&t.info.name, t.info.suite, &tags)
+            })
+            .map(|t| TestOutcome {
+                name: t.info.name.clone(),
+                suite: t.info.suite,
+                status: t.status,
+                reason: t.reason.clone(),
+                duration_us: t.duration_us,
+            })
+            .collect();
+
+        for o in &outcomes {
+            self.log.extend_from_slice(format!("{} {}\n", o.status.name(), o.name).as_bytes());
+        }
+        self.last_run = outcomes.clone();
+        outcomes
+    }
+}
+
+impl Transport for FakeDevice {
+    fn ioctl(&mut self, code: u32, input: &[u8], output: &mut [u8]) -> io::Result<usize> {
+        let request = Request::decode(code, input).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        self.requests.push(request.clone());
+
+        let format = request.format();
+        let response = match request {
+            Request::ListTests { .. } => Response::Tests(self.tests.iter().map(|t| t.info.clone()).collect()),
+            Request::RunTests { filter, .. } => Response::Results(self.run(&filter)),
+            Request::GetResults { .. } => Response::Results(self.last_run.clone()),
+        };
+        let response = response.encode(format);
+
+        // as the driver's output_len: everything, just the header, or nothing
+        let len = if output.len() >= response.len() {
+            response.len()
+        } else if output.len() >= RESPONSE_HEADER_LEN {
+            RESPONSE_HEADER_LEN
+        } else {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "output buffer too small"));
+        };
+        output[..len].copy_from_slice(&response[..len]);
+        Ok(len)
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = buf.len().min(self.log.len());
+        buf[..n].copy_from_slice(&self.log[..n]);
+        self.log.drain(..n);
+        Ok(n)
+    }
+}
@@ -0,0 +1,156 @@
+//! User-mode controller for the `\\.\WdkMutexTest` device.
+//!
+//! Lists and runs the driver's tests and turns the results into something CI can consume: JUnit
+//! XML, JSON or a table for people. Everything talks to the driver through a [`Transport`], so
+//! apart from [`device`], which only builds on Windows, the whole controller, command line
+//! included, runs on any host against the in-process [`fake::FakeDevice`].
+//!
+//! Runs the controller cannot reach, such as the one at load on a machine that then crashed, can
+//! still be reported from their captured debug output with [`transcript`].
+
+pub mod cli;
+#[cfg(windows)]
+pub mod device;
+pub mod fake;
+pub mod report;
+pub mod transcript;
+
+use std::{fmt, io};
+
+use wdk_mutex_tests_protocol::{DecodeError, Format, Request, Response, ResponseHeader, TestFilter, TestInfo, TestOutcome, RESPONSE_HEADER_LEN};
+
+/// Path user mode opens the control device through.
+pub const DEVICE_PATH: &str = r"\\.\WdkMutexTest";
+
+/// Output buffer size tried first, large enough for most responses in one round trip.
+const INITIAL_OUTPUT_LEN: usize = 64 * 1024;
+
+/// Moves bytes to and from the control device.
+pub trait Transport {
+    /// `DeviceIoControl` with `code` and `input`, returning the number of bytes written to
+    /// `output`.
+    ///
+    /// A response that did not fit but whose header did is not an error: only the header is
+    /// returned, and its `total_len` tells the caller what size to retry with.
+    fn ioctl(&mut self, code: u32, input: &[u8], output: &mut [u8]) -> io::Result<usize>;
+
+    /// `ReadFile`, returning the number of bytes read, 0 once the driver's log is empty.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>;
+}
+
+/// Why a request to the device failed.
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Decode(DecodeError),
+    /// The device answered with a different kind of response than was asked for.
+    UnexpectedResponse,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "device I/O failed: {e}"),
+            Error::Decode(e) => write!(f, "bad response from the device: {e}"),
+            Error::UnexpectedResponse => f.write_str("the device answered with an unexpected response"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<DecodeError> for Error {
+    fn from(e: DecodeError) -> Self {
+        Error::Decode(e)
+    }
+}
+
+/// The requests user mode can make, over any [`Transport`].
+pub struct Client<T> {
+    transport: T,
+}
+
+impl<T: Transport> Client<T> {
+    pub fn new(transport: T) -> Self {
+        Self { transport }
+    }
+
+    pub fn into_transport(self) -> T {
+        self.transport
+    }
+
+    /// The registered tests selected by `filter`.
+    pub fn list_tests(&mut self, filter: &TestFilter) -> Result<Vec<TestInfo>, Error> {
+        // the device lists everything, the filter is applied here the same way the driver would
+        match self.request(&Request::ListTests { format: Format::Binary })? {
+            Response::Tests(tests) => Ok(tests
+                .into_iter()
+                .filter(|t| {
+                    let tags: Vec<_> = t.tags.iter().map(String::as_str).collect();
+                    filter.matches(&t.name, t.suite, &tags)
+                })
+                .collect()),
+            Response::Results(_) => Err(Error::UnexpectedResponse),
+        }
+    }
+
+    /// Run the tests selected by `filter` and return their outcomes, waiting for the run to end.
+    pub fn run_tests(&mut self, filter: &TestFilter) -> Result<Vec<TestOutcome>, Error> {
+        let request = Request::RunTests { format: Format::Binary, filter: filter.clone() };
+        match self.request(&request)? {
+            Response::Results(outcomes) => Ok(outcomes),
+            Response::Tests(_) => Err(Error::UnexpectedResponse),
+        }
+    }
+
+    /// The outcomes of the most recent run, including the one at load.
+    pub fn results(&mut self) -> Result<Vec<TestOutcome>, Error> {
+        match self.request(&Request::GetResults { format: Format::Binary })? {
+            Response::Results(outcomes) => Ok(outcomes),
+            Response::Tests(_) => Err(Error::UnexpectedResponse),
+        }
+    }
+
+    /// Drain the driver's log, see its `log` module.
+    pub fn read_log(&mut self) -> Result<String, Error> {
+        let mut log = Vec::new();
+        let mut buf = vec![0; INITIAL_OUTPUT_LEN];
+        loop {
+            let n = self.transport.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            log.extend_from_slice(&buf[..n]);
+        }
+
+        Ok(String::from_utf8_lossy(&log).into_owned())
+    }
+
+    /// Send `request` and decode the response, retrying once with a buffer of the size the device
+    /// asked for if the first one was too small.
+    fn request(&mut self, request: &Request) -> Result<Response, Error> {
+        let input = request.encode();
+        let mut output = vec![0; INITIAL_OUTPUT_LEN];
+
+        let mut len = self.transport.ioctl(request.ioctl_code(), &input, &mut output)?;
+        let header = ResponseHeader::decode(&output[..len])?;
+        let total_len = header.total_len as usize;
+        if total_len > len && total_len >= RESPONSE_HEADER_LEN {
+            // a run would be repeated by sending RunTests again, fetch its results instead
+            let retry = match request {
+                Request::RunTests { format, .. } => Request::GetResults { format: *format },
+                _ => request.clone(),
+            };
+            output.resize(total_len, 0);
+            len = self.transport.ioctl(retry.ioctl_code(), &retry.encode(), &mut output)?;
+        }
+
+        Ok(Response::decode(&output[..len])?)
+    }
+}
@@ -0,0 +1,60 @@
+//! `wdk-mutex-test-ctl`, see [`wdk_mutex_tests_controller::cli::USAGE`].
+
+use std::process::exit;
+
+use wdk_mutex_tests_controller::cli::{self, Action, Command, EXIT_ERROR};
+
+fn main() {
+    let command = match Command::parse(std::env::args().skip(1)) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("{e}\n\n{}", cli::USAGE);
+            exit(EXIT_ERROR);
+        },
+    };
+
+    if command.action == Action::ParseLog {
+        exit(match cli::parse_log(&command, &mut std::io::stdout().lock()) {
+            Ok(status) => status,
+            Err(e) => {
+                eprintln!("{e}");
+                EXIT_ERROR
+            },
+        });
+    }
+
+    exit(run(&command));
+}
+
+#[cfg(windows)]
+fn run(command: &Command) -> i32 {
+    use std::io;
+
+    use wdk_mutex_tests_controller::{device::Device, Client, DEVICE_PATH};
+
+    let device = match Device::open(DEVICE_PATH, command.action.needs_write()) {
+        Ok(device) => device,
+        Err(e) if command.action.needs_write() && e.kind() == io::ErrorKind::PermissionDenied => {
+            eprintln!("opening {DEVICE_PATH} failed, running tests needs an elevated prompt: {e}");
+            return EXIT_ERROR;
+        },
+        Err(e) => {
+            eprintln!("opening {DEVICE_PATH} failed, is the driver loaded? {e}");
+            return EXIT_ERROR;
+        },
+    };
+
+    match cli::execute(command, &mut Client::new(device), &mut io::stdout().lock()) {
+        Ok(status) => status,
+        Err(e) => {
+            eprintln!("{e}");
+            EXIT_ERROR
+        },
+    }
+}
+
+#[cfg(not(windows))]
+fn run(_command: &Command) -> i32 {
+    eprintln!("the control device only exists on Windows");
+    EXIT_ERROR
+}
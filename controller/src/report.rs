@@ -0,0 +1,181 @@
+//! Renderings of test lists and outcomes: JUnit XML for CI, JSON, and tables for people.
+
+use std::fmt::Write;
+
+use wdk_mutex_tests_protocol::{encode_json, Response, Status, Suite, TestInfo, TestOutcome};
+
+use crate::transcript::Metric;
+
+/// Name of the outermost `<testsuites>` element.
+const JUNIT_NAME: &str = "wdk-mutex-test";
+
+/// How results are written to stdout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Table,
+    Json,
+    Junit,
+}
+
+impl OutputFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "table" => Some(OutputFormat::Table),
+            "json" => Some(OutputFormat::Json),
+            "junit" => Some(OutputFormat::Junit),
+            _ => None,
+        }
+    }
+}
+
+/// `outcomes` in `format`.
+pub fn outcomes(outcomes: &[TestOutcome], format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Table => outcomes_table(outcomes),
+        OutputFormat::Json => outcomes_json(outcomes),
+        OutputFormat::Junit => junit(outcomes),
+    }
+}
+
+/// `tests` in `format`, JUnit having nothing to say about tests that have not run.
+pub fn tests(tests: &[TestInfo], format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Table | OutputFormat::Junit => tests_table(tests),
+        OutputFormat::Json => {
+            let mut out = encode_json(&Response::Tests(tests.to_vec()));
+            out.push('\n');
+            out
+        },
+    }
+}
+
+/// The same JSON the driver returns for a `Format::Json` request.
+pub fn outcomes_json(outcomes: &[TestOutcome]) -> String {
+    let mut out = encode_json(&Response::Results(outcomes.to_vec()));
+    out.push('\n');
+    out
+}
+
+/// One `<testsuite>` per suite that ran, failures and skips carrying their reason.
+pub fn junit(outcomes: &[TestOutcome]) -> String {
+    let count = |outcomes: &[&TestOutcome], status| outcomes.iter().filter(|o| o.status == status).count();
+    let all: Vec<_> = outcomes.iter().collect();
+
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(
+        out,
+        "<testsuites name=\"{JUNIT_NAME}\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{}\">",
+        all.len(),
+        count(&all, Status::Failed),
+        count(&all, Status::Skipped),
+        seconds(outcomes.iter().map(|o| o.duration_us).sum()),
+    );
+
+    for suite in Suite::ALL {
+        let ran: Vec<_> = outcomes.iter().filter(|o| o.suite == suite).collect();
+        if ran.is_empty() {
+            continue;
+        }
+
+        let _ = writeln!(
+            out,
+            "  <testsuite name=\"{suite}\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{}\">",
+            ran.len(),
+            count(&ran, Status::Failed),
+            count(&ran, Status::Skipped),
+            seconds(ran.iter().map(|o| o.duration_us).sum()),
+        );
+        for o in ran {
+            let _ = write!(
+                out,
+                "    <testcase name=\"{}\" classname=\"{suite}\" time=\"{}\"",
+                xml_escape(&o.name),
+                seconds(o.duration_us),
+            );
+            match o.status {
+                Status::Passed => out.push_str("/>\n"),
+                Status::Failed => {
+                    let _ = writeln!(out, ">\n      <failure message=\"{}\"/>\n    </testcase>", xml_escape(&o.reason));
+                },
+                Status::Skipped => {
+                    let _ = writeln!(out, ">\n      <skipped message=\"{}\"/>\n    </testcase>", xml_escape(&o.reason));
+                },
+            }
+        }
+        out.push_str("  </testsuite>\n");
+    }
+
+    out.push_str("</testsuites>\n");
+    out
+}
+
+/// One row per outcome followed by the totals, e.g. `Passed: 4, failed: 1, skipped: 0.`
+pub fn outcomes_table(outcomes: &[TestOutcome]) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "{:<7} {:<10} {:<55} {:>12}  REASON", "STATUS", "SUITE", "TEST", "TIME (us)");
+    for o in outcomes {
+        let _ = writeln!(
+            out,
+            "{:<7} {:<10} {:<55} {:>12}  {}",
+            o.status.name().to_uppercase(),
+            o.suite.name(),
+            o.name,
+            o.duration_us,
+            o.reason,
+        );
+    }
+
+    let count = |status| outcomes.iter().filter(|o| o.status == status).count();
+    let _ = writeln!(
+        out,
+        "Passed: {}, failed: {}, skipped: {}.",
+        count(Status::Passed),
+        count(Status::Failed),
+        count(Status::Skipped),
+    );
+    out
+}
+
+/// One row per test with its tags.
+pub fn tests_table(tests: &[TestInfo]) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "{:<10} {:<55} TAGS", "SUITE", "TEST");
+    for t in tests {
+        let _ = writeln!(out, "{:<10} {:<55} {}", t.suite.name(), t.name, t.tags.join(","));
+    }
+    out
+}
+
+/// One row per metric, in the order the tests reported them.
+pub fn metrics_table(metrics: &[Metric]) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "{:<40} {:<40} {:>14}  UNIT", "TEST", "METRIC", "VALUE");
+    for m in metrics {
+        let _ = writeln!(out, "{:<40} {:<40} {:>14}  {}", m.test, m.name, m.value, m.unit);
+    }
+    out
+}
+
+/// Microseconds as JUnit's fractional seconds.
+fn seconds(us: u64) -> String {
+    format!("{}.{:06}", us / 1_000_000, us % 1_000_000)
+}
+
+/// `s` escaped for use in an XML attribute. Control characters XML 1.0 cannot carry are replaced.
+fn xml_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            '\n' => out.push_str("&#10;"),
+            '\t' => out.push_str("&#9;"),
+            c if (c as u32) < 0x20 => out.push('\u{fffd}'),
+            c => out.push(c),
+        }
+    }
+    out
+}
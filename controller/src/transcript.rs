@@ -0,0 +1,96 @@
+//! Results recovered from captured driver output, such as a DebugView or WinDbg log, by way of
+//! the line format records in it, see [`Event`].
+
+use wdk_mutex_tests_protocol::{Event, Status, Suite, TestOutcome};
+
+/// A measurement reported by a test.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Metric {
+    pub test: String,
+    pub name: String,
+    pub value: u64,
+    pub unit: String,
+}
+
+/// Everything found in a transcript, in the order it was printed.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Transcript {
+    /// One per finished test, plus a failed one for each test that started but never finished.
+    pub outcomes: Vec<TestOutcome>,
+    pub metrics: Vec<Metric>,
+    /// Runs that started.
+    pub runs: usize,
+    /// Runs that started but never printed their end, because the machine or the driver went down.
+    pub unfinished_runs: usize,
+}
+
+impl Transcript {
+    /// Tests that started but never finished.
+    pub fn crashed(&self) -> impl Iterator<Item = &TestOutcome> {
+        self.outcomes.iter().filter(|o| o.status == Status::Failed && o.reason.starts_with(CRASHED))
+    }
+}
+
+/// Start of the reason given to a test that never finished.
+pub const CRASHED: &str = "crashed:";
+
+/// Pick the records out of `text` and rebuild the outcomes of the runs they describe. Lines that
+/// hold no record are ignored.
+pub fn parse(text: &str) -> Transcript {
+    let mut transcript = Transcript::default();
+    let mut running: Option<(Suite, String)> = None;
+    let mut run_open = false;
+
+    for line in text.lines() {
+        let Some(event) = Event::parse(line) else {
+            continue;
+        };
+
+        match event {
+            Event::RunStart { .. } => {
+                crash(&mut transcript, running.take(), "the next run started");
+                if run_open {
+                    transcript.unfinished_runs += 1;
+                }
+                transcript.runs += 1;
+                run_open = true;
+            },
+            Event::Start { suite, test } => {
+                crash(&mut transcript, running.take(), "the next test started");
+                running = Some((suite, test));
+            },
+            Event::Result { suite, test, status, duration_us, reason } => {
+                if running.as_ref().is_some_and(|(_, name)| *name != test) {
+                    crash(&mut transcript, running.take(), "another test finished");
+                }
+                running = None;
+                transcript.outcomes.push(TestOutcome { name: test, suite, status, reason, duration_us });
+            },
+            Event::Metric { test, name, value, unit } => transcript.metrics.push(Metric { test, name, value, unit }),
+            Event::RunEnd { .. } => {
+                crash(&mut transcript, running.take(), "the run ended");
+                run_open = false;
+            },
+        }
+    }
+
+    crash(&mut transcript, running, "the transcript ended");
+    if run_open {
+        transcript.unfinished_runs += 1;
+    }
+
+    transcript
+}
+
+/// Record `test`, if any, as having started but not finished before `what`.
+fn crash(transcript: &mut Transcript, test: Option<(Suite, String)>, what: &str) {
+    if let Some((suite, name)) = test {
+        transcript.outcomes.push(TestOutcome {
+            name,
+            suite,
+            status: Status::Failed,
+            reason: format!("{CRASHED} started but never finished before {what}"),
+            duration_us: 0,
+        });
+    }
+}
@@ -0,0 +1,282 @@
+//! The controller, command line included, against the in-process fake device.
+
+use wdk_mutex_tests_controller::{cli::{self, Action, Command, EXIT_FAILED, EXIT_PASSED}, fake::{FakeDevice, FakeTest}, report, transcript, Client};
+use wdk_mutex_tests_protocol::{Event, Request, Status, Suite, TestFilter, TestInfo};
+
+fn test(name: &str, suite: Suite, tags: &[&str], status: Status, reason: &str) -> FakeTest {
+    FakeTest {
+        info: TestInfo {
+            name: name.into(),
+            suite,
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+        },
+        status,
+        reason: reason.into(),
+        duration_us: 1_250,
+    }
+}
+
+fn device() -> FakeDevice {
+    FakeDevice::new(vec![
+        test("kmutex::to_owned", Suite::KMutex, &[], Status::Passed, ""),
+        test("kmutex::grt_churn", Suite::KMutex, &["multithread", "churn"], Status::Failed, "expected <1> win & got 2"),
+        test("fast_mutex::to_owned", Suite::FastMutex, &[], Status::Passed, ""),
+        test("grt::init_under_alloc_failure", Suite::Grt, &["alloc"], Status::Skipped, "not injectable"),
+    ])
+}
+
+fn args(s: &str) -> Vec<String> {
+    s.split_whitespace().map(String::from).collect()
+}
+
+/// Run the command line `s` against `device`, returning the exit status and stdout.
+fn run(device: FakeDevice, s: &str) -> (i32, String, Client<FakeDevice>) {
+    let command = Command::parse(args(s)).unwrap();
+    let mut client = Client::new(device);
+    let mut out = Vec::new();
+    let status = cli::execute(&command, &mut client, &mut out).unwrap();
+    (status, String::from_utf8(out).unwrap(), client)
+}
+
+#[test]
+fn parses_filters_and_outputs() {
+    let command = Command::parse(args("run --name kmutex::* --tag churn --suite fastmutex --format junit --junit out.xml")).unwrap();
+
+    assert_eq!(command.action, Action::Run);
+    assert_eq!(
+        command.filter,
+        TestFilter { name: Some("kmutex::*".into()), tag: Some("churn".into()), suite: Some(Suite::FastMutex) },
+    );
+    assert_eq!(command.format, report::OutputFormat::Junit);
+    assert_eq!(command.junit.as_deref(), Some("out.xml".as_ref()));
+}
+
+#[test]
+fn only_run_opens_the_device_for_writing() {
+    for (s, write) in [("list", false), ("run", true), ("results", false), ("log", false), ("parse-log driver.log", false)] {
+        assert_eq!(Command::parse(args(s)).unwrap().action.needs_write(), write, "{s}");
+    }
+}
+
+#[test]
+fn rejects_bad_usage() {
+    for bad in ["", "frobnicate", "run --name", "run --suite Spinlock", "run --format yaml", "list --verbose"] {
+        assert!(Command::parse(args(bad)).is_err(), "`{bad}` was accepted");
+    }
+}
+
+#[test]
+fn list_applies_the_filter() {
+    let (status, out, _) = run(device(), "list --tag churn");
+
+    assert_eq!(status, EXIT_PASSED);
+    assert!(out.contains("kmutex::grt_churn"), "{out}");
+    assert!(out.contains("multithread,churn"), "{out}");
+    assert!(!out.contains("kmutex::to_owned"), "{out}");
+}
+
+#[test]
+fn exit_status_follows_the_outcomes() {
+    let (status, out, _) = run(device(), "run --name *");
+    assert_eq!(status, EXIT_FAILED, "the full run has a failing test");
+    assert!(out.contains("Passed: 2, failed: 1, skipped: 1."), "{out}");
+
+    let (status, out, _) = run(device(), "run --suite KMutex --name kmutex::to_owned");
+    assert_eq!(status, EXIT_PASSED);
+    assert!(out.contains("PASSED  KMutex     kmutex::to_owned"), "{out}");
+}
+
+#[test]
+fn a_run_selecting_nothing_fails() {
+    let (status, out, _) = run(device(), "run --tag nonexistent");
+
+    assert_eq!(status, EXIT_FAILED);
+    assert!(out.contains("Passed: 0, failed: 0, skipped: 0."), "{out}");
+}
+
+#[test]
+fn run_sends_the_filter_to_the_device() {
+    let (_, _, client) = run(device(), "run --tag alloc --format json");
+    let device = client.into_transport();
+
+    assert_eq!(device.requests.len(), 1);
+    let Request::RunTests { filter, .. } = &device.requests[0] else {
+        panic!("{:?}", device.requests);
+    };
+    assert_eq!(filter.tag.as_deref(), Some("alloc"));
+}
+
+#[test]
+fn results_report_the_last_run() {
+    let mut device = device();
+    device.last_run = vec![wdk_mutex_tests_protocol::TestOutcome {
+        name: "fast_mutex::to_owned".into(),
+        suite: Suite::FastMutex,
+        status: Status::Failed,
+        reason: "at load".into(),
+        duration_us: 7,
+    }];
+
+    let (status, out, _) = run(device, "results --format json");
+    assert_eq!(status, EXIT_FAILED);
+    assert_eq!(
+        out,
+        "{\"version\":1,\"results\":[{\"name\":\"fast_mutex::to_owned\",\"suite\":\"FastMutex\",\"status\":\"failed\",\"reason\":\"at load\",\"duration_us\":7}]}\n",
+    );
+}
+
+#[test]
+fn junit_has_a_testsuite_per_suite_and_escapes_reasons() {
+    let (_, out, _) = run(device(), "run --format junit");
+
+    assert!(out.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n"), "{out}");
+    assert!(out.contains("<testsuites name=\"wdk-mutex-test\" tests=\"4\" failures=\"1\" skipped=\"1\" time=\"0.005000\">"), "{out}");
+    assert!(out.contains("<testsuite name=\"KMutex\" tests=\"2\" failures=\"1\" skipped=\"0\" time=\"0.002500\">"), "{out}");
+    assert!(out.contains("<testcase name=\"kmutex::to_owned\" classname=\"KMutex\" time=\"0.001250\"/>"), "{out}");
+    assert!(out.contains("<failure message=\"expected &lt;1&gt; win &amp; got 2\"/>"), "{out}");
+    assert!(out.contains("<skipped message=\"not injectable\"/>"), "{out}");
+    assert!(out.ends_with("</testsuites>\n"), "{out}");
+}
+
+#[test]
+fn report_files_and_log_are_written() {
+    let dir = std::env::temp_dir().join(format!("wdk-mutex-test-ctl-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (junit, json, log) = (dir.join("out.xml"), dir.join("out.json"), dir.join("out.log"));
+
+    let command = format!("run --name kmutex::* --junit {} --json {} --log {}", junit.display(), json.display(), log.display());
+    let (status, _, _) = run(device(), &command);
+
+    assert_eq!(status, EXIT_FAILED);
+    assert!(std::fs::read_to_string(&junit).unwrap().contains("<failure"));
+    assert!(std::fs::read_to_string(&json).unwrap().contains("\"status\":\"failed\""));
+    assert_eq!(std::fs::read_to_string(&log).unwrap(), "passed kmutex::to_owned\nfailed kmutex::grt_churn\n");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn oversized_responses_are_fetched_in_a_second_round_trip() {
+    // enough tests that the list is larger than the first output buffer
+    let tests = (0..5_000).map(|i| test(&format!("kmutex::generated_{i:05}"), Suite::KMutex, &["bulk"], Status::Passed, "")).collect();
+    let mut client = Client::new(FakeDevice::new(tests));
+
+    let listed = client.list_tests(&TestFilter::default()).unwrap();
+    assert_eq!(listed.len(), 5_000);
+
+    // a run that does not fit is not repeated, its results are fetched instead
+    let outcomes = client.run_tests(&TestFilter::default()).unwrap();
+    assert_eq!(outcomes.len(), 5_000);
+    let requests = client.into_transport().requests;
+    assert!(
+        matches!(requests[..], [Request::ListTests { .. }, Request::ListTests { .. }, Request::RunTests { .. }, Request::GetResults { .. }]),
+        "{requests:?}",
+    );
+}
+
+#[test]
+fn events_round_trip_through_their_line() {
+    let events = [
+        Event::RunStart { tests: 12 },
+        Event::Start { suite: Suite::KMutex, test: "kmutex::to_owned".into() },
+        Event::Result {
+            suite: Suite::Grt,
+            test: "grt::thrice".into(),
+            status: Status::Failed,
+            duration_us: 980,
+            reason: "expected 3, got 2\nat C:\\src\\grt.rs\r".into(),
+        },
+        Event::Result { suite: Suite::FastMutex, test: "fast_mutex::to_owned".into(), status: Status::Passed, duration_us: 7, reason: String::new() },
+        Event::Metric { test: "grt::bench_scaling".into(), name: "get_ns_per_op_1000".into(), value: 85, unit: "ns".into() },
+        Event::RunEnd { passed: 10, failed: 1, skipped: 1 },
+    ];
+
+    for event in events {
+        let line = event.to_string();
+        assert!(!line.contains('\n') && !line.contains('\r'), "{line:?}");
+        assert_eq!(Event::parse(&line), Some(event), "{line:?}");
+    }
+    assert_eq!(Event::parse("[wdk-mutex-test] [+] Test passed: kmutex::to_owned"), None);
+    assert_eq!(Event::parse("#WMT1 PASS Spinlock spin::lock 3"), None);
+    assert_eq!(Event::parse("#WMT1 PASS KMutex kmutex::to_owned soon"), None);
+}
+
+/// A DebugView capture of a run at load, each line prefixed with its index, time and process.
+const DEBUGVIEW: &str = "\
+00000001\t0.00000000\t[wdk-mutex-test] Running 3 tests
+00000002\t0.00001200\t#WMT1 RUN 3\r
+00000003\t0.00001500\t[wdk-mutex-test] Running kmutex::to_owned
+00000004\t0.00001600\t#WMT1 START KMutex kmutex::to_owned
+00000005\t0.00120000\t#WMT1 PASS KMutex kmutex::to_owned 1184
+00000006\t0.00130000\t#WMT1 START Grt grt::bench_scaling
+00000007\t0.31000000\t#WMT1 METRIC grt::bench_scaling get_ns_per_op_1000 85 ns
+00000008\t0.31000100\t#WMT1 FAIL Grt grt::bench_scaling 309000 too slow: 85 > 80\\nsee log
+00000009\t0.31000200\t#WMT1 START KMutex kmutex::grt_churn
+";
+
+#[test]
+fn transcripts_are_parsed_whatever_prefixes_the_records() {
+    let parsed = transcript::parse(DEBUGVIEW);
+
+    assert_eq!(parsed.runs, 1);
+    assert_eq!(parsed.unfinished_runs, 1);
+    let summary: Vec<_> = parsed.outcomes.iter().map(|o| (o.name.as_str(), o.status, o.duration_us)).collect();
+    assert_eq!(
+        summary,
+        [("kmutex::to_owned", Status::Passed, 1184), ("grt::bench_scaling", Status::Failed, 309_000), ("kmutex::grt_churn", Status::Failed, 0)],
+    );
+    assert_eq!(parsed.outcomes[1].reason, "too slow: 85 > 80\nsee log");
+    assert_eq!(parsed.metrics.len(), 1);
+    assert_eq!(parsed.metrics[0].value, 85);
+
+    let crashed: Vec<_> = parsed.crashed().map(|o| o.name.as_str()).collect();
+    assert_eq!(crashed, ["kmutex::grt_churn"]);
+}
+
+#[test]
+fn a_test_interrupted_by_the_next_start_counts_as_crashed() {
+    // the machine rebooted during the first run, the second ran to the end
+    let text = "\
+#WMT1 RUN 2
+#WMT1 START KMutex kmutex::to_owned
+#WMT1 RUN 1
+#WMT1 START KMutex kmutex::to_owned
+#WMT1 PASS KMutex kmutex::to_owned 5
+#WMT1 END 1 0 0
+";
+    let parsed = transcript::parse(text);
+
+    assert_eq!((parsed.runs, parsed.unfinished_runs), (2, 1));
+    assert_eq!(parsed.outcomes.len(), 2);
+    assert_eq!(parsed.outcomes[0].status, Status::Failed);
+    assert!(parsed.outcomes[0].reason.starts_with(transcript::CRASHED), "{}", parsed.outcomes[0].reason);
+    assert_eq!(parsed.outcomes[1].status, Status::Passed);
+}
+
+#[test]
+fn parse_log_reports_without_a_device() {
+    let dir = std::env::temp_dir().join(format!("wdk-mutex-test-ctl-parse-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (capture, junit) = (dir.join("capture.log"), dir.join("out.xml"));
+    std::fs::write(&capture, DEBUGVIEW).unwrap();
+
+    let command = Command::parse(args(&format!("parse-log {} --junit {}", capture.display(), junit.display()))).unwrap();
+    assert_eq!(command.action, Action::ParseLog);
+    let mut out = Vec::new();
+    let status = cli::parse_log(&command, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+
+    assert_eq!(status, EXIT_FAILED);
+    assert!(out.contains("Passed: 1, failed: 2, skipped: 0."), "{out}");
+    assert!(out.contains("get_ns_per_op_1000"), "{out}");
+    assert!(out.contains("1 of 1 runs never finished."), "{out}");
+    assert!(std::fs::read_to_string(&junit).unwrap().contains("started but never finished"));
+
+    // a complete run that passed exits cleanly, and filters apply as for results
+    std::fs::write(&capture, "#WMT1 RUN 1\n#WMT1 START KMutex kmutex::to_owned\n#WMT1 PASS KMutex kmutex::to_owned 5\n#WMT1 END 1 0 0\n").unwrap();
+    let command = Command::parse(args(&format!("parse-log {} --suite KMutex", capture.display()))).unwrap();
+    assert_eq!(cli::parse_log(&command, &mut Vec::new()), Ok(EXIT_PASSED));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(Command::parse(args("parse-log")).is_err());
+    assert!(Command::parse(args("parse-log capture.log --log out.log")).is_err());
+}
@@ -0,0 +1,76 @@
+//! Minimal JSON encoding of responses, written by hand as the driver has no `std` or serde.
+
+use core::fmt::Write;
+
+use alloc::string::String;
+
+use crate::{Response, PROTOCOL_VERSION};
+
+/// Append `s` to `out` as a quoted JSON string.
+pub(crate) fn push_json_str(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            },
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// Encode `response` as a single JSON object, e.g.
+/// `{"version":1,"results":[{"name":"..","suite":"KMutex","status":"passed",..}]}`.
+pub fn encode_json(response: &Response) -> String {
+    let mut out = String::new();
+    let _ = write!(out, "{{\"version\":{PROTOCOL_VERSION},");
+
+    match response {
+        Response::Tests(tests) => {
+            out.push_str("\"tests\":[");
+            for (i, t) in tests.iter().enumerate() {
+                if i != 0 {
+                    out.push(',');
+                }
+                out.push_str("{\"name\":");
+                push_json_str(&mut out, &t.name);
+                out.push_str(",\"suite\":");
+                push_json_str(&mut out, t.suite.name());
+                out.push_str(",\"tags\":[");
+                for (j, tag) in t.tags.iter().enumerate() {
+                    if j != 0 {
+                        out.push(',');
+                    }
+                    push_json_str(&mut out, tag);
+                }
+                out.push_str("]}");
+            }
+        },
+        Response::Results(results) => {
+            out.push_str("\"results\":[");
+            for (i, o) in results.iter().enumerate() {
+                if i != 0 {
+                    out.push(',');
+                }
+                out.push_str("{\"name\":");
+                push_json_str(&mut out, &o.name);
+                out.push_str(",\"suite\":");
+                push_json_str(&mut out, o.suite.name());
+                out.push_str(",\"status\":");
+                push_json_str(&mut out, o.status.name());
+                out.push_str(",\"reason\":");
+                push_json_str(&mut out, &o.reason);
+                let _ = write!(out, ",\"duration_us\":{}}}", o.duration_us);
+            }
+        },
+    }
+
+    out.push_str("]}");
+    out
+}
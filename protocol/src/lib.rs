@@ -0,0 +1,402 @@
+//! Wire protocol spoken over the `\\.\WdkMutexTest` control device.
+//!
+//! This crate has no kernel dependencies so that both the driver and anything driving it from
+//! user mode encode and decode requests the same way, and so the encoding can be exercised on any
+//! host with a plain `cargo test`.
+//!
+//! Every request and response starts with a small versioned header. Responses additionally carry
+//! the total length of the payload, so a caller whose output buffer was too small can retry with a
+//! buffer of exactly the right size.
+//!
+//! The line format the harness prints each test's start and result in, see [`Event`], lives here
+//! too, so captured debugger output can be parsed on any host.
+
+#![no_std]
+extern crate alloc;
+
+mod json;
+mod line;
+mod wire;
+
+use core::fmt;
+
+use alloc::{string::String, vec::Vec};
+
+pub use json::encode_json;
+pub use line::{Event, LINE_MARKER};
+pub use wire::{DecodeError, Reader, Writer};
+
+/// Version of the request / response layout, bumped on any incompatible change.
+pub const PROTOCOL_VERSION: u16 = 1;
+
+/// Magic at the start of every request, `WMTQ`.
+pub const REQUEST_MAGIC: u32 = u32::from_le_bytes(*b"WMTQ");
+/// Magic at the start of every response, `WMTR`.
+pub const RESPONSE_MAGIC: u32 = u32::from_le_bytes(*b"WMTR");
+
+/// Size in bytes of the header preceding every request.
+pub const REQUEST_HEADER_LEN: usize = 8;
+/// Size in bytes of the header preceding every response.
+pub const RESPONSE_HEADER_LEN: usize = 16;
+
+const FILE_DEVICE_UNKNOWN: u32 = 0x22;
+const METHOD_BUFFERED: u32 = 0;
+const FILE_ANY_ACCESS: u32 = 0;
+const FILE_WRITE_ACCESS: u32 = 2;
+
+/// Equivalent of the `CTL_CODE` macro from `devioctl.h`.
+pub const fn ctl_code(device_type: u32, function: u32, method: u32, access: u32) -> u32 {
+    (device_type << 16) | (access << 14) | (function << 2) | method
+}
+
+/// List every registered test.
+pub const IOCTL_LIST_TESTS: u32 = ctl_code(FILE_DEVICE_UNKNOWN, 0x800, METHOD_BUFFERED, FILE_ANY_ACCESS);
+/// Run the tests matching a [`TestFilter`] and return their results.
+///
+/// Requires a handle opened for writing, which the device's default security descriptor only
+/// grants to administrators and `SYSTEM`, as a run loads the machine and can bugcheck it.
+pub const IOCTL_RUN_TESTS: u32 = ctl_code(FILE_DEVICE_UNKNOWN, 0x801, METHOD_BUFFERED, FILE_WRITE_ACCESS);
+/// Fetch the results of the most recent run.
+pub const IOCTL_GET_RESULTS: u32 = ctl_code(FILE_DEVICE_UNKNOWN, 0x802, METHOD_BUFFERED, FILE_ANY_ACCESS);
+
+/// The primitive (or subsystem) a test exercises.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Suite {
+    KMutex = 0,
+    FastMutex = 1,
+    Grt = 2,
+    /// Nesting of mutexes, checked by the harness's lock-order checker.
+    LockOrder = 3,
+}
+
+impl Suite {
+    pub const ALL: [Suite; 4] = [Suite::KMutex, Suite::FastMutex, Suite::Grt, Suite::LockOrder];
+
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Suite::KMutex => "KMutex",
+            Suite::FastMutex => "FastMutex",
+            Suite::Grt => "Grt",
+            Suite::LockOrder => "LockOrder",
+        }
+    }
+
+    pub fn from_u8(v: u8) -> Option<Self> {
+        Suite::ALL.into_iter().find(|s| *s as u8 == v)
+    }
+
+    /// Case-insensitive lookup by [`Suite::name`].
+    pub fn from_name(name: &str) -> Option<Self> {
+        Suite::ALL.into_iter().find(|s| s.name().eq_ignore_ascii_case(name))
+    }
+}
+
+impl fmt::Display for Suite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Encoding of a response payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u16)]
+pub enum Format {
+    Binary = 0,
+    Json = 1,
+}
+
+impl Format {
+    pub fn from_u16(v: u16) -> Option<Self> {
+        match v {
+            0 => Some(Format::Binary),
+            1 => Some(Format::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Selects a subset of the registered tests. Empty fields match everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TestFilter {
+    /// Exact test name, or a prefix when it ends in `*`.
+    pub name: Option<String>,
+    pub tag: Option<String>,
+    pub suite: Option<Suite>,
+}
+
+impl TestFilter {
+    pub fn matches(&self, name: &str, suite: Suite, tags: &[&str]) -> bool {
+        let name_ok = match self.name.as_deref() {
+            None => true,
+            Some(pattern) => match pattern.strip_suffix('*') {
+                Some(prefix) => name.starts_with(prefix),
+                None => name == pattern,
+            },
+        };
+        let tag_ok = match self.tag.as_deref() {
+            None => true,
+            Some(tag) => tags.contains(&tag),
+        };
+        let suite_ok = self.suite.is_none_or(|s| s == suite);
+
+        name_ok && tag_ok && suite_ok
+    }
+
+    fn encode(&self, w: &mut Writer) {
+        w.str(self.name.as_deref().unwrap_or(""));
+        w.str(self.tag.as_deref().unwrap_or(""));
+        w.u8(self.suite.map_or(u8::MAX, |s| s as u8));
+    }
+
+    fn decode(r: &mut Reader) -> Result<Self, DecodeError> {
+        let non_empty = |s: String| if s.is_empty() { None } else { Some(s) };
+        let name = non_empty(r.string()?);
+        let tag = non_empty(r.string()?);
+        let suite = match r.u8()? {
+            u8::MAX => None,
+            v => Some(Suite::from_u8(v).ok_or(DecodeError::InvalidValue)?),
+        };
+
+        Ok(TestFilter { name, tag, suite })
+    }
+}
+
+/// A request sent to the control device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    ListTests { format: Format },
+    RunTests { format: Format, filter: TestFilter },
+    GetResults { format: Format },
+}
+
+impl Request {
+    /// The IOCTL code this request is sent with.
+    pub fn ioctl_code(&self) -> u32 {
+        match self {
+            Request::ListTests { .. } => IOCTL_LIST_TESTS,
+            Request::RunTests { .. } => IOCTL_RUN_TESTS,
+            Request::GetResults { .. } => IOCTL_GET_RESULTS,
+        }
+    }
+
+    pub fn format(&self) -> Format {
+        match self {
+            Request::ListTests { format } | Request::RunTests { format, .. } | Request::GetResults { format } => *format,
+        }
+    }
+
+    /// Encode the input buffer for [`Request::ioctl_code`].
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = Writer::new();
+        w.u32(REQUEST_MAGIC);
+        w.u16(PROTOCOL_VERSION);
+        w.u16(self.format() as u16);
+        if let Request::RunTests { filter, .. } = self {
+            filter.encode(&mut w);
+        }
+
+        w.into_inner()
+    }
+
+    /// Decode the input buffer of an IOCTL with the given control code.
+    pub fn decode(ioctl_code: u32, input: &[u8]) -> Result<Self, DecodeError> {
+        let mut r = Reader::new(input);
+        if r.u32()? != REQUEST_MAGIC {
+            return Err(DecodeError::BadMagic);
+        }
+        let version = r.u16()?;
+        if version != PROTOCOL_VERSION {
+            return Err(DecodeError::UnsupportedVersion(version));
+        }
+        let format = Format::from_u16(r.u16()?).ok_or(DecodeError::InvalidValue)?;
+
+        match ioctl_code {
+            IOCTL_LIST_TESTS => Ok(Request::ListTests { format }),
+            IOCTL_RUN_TESTS => Ok(Request::RunTests { format, filter: TestFilter::decode(&mut r)? }),
+            IOCTL_GET_RESULTS => Ok(Request::GetResults { format }),
+            _ => Err(DecodeError::UnknownIoctl(ioctl_code)),
+        }
+    }
+}
+
+/// Description of a registered test.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestInfo {
+    pub name: String,
+    pub suite: Suite,
+    pub tags: Vec<String>,
+}
+
+/// Outcome of a test.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Status {
+    Passed = 0,
+    Failed = 1,
+    Skipped = 2,
+}
+
+impl Status {
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Status::Passed => "passed",
+            Status::Failed => "failed",
+            Status::Skipped => "skipped",
+        }
+    }
+
+    pub fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            0 => Some(Status::Passed),
+            1 => Some(Status::Failed),
+            2 => Some(Status::Skipped),
+            _ => None,
+        }
+    }
+}
+
+/// Result of a single test within a run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestOutcome {
+    pub name: String,
+    pub suite: Suite,
+    pub status: Status,
+    /// Why the test failed or was skipped, empty when it passed.
+    pub reason: String,
+    pub duration_us: u64,
+}
+
+/// The payload of a response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Response {
+    Tests(Vec<TestInfo>),
+    Results(Vec<TestOutcome>),
+}
+
+const KIND_TESTS: u16 = 0;
+const KIND_RESULTS: u16 = 1;
+
+/// Header preceding every response payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResponseHeader {
+    pub version: u16,
+    pub format: Format,
+    /// Length of the whole response, header included.
+    pub total_len: u32,
+}
+
+impl ResponseHeader {
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut r = Reader::new(bytes);
+        if r.u32()? != RESPONSE_MAGIC {
+            return Err(DecodeError::BadMagic);
+        }
+        let version = r.u16()?;
+        if version != PROTOCOL_VERSION {
+            return Err(DecodeError::UnsupportedVersion(version));
+        }
+        let format = Format::from_u16(r.u16()?).ok_or(DecodeError::InvalidValue)?;
+        let total_len = r.u32()?;
+        let _reserved = r.u32()?;
+
+        Ok(ResponseHeader { version, format, total_len })
+    }
+}
+
+impl Response {
+    /// Encode the response, header included, in the requested `format`.
+    pub fn encode(&self, format: Format) -> Vec<u8> {
+        let mut w = Writer::new();
+        w.u32(RESPONSE_MAGIC);
+        w.u16(PROTOCOL_VERSION);
+        w.u16(format as u16);
+        w.u32(0); // total length, patched below
+        w.u32(0); // reserved
+
+        match format {
+            Format::Binary => self.encode_binary(&mut w),
+            Format::Json => w.bytes(encode_json(self).as_bytes()),
+        }
+
+        let mut out = w.into_inner();
+        let total_len = out.len() as u32;
+        out[8..12].copy_from_slice(&total_len.to_le_bytes());
+        out
+    }
+
+    fn encode_binary(&self, w: &mut Writer) {
+        match self {
+            Response::Tests(tests) => {
+                w.u16(KIND_TESTS);
+                w.u32(tests.len() as u32);
+                for t in tests {
+                    w.str(&t.name);
+                    w.u8(t.suite as u8);
+                    w.u16(t.tags.len() as u16);
+                    for tag in &t.tags {
+                        w.str(tag);
+                    }
+                }
+            },
+            Response::Results(results) => {
+                w.u16(KIND_RESULTS);
+                w.u32(results.len() as u32);
+                for o in results {
+                    w.str(&o.name);
+                    w.u8(o.suite as u8);
+                    w.u8(o.status as u8);
+                    w.str(&o.reason);
+                    w.u64(o.duration_us);
+                }
+            },
+        }
+    }
+
+    /// Decode a complete binary response, header included.
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let header = ResponseHeader::decode(bytes)?;
+        if header.format != Format::Binary {
+            return Err(DecodeError::InvalidValue);
+        }
+        let total_len = header.total_len as usize;
+        if bytes.len() < total_len || total_len < RESPONSE_HEADER_LEN {
+            return Err(DecodeError::Truncated);
+        }
+
+        let mut r = Reader::new(&bytes[RESPONSE_HEADER_LEN..total_len]);
+        let suite = |r: &mut Reader| Suite::from_u8(r.u8()?).ok_or(DecodeError::InvalidValue);
+
+        match r.u16()? {
+            KIND_TESTS => {
+                let count = r.u32()?;
+                let mut tests = Vec::new();
+                for _ in 0..count {
+                    let name = r.string()?;
+                    let suite = suite(&mut r)?;
+                    let tag_count = r.u16()?;
+                    let mut tags = Vec::new();
+                    for _ in 0..tag_count {
+                        tags.push(r.string()?);
+                    }
+                    tests.push(TestInfo { name, suite, tags });
+                }
+                Ok(Response::Tests(tests))
+            },
+            KIND_RESULTS => {
+                let count = r.u32()?;
+                let mut results = Vec::new();
+                for _ in 0..count {
+                    let name = r.string()?;
+                    let suite = suite(&mut r)?;
+                    let status = Status::from_u8(r.u8()?).ok_or(DecodeError::InvalidValue)?;
+                    let reason = r.string()?;
+                    let duration_us = r.u64()?;
+                    results.push(TestOutcome { name, suite, status, reason, duration_us });
+                }
+                Ok(Response::Results(results))
+            },
+            _ => Err(DecodeError::InvalidValue),
+        }
+    }
+}
@@ -0,0 +1,152 @@
+//! Stable one-line records of a run, printed by the harness alongside its human-readable output.
+//!
+//! Whatever captured the driver's output, DebugView, WinDbg or the driver's own log, each record
+//! is one line containing [`LINE_MARKER`] followed by space separated fields, and can be turned
+//! back into an [`Event`] with [`Event::parse`] however the line was prefixed:
+//!
+//! ```text
+//! #WMT1 RUN 12
+//! #WMT1 START KMutex kmutex::to_owned
+//! #WMT1 METRIC grt::bench_scaling get_ns_per_op_1000 85 ns
+//! #WMT1 PASS KMutex kmutex::to_owned 1250
+//! #WMT1 FAIL Grt grt::thrice 980 expected 3, got 2\nsecond line
+//! #WMT1 SKIP Grt grt::bench_scaling 0 benchmarks are disabled
+//! #WMT1 END 10 1 1
+//! ```
+//!
+//! `PASS`, `FAIL` and `SKIP` carry the duration in microseconds and, last, the reason, with
+//! backslashes, newlines and carriage returns escaped as `\\`, `\n` and `\r` so it stays on one
+//! line. A `START` without a matching result means the test never finished.
+//!
+//! The version in the marker is bumped on any incompatible change to these records.
+
+use core::fmt;
+
+use alloc::string::{String, ToString};
+
+use crate::{Status, Suite};
+
+/// Starts every record.
+pub const LINE_MARKER: &str = "#WMT1";
+
+/// One record of a run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// A run of `tests` tests, repeats included, is starting.
+    RunStart { tests: u32 },
+    /// `test` is starting.
+    Start { suite: Suite, test: String },
+    /// `test` finished.
+    Result { suite: Suite, test: String, status: Status, duration_us: u64, reason: String },
+    /// A measurement made by `test`. `name` has no spaces.
+    Metric { test: String, name: String, value: u64, unit: String },
+    /// The run finished.
+    RunEnd { passed: u32, failed: u32, skipped: u32 },
+}
+
+impl Event {
+    /// The record in `line`, or `None` if it holds none or a malformed one.
+    pub fn parse(line: &str) -> Option<Event> {
+        let (_, record) = line.split_once(LINE_MARKER)?;
+        let record = record.trim_end_matches(['\r', '\n']).strip_prefix(' ')?;
+        let (kind, rest) = record.split_once(' ').unwrap_or((record, ""));
+
+        let event = match kind {
+            "RUN" => Event::RunStart { tests: rest.parse().ok()? },
+            "START" => {
+                let (suite, test) = rest.split_once(' ')?;
+                Event::Start { suite: Suite::from_name(suite)?, test: field(test)? }
+            },
+            "PASS" | "FAIL" | "SKIP" => {
+                let status = match kind {
+                    "PASS" => Status::Passed,
+                    "FAIL" => Status::Failed,
+                    _ => Status::Skipped,
+                };
+                let mut fields = rest.splitn(4, ' ');
+                let suite = Suite::from_name(fields.next()?)?;
+                let test = field(fields.next()?)?;
+                let duration_us = fields.next()?.parse().ok()?;
+                let reason = unescape(fields.next().unwrap_or(""));
+                Event::Result { suite, test, status, duration_us, reason }
+            },
+            "METRIC" => {
+                let mut fields = rest.splitn(4, ' ');
+                let test = field(fields.next()?)?;
+                let name = field(fields.next()?)?;
+                let value = fields.next()?.parse().ok()?;
+                let unit = fields.next().unwrap_or("").to_string();
+                Event::Metric { test, name, value, unit }
+            },
+            "END" => {
+                let mut fields = rest.split(' ');
+                let mut count = || fields.next()?.parse().ok();
+                Event::RunEnd { passed: count()?, failed: count()?, skipped: count()? }
+            },
+            _ => return None,
+        };
+
+        Some(event)
+    }
+}
+
+impl fmt::Display for Event {
+    /// The record without a trailing newline, starting with [`LINE_MARKER`].
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{LINE_MARKER} ")?;
+        match self {
+            Event::RunStart { tests } => write!(f, "RUN {tests}"),
+            Event::Start { suite, test } => write!(f, "START {suite} {test}"),
+            Event::Result { suite, test, status, duration_us, reason } => {
+                let kind = match status {
+                    Status::Passed => "PASS",
+                    Status::Failed => "FAIL",
+                    Status::Skipped => "SKIP",
+                };
+                write!(f, "{kind} {suite} {test} {duration_us}")?;
+                if !reason.is_empty() {
+                    f.write_str(" ")?;
+                    for c in reason.chars() {
+                        match c {
+                            '\\' => f.write_str("\\\\")?,
+                            '\n' => f.write_str("\\n")?,
+                            '\r' => f.write_str("\\r")?,
+                            c => write!(f, "{c}")?,
+                        }
+                    }
+                }
+                Ok(())
+            },
+            Event::Metric { test, name, value, unit } => write!(f, "METRIC {test} {name} {value} {unit}"),
+            Event::RunEnd { passed, failed, skipped } => write!(f, "END {passed} {failed} {skipped}"),
+        }
+    }
+}
+
+/// A single non-empty field.
+fn field(s: &str) -> Option<String> {
+    (!s.is_empty() && !s.contains(' ')).then(|| s.to_string())
+}
+
+/// Undo the escaping of a reason, leaving unknown escapes as they are.
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => out.push('\\'),
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some(other) => {
+                out.push('\\');
+                out.push(other);
+            },
+            None => out.push('\\'),
+        }
+    }
+    out
+}
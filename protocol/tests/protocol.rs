@@ -0,0 +1,181 @@
+//! Encoding and decoding of requests and responses, well-formed and not.
+
+use wdk_mutex_tests_protocol::{
+    ctl_code, DecodeError, Format, Request, Response, ResponseHeader, Status, Suite, TestFilter, TestInfo, TestOutcome, IOCTL_GET_RESULTS,
+    IOCTL_LIST_TESTS, IOCTL_RUN_TESTS, PROTOCOL_VERSION, RESPONSE_HEADER_LEN,
+};
+
+fn requests() -> Vec<Request> {
+    let mut requests = Vec::new();
+    for format in [Format::Binary, Format::Json] {
+        requests.push(Request::ListTests { format });
+        requests.push(Request::GetResults { format });
+        requests.push(Request::RunTests { format, filter: TestFilter::default() });
+        for suite in Suite::ALL {
+            requests.push(Request::RunTests {
+                format,
+                filter: TestFilter { name: Some("kmutex::*".into()), tag: Some("churn".into()), suite: Some(suite) },
+            });
+        }
+    }
+    requests
+}
+
+fn tests() -> Response {
+    Response::Tests(vec![
+        TestInfo { name: "kmutex::to_owned".into(), suite: Suite::KMutex, tags: vec![] },
+        TestInfo { name: "grt::churn".into(), suite: Suite::Grt, tags: vec!["multithread".into(), "churn".into()] },
+    ])
+}
+
+fn results() -> Response {
+    Response::Results(vec![
+        TestOutcome { name: "kmutex::to_owned".into(), suite: Suite::KMutex, status: Status::Passed, reason: String::new(), duration_us: 1_250 },
+        TestOutcome {
+            name: "fast_mutex::thrice".into(),
+            suite: Suite::FastMutex,
+            status: Status::Failed,
+            reason: "expected 3, got \"2\"\nsecond line".into(),
+            duration_us: u64::MAX,
+        },
+        TestOutcome { name: "grt::bench_scaling".into(), suite: Suite::Grt, status: Status::Skipped, reason: "é ✓".into(), duration_us: 0 },
+    ])
+}
+
+/// A well-formed run request for the Grt suite.
+fn run_request() -> Vec<u8> {
+    Request::RunTests { format: Format::Binary, filter: TestFilter { name: None, tag: None, suite: Some(Suite::Grt) } }.encode()
+}
+
+#[test]
+fn requests_round_trip() {
+    for request in requests() {
+        let bytes = request.encode();
+        assert_eq!(Request::decode(request.ioctl_code(), &bytes), Ok(request));
+    }
+}
+
+#[test]
+fn responses_round_trip() {
+    for response in [tests(), results(), Response::Tests(vec![]), Response::Results(vec![])] {
+        let bytes = response.encode(Format::Binary);
+        assert_eq!(Response::decode(&bytes), Ok(response));
+    }
+}
+
+#[test]
+fn response_header_carries_the_total_length() {
+    for format in [Format::Binary, Format::Json] {
+        let bytes = results().encode(format);
+        let header = ResponseHeader::decode(&bytes[..RESPONSE_HEADER_LEN]).unwrap();
+
+        assert_eq!(header, ResponseHeader { version: PROTOCOL_VERSION, format, total_len: bytes.len() as u32 });
+    }
+}
+
+#[test]
+fn json_escapes_reasons() {
+    let bytes = results().encode(Format::Json);
+    let json = std::str::from_utf8(&bytes[RESPONSE_HEADER_LEN..]).unwrap();
+
+    assert!(json.starts_with("{\"version\":1,\"results\":[{\"name\":\"kmutex::to_owned\",\"suite\":\"KMutex\""), "{json}");
+    assert!(json.contains(r#""reason":"expected 3, got \"2\"\nsecond line""#), "{json}");
+    assert!(json.ends_with("]}"), "{json}");
+}
+
+#[test]
+fn rejects_bad_magic() {
+    let mut request = run_request();
+    request[0] ^= 0xff;
+    assert_eq!(Request::decode(IOCTL_RUN_TESTS, &request), Err(DecodeError::BadMagic));
+
+    // a request is not a response
+    assert_eq!(Response::decode(&run_request()), Err(DecodeError::BadMagic));
+    let mut response = tests().encode(Format::Binary);
+    response[3] ^= 0xff;
+    assert_eq!(Response::decode(&response), Err(DecodeError::BadMagic));
+}
+
+#[test]
+fn rejects_other_versions() {
+    let version = (PROTOCOL_VERSION + 1).to_le_bytes();
+
+    let mut request = run_request();
+    request[4..6].copy_from_slice(&version);
+    assert_eq!(Request::decode(IOCTL_RUN_TESTS, &request), Err(DecodeError::UnsupportedVersion(PROTOCOL_VERSION + 1)));
+
+    let mut response = results().encode(Format::Binary);
+    response[4..6].copy_from_slice(&version);
+    assert_eq!(Response::decode(&response), Err(DecodeError::UnsupportedVersion(PROTOCOL_VERSION + 1)));
+}
+
+#[test]
+fn rejects_truncated_buffers() {
+    let request = Request::RunTests {
+        format: Format::Binary,
+        filter: TestFilter { name: Some("kmutex::*".into()), tag: Some("churn".into()), suite: Some(Suite::KMutex) },
+    }
+    .encode();
+    for len in 0..request.len() {
+        assert_eq!(Request::decode(IOCTL_RUN_TESTS, &request[..len]), Err(DecodeError::Truncated), "{len} of {} bytes", request.len());
+    }
+
+    for response in [tests(), results()] {
+        let bytes = response.encode(Format::Binary);
+        for len in 0..bytes.len() {
+            assert_eq!(Response::decode(&bytes[..len]), Err(DecodeError::Truncated), "{len} of {} bytes", bytes.len());
+        }
+    }
+}
+
+#[test]
+fn rejects_unknown_ioctls() {
+    let request = Request::ListTests { format: Format::Binary }.encode();
+    let next = ctl_code(IOCTL_GET_RESULTS >> 16, 0x803, 0, 0);
+    for unknown in [0, next, IOCTL_LIST_TESTS | 1] {
+        assert_eq!(Request::decode(unknown, &request), Err(DecodeError::UnknownIoctl(unknown)), "{unknown:#x}");
+    }
+}
+
+#[test]
+fn rejects_invalid_values() {
+    // the suite is the last byte of a run request, u8::MAX meaning any
+    let mut request = run_request();
+    *request.last_mut().unwrap() = Suite::ALL.len() as u8;
+    assert_eq!(Request::decode(IOCTL_RUN_TESTS, &request), Err(DecodeError::InvalidValue));
+
+    let mut request = Request::ListTests { format: Format::Binary }.encode();
+    request[6] = 7;
+    assert_eq!(Request::decode(IOCTL_LIST_TESTS, &request), Err(DecodeError::InvalidValue));
+
+    // after the header come the kind, the count and the first test's name
+    let mut response = tests().encode(Format::Binary);
+    let suite = RESPONSE_HEADER_LEN + 2 + 4 + 2 + "kmutex::to_owned".len();
+    response[suite] = Suite::ALL.len() as u8;
+    assert_eq!(Response::decode(&response), Err(DecodeError::InvalidValue));
+
+    let mut response = results().encode(Format::Binary);
+    response[suite + 1] = 3;
+    assert_eq!(Response::decode(&response), Err(DecodeError::InvalidValue));
+
+    // only binary responses decode
+    assert_eq!(Response::decode(&results().encode(Format::Json)), Err(DecodeError::InvalidValue));
+}
+
+#[test]
+fn rejects_invalid_utf8() {
+    let mut request =
+        Request::RunTests { format: Format::Binary, filter: TestFilter { name: Some("ab".into()), tag: None, suite: None } }.encode();
+    // the name's bytes follow the header and their length
+    request[10] = 0xff;
+    assert_eq!(Request::decode(IOCTL_RUN_TESTS, &request), Err(DecodeError::InvalidUtf8));
+}
+
+#[test]
+fn only_running_needs_write_access() {
+    let access = |code: u32| (code >> 14) & 3;
+
+    assert_eq!(access(IOCTL_RUN_TESTS), 2);
+    assert_eq!(access(IOCTL_LIST_TESTS), 0);
+    assert_eq!(access(IOCTL_GET_RESULTS), 0);
+}
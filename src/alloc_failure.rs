@@ -0,0 +1,79 @@
+//! The harness's own pool allocations when they fail.
+//!
+//! Failures injected by [`crate::fault`] reach the pool memory tests allocate through
+//! [`crate::kernel`], and these tests check the harness copes with losing it: the test using it
+//! fails with a reason rather than writing through a null pointer, and nothing is left allocated.
+//!
+//! They do not reach `wdk_mutex`, which calls `ExAllocatePool2` itself, so no test here makes
+//! `new`, `Grt::register_*` or `Grt::init` fail.
+
+use alloc::format;
+
+use crate::{conformance::{multithread_mutex_global_static_manual_pool, LockPrimitive}, fault::{Injection, Plan}, kernel::perf_counter_us, registry::{TestContext, TestError, TestResult}};
+
+/// Reason [`multithread_mutex_global_static_manual_pool`] fails with when its allocation fails.
+const POOL_FAILED: &str = "ExAllocatePool2 failed";
+
+/// Runs of the manual pool test under memory pressure.
+const PRESSURE_RUNS: usize = 20;
+
+/// Fail unless `result` is the manual pool test failing on its allocation.
+fn expect_pool_failed(result: TestResult) -> TestResult {
+    match result {
+        Err(TestError::Failed(reason)) if reason == POOL_FAILED => Ok(()),
+        other => Err(TestError::fail(format!("expected the test to fail with `{POOL_FAILED}`, got {other:?}"))),
+    }
+}
+
+/// The manual pool test when its `ExAllocatePool2` fails.
+pub fn manual_pool_under_alloc_failure<P: LockPrimitive>(ctx: &TestContext) -> TestResult {
+    let injection = Injection::arm(Plan::Nth(1));
+    let result = multithread_mutex_global_static_manual_pool::<P>(ctx);
+    let (injected, outstanding) = (injection.injected(), injection.outstanding());
+    drop(injection);
+
+    if injected != 1 {
+        return Err(TestError::fail(format!("expected 1 injected pool allocation failure, got {injected}")));
+    }
+    expect_pool_failed(result)?;
+    if outstanding != 0 {
+        return Err(TestError::fail(format!("the failed test left {outstanding} pool allocation(s) behind")));
+    }
+
+    // the failure left nothing behind that stops the test passing normally
+    multithread_mutex_global_static_manual_pool::<P>(ctx)
+}
+
+/// The manual pool test run repeatedly while half of all pool allocations fail.
+///
+/// Every run must either pass or fail on its allocation, and no run may leave pool memory behind.
+pub fn manual_pool_under_memory_pressure<P: LockPrimitive>(ctx: &TestContext) -> TestResult {
+    let injection = Injection::arm(Plan::Fraction { per_million: 500_000, seed: perf_counter_us() });
+    let mut failed = 0;
+
+    for run in 0..PRESSURE_RUNS {
+        let injected = injection.injected();
+        let result = multithread_mutex_global_static_manual_pool::<P>(ctx);
+
+        if injection.injected() != injected {
+            expect_pool_failed(result).map_err(|e| match e {
+                TestError::Failed(reason) => TestError::fail(format!("run {run}: {reason}")),
+                e => e,
+            })?;
+            failed += 1;
+        } else {
+            result?;
+        }
+
+        let outstanding = injection.outstanding();
+        if outstanding != 0 {
+            return Err(TestError::fail(format!("run {run} left {outstanding} pool allocation(s) behind")));
+        }
+    }
+
+    let injected = injection.injected();
+    if failed != injected {
+        return Err(TestError::fail(format!("{injected} allocations failed but {failed} runs reported it")));
+    }
+    Ok(())
+}
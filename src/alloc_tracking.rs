@@ -0,0 +1,114 @@
+//! Live allocation counts, for the runner's leak check.
+//!
+//! [`TrackingAllocator`] sits at the top of the global allocator stack and counts every heap
+//! allocation and byte still live, and [`crate::kernel::ex_allocate_pool2`] does the same for the
+//! pool allocations tests make through it, tagged [`POOL_TAG`]. The runner takes a [`snapshot`]
+//! before and after each test and fails a test that passed if the two differ, see
+//! [`crate::runner::run_test`].
+//!
+//! Only the harness's own allocations are counted. `wdk_mutex` allocates its pool with
+//! `ExAllocatePool2` directly, under the same tag, and none of that is seen here, so a pool leak
+//! inside `wdk_mutex` passes the check; only heap memory it takes through the global allocator is
+//! counted.
+//!
+//! The counts are global, so anything allocated by another thread while a test runs is counted
+//! against it. Runs are exclusive and the tests join their threads, so in practice that is only a
+//! control request racing the run.
+
+use core::{alloc::{GlobalAlloc, Layout}, sync::atomic::{AtomicI64, Ordering}};
+
+use alloc::{format, string::String};
+
+use crate::kernel::POOL_TAG;
+
+static HEAP_ALLOCS: AtomicI64 = AtomicI64::new(0);
+static HEAP_BYTES: AtomicI64 = AtomicI64::new(0);
+static POOL_ALLOCS: AtomicI64 = AtomicI64::new(0);
+static POOL_BYTES: AtomicI64 = AtomicI64::new(0);
+
+/// Allocations and bytes live at one point, or the change between two points.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub heap_allocs: i64,
+    pub heap_bytes: i64,
+    pub pool_allocs: i64,
+    pub pool_bytes: i64,
+}
+
+impl Usage {
+    /// The change from `before` to `self`.
+    pub fn since(&self, before: &Usage) -> Usage {
+        Usage {
+            heap_allocs: self.heap_allocs - before.heap_allocs,
+            heap_bytes: self.heap_bytes - before.heap_bytes,
+            pool_allocs: self.pool_allocs - before.pool_allocs,
+            pool_bytes: self.pool_bytes - before.pool_bytes,
+        }
+    }
+
+    /// Describe a change as a leak, e.g. `leaked 48 bytes: 32 in 1 heap allocation(s), 16 in 1
+    /// harness pool allocation(s)`, or `None` if no more allocations are live than before.
+    ///
+    /// Only the number of allocations decides: a buffer that existed before and has since grown,
+    /// such as a log, changes the byte counts without anything having leaked.
+    pub fn leak(&self) -> Option<String> {
+        if self.heap_allocs <= 0 && self.pool_allocs <= 0 {
+            return None;
+        }
+
+        Some(format!(
+            "leaked {} bytes: {} in {} heap allocation(s), {} in {} harness pool allocation(s)",
+            self.heap_bytes + self.pool_bytes,
+            self.heap_bytes,
+            self.heap_allocs,
+            self.pool_bytes,
+            self.pool_allocs,
+        ))
+    }
+}
+
+/// Allocations and bytes live right now.
+pub fn snapshot() -> Usage {
+    Usage {
+        heap_allocs: HEAP_ALLOCS.load(Ordering::SeqCst),
+        heap_bytes: HEAP_BYTES.load(Ordering::SeqCst),
+        pool_allocs: POOL_ALLOCS.load(Ordering::SeqCst),
+        pool_bytes: POOL_BYTES.load(Ordering::SeqCst),
+    }
+}
+
+/// Called by [`crate::kernel::ex_allocate_pool2`] for each successful allocation.
+pub fn record_pool_alloc(tag: u32, size: usize) {
+    if tag == POOL_TAG {
+        POOL_ALLOCS.fetch_add(1, Ordering::SeqCst);
+        POOL_BYTES.fetch_add(size as i64, Ordering::SeqCst);
+    }
+}
+
+/// Called by [`crate::kernel::ex_free_pool`] for each free.
+pub fn record_pool_free(tag: u32, size: usize) {
+    if tag == POOL_TAG {
+        POOL_ALLOCS.fetch_sub(1, Ordering::SeqCst);
+        POOL_BYTES.fetch_sub(size as i64, Ordering::SeqCst);
+    }
+}
+
+/// Global allocator that counts the allocations and bytes `A` has live.
+pub struct TrackingAllocator<A>(pub A);
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for TrackingAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let p = unsafe { self.0.alloc(layout) };
+        if !p.is_null() {
+            HEAP_ALLOCS.fetch_add(1, Ordering::SeqCst);
+            HEAP_BYTES.fetch_add(layout.size() as i64, Ordering::SeqCst);
+        }
+        p
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        HEAP_ALLOCS.fetch_sub(1, Ordering::SeqCst);
+        HEAP_BYTES.fetch_sub(layout.size() as i64, Ordering::SeqCst);
+        unsafe { self.0.dealloc(ptr, layout) };
+    }
+}
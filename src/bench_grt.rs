@@ -0,0 +1,177 @@
+//! Scalability benchmark of the global registry, [`Grt`].
+//!
+//! Product drivers register hundreds of named mutexes, so the cost of `register_*` and `get_*` as
+//! the registry grows matters more than at the three keys the other tests use. For each size in
+//! [`SIZES`] the registry is emptied and filled with that many keys, alternating `KMutex` and
+//! `FastMutex`, timing every registration. Lookups over all keys are then timed from the test
+//! thread alone, and from `ctx.threads` threads looking up at once. Only `get_*` is timed for
+//! lookups, the mutexes found are not locked.
+//!
+//! Times come from the performance counter, and each result is the mean over at least
+//! [`MIN_SAMPLE_OPS`] operations where the size allows. The per-operation cost at each size is
+//! printed as a table, e.g.:
+//!
+//! ```text
+//! [wdk-mutex-test] [i] Grt bench      keys   register ns/op   get ns/op   contended get ns/op (3 threads)
+//! [wdk-mutex-test] [i] Grt bench        10              412          61                    98
+//! ```
+//!
+//! The benchmark only fails if the registry misbehaves, not on any timing. It is skipped unless
+//! `Parameters\Benchmarks` is set.
+
+use core::{hint::{black_box, spin_loop}, sync::atomic::{AtomicU64, AtomicUsize, Ordering}};
+
+use alloc::{format, sync::Arc, vec::Vec};
+
+use crate::{kernel::perf_counter_ns, println, registry::{Suite, TestCase, TestContext, TestError, TestResult}, runner, threads::SystemThreadGroup, wdk_mutex::{errors::GrtError, grt::Grt}, workers::record_progress};
+
+/// Tests registered with the runner, see [`crate::registry`].
+pub static TESTS: &[TestCase] = &[
+    TestCase {
+        name: "grt::bench_scaling",
+        suite: Suite::Grt,
+        tags: &["bench"],
+        run: bench_scaling,
+    },
+];
+
+/// Registry sizes benchmarked, in keys.
+const SIZES: [usize; 4] = [10, 100, 1_000, 10_000];
+
+/// Lookups each measurement is averaged over, at least; small registries are walked repeatedly.
+const MIN_SAMPLE_OPS: usize = 10_000;
+
+/// Per-operation cost at one registry size, in nanoseconds.
+struct Sample {
+    keys: usize,
+    register_ns: u64,
+    get_ns: u64,
+    contended_get_ns: u64,
+}
+
+fn bench_scaling(ctx: &TestContext) -> TestResult {
+    if !ctx.benchmarks {
+        return Err(TestError::skip("benchmarks disabled, set Parameters\\Benchmarks"));
+    }
+
+    let mut samples = Vec::with_capacity(SIZES.len());
+
+    for size in SIZES {
+        // start each size from an empty registry, the scope's teardown does the same at the end
+        unsafe { Grt::destroy() }?;
+        Grt::init()?;
+
+        let keys: Arc<Vec<&'static str>> = Arc::new((0..size).map(|i| ctx.grt.key(&format!("bench_{size}_{i}"))).collect());
+
+        let start = perf_counter_ns();
+        for (i, &key) in keys.iter().enumerate() {
+            if i % 2 == 0 {
+                Grt::register_kmutex(key, i as u64)?;
+            } else {
+                Grt::register_fast_mutex(key, i as u64)?;
+            }
+        }
+        let register_ns = (perf_counter_ns() - start) / size as u64;
+        verify(&keys)?;
+
+        let ops = size.max(MIN_SAMPLE_OPS);
+        let start = perf_counter_ns();
+        lookups(&keys, 0, ops)?;
+        let get_ns = (perf_counter_ns() - start) / ops as u64;
+
+        let contended_get_ns = contended_lookups(&keys, ctx.threads, ops)?;
+
+        samples.push(Sample { keys: size, register_ns, get_ns, contended_get_ns });
+    }
+
+    println!(
+        "[wdk-mutex-test] [i] Grt bench {:>9} {:>16} {:>11} {:>21} ({} threads)",
+        "keys", "register ns/op", "get ns/op", "contended get ns/op", ctx.threads,
+    );
+    for s in &samples {
+        println!(
+            "[wdk-mutex-test] [i] Grt bench {:>9} {:>16} {:>11} {:>21}",
+            s.keys, s.register_ns, s.get_ns, s.contended_get_ns,
+        );
+    }
+    for s in &samples {
+        runner::metric(&format!("register_ns_per_op_{}", s.keys), s.register_ns, "ns");
+        runner::metric(&format!("get_ns_per_op_{}", s.keys), s.get_ns, "ns");
+        runner::metric(&format!("contended_get_ns_per_op_{}", s.keys), s.contended_get_ns, "ns");
+    }
+
+    Ok(())
+}
+
+/// Check every key maps to the mutex registered for it, outside of any measurement.
+fn verify(keys: &[&'static str]) -> TestResult {
+    for (i, &key) in keys.iter().enumerate() {
+        let val = if i % 2 == 0 {
+            *Grt::get_kmutex::<u64>(key)?.lock()?
+        } else {
+            *Grt::get_fast_mutex::<u64>(key)?.lock()?
+        };
+        if val != i as u64 {
+            return Err(TestError::fail(format!("{key} holds {val}, expected {i}")));
+        }
+    }
+
+    Ok(())
+}
+
+/// Look up `ops` keys, walking `keys` from `first` in a stride that defeats sequential access.
+fn lookups(keys: &[&'static str], first: usize, ops: usize) -> Result<(), GrtError> {
+    for n in 0..ops {
+        let i = (first + n * 7_919) % keys.len();
+
+        if i % 2 == 0 {
+            black_box(Grt::get_kmutex::<u64>(keys[i])?);
+        } else {
+            black_box(Grt::get_fast_mutex::<u64>(keys[i])?);
+        }
+    }
+
+    Ok(())
+}
+
+/// Mean cost of a lookup while `threads` threads each perform `ops` of them at once.
+fn contended_lookups(keys: &Arc<Vec<&'static str>>, threads: usize, ops: usize) -> Result<u64, TestError> {
+    let started = Arc::new(AtomicUsize::new(0));
+    let next_id = Arc::new(AtomicUsize::new(0));
+    let total_ns = Arc::new(AtomicU64::new(0));
+    let failures = Arc::new(AtomicUsize::new(0));
+
+    let mut group = SystemThreadGroup::new();
+    let (k, st, id, ns, f) = (Arc::clone(keys), Arc::clone(&started), Arc::clone(&next_id), Arc::clone(&total_ns), Arc::clone(&failures));
+    let spawned = group.spawn(threads, move || {
+        let first = id.fetch_add(1, Ordering::SeqCst) * k.len() / threads;
+
+        record_progress("waiting for the other threads to start");
+        st.fetch_add(1, Ordering::SeqCst);
+        while st.load(Ordering::SeqCst) < threads {
+            spin_loop();
+        }
+
+        // once, not per lookup, so the timing is not charged for it
+        record_progress("looking up");
+        let start = perf_counter_ns();
+        if let Err(e) = lookups(&k, first, ops) {
+            println!("[wdk-mutex-test] [-] Grt bench lookup failed: {e:?}");
+            f.fetch_add(1, Ordering::SeqCst);
+        }
+        ns.fetch_add(perf_counter_ns() - start, Ordering::SeqCst);
+    });
+    if let Err(status) = spawned {
+        // release whichever threads did start from waiting on the rest, they are joined on drop
+        started.fetch_add(threads, Ordering::SeqCst);
+        return Err(TestError::status("spawning threads", status));
+    }
+    group.join_all().map_err(|s| TestError::status("joining threads", s))?;
+
+    let failures = failures.load(Ordering::SeqCst);
+    if failures != 0 {
+        return Err(TestError::fail(format!("{failures} thread(s) saw a bad lookup, see the log")));
+    }
+
+    Ok(total_ns.load(Ordering::SeqCst) / (threads * ops) as u64)
+}
@@ -0,0 +1,313 @@
+//! Test parameters, read from the `Parameters` subkey of the driver's service key.
+//!
+//! Every value is optional, anything missing or of the wrong type keeps its default:
+//!
+//! | Value             | Type        | Default | Meaning                                                |
+//! |-------------------|-------------|---------|--------------------------------------------------------|
+//! | `ThreadCount`     | `REG_DWORD` | 3       | Threads spawned by each multithreaded test             |
+//! | `Iterations`      | `REG_DWORD` | 500     | Lock / increment cycles performed by each thread       |
+//! | `NameFilter`      | `REG_SZ`    |         | Only run tests with this name, or prefix ending `*`    |
+//! | `TagFilter`       | `REG_SZ`    |         | Only run tests carrying this tag                       |
+//! | `Repeat`          | `REG_DWORD` | 1       | Number of passes over the selected tests               |
+//! | `FailFast`        | `REG_DWORD` | 0       | Non-zero stops the run at the first failing test       |
+//! | `SoakSeconds`     | `REG_DWORD` | 0       | Duration of each soak test, 0 skips them               |
+//! | `AllowUnsound`    | `REG_DWORD` | 0       | Non-zero runs tests that exercise undefined behaviour  |
+//! | `Benchmarks`      | `REG_DWORD` | 0       | Non-zero runs the `bench` tagged benchmarks            |
+//! | `TimeoutSeconds`  | `REG_DWORD` | 60      | Deadline for a test's threads to exit, 0 for none      |
+//! | `AbortOnTimeout`  | `REG_DWORD` | 0       | Non-zero stops the run at the first test timing out    |
+//! | `WatchdogSeconds` | `REG_DWORD` | 10      | Stall that makes the watchdog print workers, 0 for off |
+//!
+//! A soak test's deadline is `TimeoutSeconds` on top of its `SoakSeconds`. The filters only apply
+//! to the run at load; `IOCTL_RUN_TESTS` carries its own filter.
+
+use core::{ptr::null_mut, sync::atomic::{AtomicPtr, Ordering}};
+
+#[cfg(feature = "driver")]
+use alloc::boxed::Box;
+use alloc::string::String;
+use wdk_mutex_tests_protocol::TestFilter;
+
+/// Upper bound on `ThreadCount`, to keep a typo in the registry from exhausting the system.
+pub const MAX_THREADS: u32 = 64;
+
+/// Most times a test runs `threads * iterations` passes against the same counter, as
+/// `lock_order::abba` does once in each order.
+pub const MAX_ROUNDS: u32 = 2;
+
+/// What the runner does after a test's threads missed their deadline, see [`crate::threads`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutPolicy {
+    /// Fail the test and go on with the next. The hung threads are left running and waited for at
+    /// unload.
+    Continue,
+    /// Fail the test and run nothing after it, for when whatever the hung threads hold would
+    /// disturb the tests that follow.
+    Abort,
+}
+
+/// Parameters of a test run.
+#[derive(Debug, Clone)]
+pub struct TestConfig {
+    pub threads: u32,
+    pub iterations: u32,
+    pub name_filter: Option<String>,
+    pub tag_filter: Option<String>,
+    pub repeat: u32,
+    pub fail_fast: bool,
+    pub soak_seconds: u32,
+    pub allow_unsound: bool,
+    pub benchmarks: bool,
+    pub timeout_seconds: u32,
+    pub on_timeout: TimeoutPolicy,
+    pub watchdog_seconds: u32,
+}
+
+impl TestConfig {
+    /// The parameters the tests were originally written with.
+    pub const DEFAULT: TestConfig = TestConfig {
+        threads: 3,
+        iterations: 500,
+        name_filter: None,
+        tag_filter: None,
+        repeat: 1,
+        fail_fast: false,
+        soak_seconds: 0,
+        allow_unsound: false,
+        benchmarks: false,
+        timeout_seconds: 60,
+        on_timeout: TimeoutPolicy::Continue,
+        watchdog_seconds: 10,
+    };
+
+    /// Bring every value into its supported range.
+    ///
+    /// The thread count is capped at [`MAX_THREADS`], and the iterations so that
+    /// `MAX_ROUNDS * threads * iterations` still fits the `u32` counters the tests increment.
+    pub fn clamped(mut self) -> Self {
+        self.threads = self.threads.clamp(1, MAX_THREADS);
+        self.iterations = self.iterations.clamp(1, u32::MAX / MAX_ROUNDS / self.threads);
+        self.repeat = self.repeat.max(1);
+        self
+    }
+
+    /// How long a test may take before its threads are given up on, `None` for no deadline.
+    pub fn timeout_us(&self) -> Option<u64> {
+        (self.timeout_seconds != 0).then(|| (self.timeout_seconds as u64 + self.soak_seconds as u64) * 1_000_000)
+    }
+
+    /// The tests selected by the name and tag filters.
+    pub fn filter(&self) -> TestFilter {
+        TestFilter {
+            name: self.name_filter.clone(),
+            tag: self.tag_filter.clone(),
+            suite: None,
+        }
+    }
+}
+
+impl Default for TestConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+#[cfg(feature = "driver")]
+pub use self::driver::read_parameters;
+
+/// The parameters read at load, see [`install`].
+static CONFIG: AtomicPtr<TestConfig> = AtomicPtr::new(null_mut());
+
+/// Make `config` the parameters of every run, including those started through the control
+/// device. Called once from `DriverEntry` before the device is created.
+#[cfg(feature = "driver")]
+pub fn install(config: TestConfig) {
+    let old = CONFIG.swap(Box::into_raw(Box::new(config)), Ordering::SeqCst);
+    if !old.is_null() {
+        let _ = unsafe { Box::from_raw(old) };
+    }
+}
+
+/// The installed parameters, or the defaults before [`install`]. Under host-sim nothing is
+/// installed, so these are always the defaults.
+pub fn current() -> &'static TestConfig {
+    static DEFAULT: TestConfig = TestConfig::DEFAULT;
+
+    // only replaced by install at load and freed at unload, when no run can be in progress
+    unsafe { CONFIG.load(Ordering::SeqCst).as_ref() }.unwrap_or(&DEFAULT)
+}
+
+/// Free the installed parameters, called on driver unload.
+#[cfg(feature = "driver")]
+pub fn free() {
+    let old = CONFIG.swap(null_mut(), Ordering::SeqCst);
+    if !old.is_null() {
+        let _ = unsafe { Box::from_raw(old) };
+    }
+}
+
+#[cfg(feature = "driver")]
+mod driver {
+    use core::{mem::size_of, ptr::{addr_of, null_mut}, slice};
+
+    use alloc::{string::String, vec, vec::Vec};
+    use wdk::nt_success;
+    use wdk_sys::{ntddk::{ZwClose, ZwOpenKey, ZwQueryValueKey}, HANDLE, KEY_READ, KEY_VALUE_PARTIAL_INFORMATION, OBJECT_ATTRIBUTES, OBJ_CASE_INSENSITIVE, OBJ_KERNEL_HANDLE, PCUNICODE_STRING, REG_DWORD, REG_SZ, UNICODE_STRING, _KEY_VALUE_INFORMATION_CLASS::KeyValuePartialInformation};
+
+    use super::{TestConfig, TimeoutPolicy};
+    use crate::{println, utils::ToU16Vec};
+
+    /// Read the `Parameters` subkey of `registry_path`, the service key handed to `DriverEntry`.
+    ///
+    /// A missing subkey is not an error, the defaults are used.
+    pub fn read_parameters(registry_path: PCUNICODE_STRING) -> TestConfig {
+        let mut config = TestConfig::DEFAULT;
+
+        let Some(key) = (unsafe { ParametersKey::open(registry_path) }) else {
+            println!("[wdk-mutex-test] [i] No Parameters key, using default test parameters.");
+            return config;
+        };
+
+        if let Some(v) = key.dword("ThreadCount") {
+            config.threads = v;
+        }
+        if let Some(v) = key.dword("Iterations") {
+            config.iterations = v;
+        }
+        if let Some(v) = key.string("NameFilter") {
+            config.name_filter = Some(v);
+        }
+        if let Some(v) = key.string("TagFilter") {
+            config.tag_filter = Some(v);
+        }
+        if let Some(v) = key.dword("Repeat") {
+            config.repeat = v;
+        }
+        if let Some(v) = key.dword("FailFast") {
+            config.fail_fast = v != 0;
+        }
+        if let Some(v) = key.dword("SoakSeconds") {
+            config.soak_seconds = v;
+        }
+        if let Some(v) = key.dword("AllowUnsound") {
+            config.allow_unsound = v != 0;
+        }
+        if let Some(v) = key.dword("Benchmarks") {
+            config.benchmarks = v != 0;
+        }
+        if let Some(v) = key.dword("TimeoutSeconds") {
+            config.timeout_seconds = v;
+        }
+        if let Some(v) = key.dword("AbortOnTimeout") {
+            config.on_timeout = if v != 0 { TimeoutPolicy::Abort } else { TimeoutPolicy::Continue };
+        }
+        if let Some(v) = key.dword("WatchdogSeconds") {
+            config.watchdog_seconds = v;
+        }
+
+        config.clamped()
+    }
+
+    /// An open handle to the `Parameters` key, closed on drop.
+    struct ParametersKey(HANDLE);
+
+    impl ParametersKey {
+        unsafe fn open(registry_path: PCUNICODE_STRING) -> Option<Self> {
+            let service = unsafe { &*registry_path };
+            let service = unsafe { slice::from_raw_parts(service.Buffer, service.Length as usize / 2) };
+
+            let mut path: Vec<u16> = service.to_vec();
+            path.extend_from_slice(&"\\Parameters".to_u16_vec());
+            let mut name = unicode_string(&mut path);
+
+            let mut attributes = OBJECT_ATTRIBUTES {
+                Length: size_of::<OBJECT_ATTRIBUTES>() as u32,
+                RootDirectory: null_mut(),
+                ObjectName: &mut name,
+                Attributes: OBJ_CASE_INSENSITIVE | OBJ_KERNEL_HANDLE,
+                SecurityDescriptor: null_mut(),
+                SecurityQualityOfService: null_mut(),
+            };
+
+            let mut handle: HANDLE = null_mut();
+            let status = unsafe { ZwOpenKey(&mut handle, KEY_READ, &mut attributes) };
+            if !nt_success(status) {
+                return None;
+            }
+
+            Some(Self(handle))
+        }
+
+        /// The raw data and type of value `name`, or `None` if it does not exist.
+        fn query(&self, name: &str) -> Option<(u32, Vec<u8>)> {
+            let mut name_u16 = name.to_u16_vec();
+            let mut name = unicode_string(&mut name_u16);
+
+            // sized for the filters, values larger than this are ignored
+            let mut buf = vec![0u8; size_of::<KEY_VALUE_PARTIAL_INFORMATION>() + 512];
+            let mut result_len = 0u32;
+            let status = unsafe {
+                ZwQueryValueKey(
+                    self.0,
+                    &mut name,
+                    KeyValuePartialInformation,
+                    buf.as_mut_ptr().cast(),
+                    buf.len() as u32,
+                    &mut result_len,
+                )
+            };
+            if !nt_success(status) {
+                return None;
+            }
+
+            let info = buf.as_ptr() as *const KEY_VALUE_PARTIAL_INFORMATION;
+            let (ty, len) = unsafe { ((*info).Type, (*info).DataLength as usize) };
+            let data = unsafe { slice::from_raw_parts(addr_of!((*info).Data) as *const u8, len) };
+
+            Some((ty, data.to_vec()))
+        }
+
+        fn dword(&self, name: &str) -> Option<u32> {
+            match self.query(name)? {
+                (REG_DWORD, data) if data.len() == 4 => Some(u32::from_le_bytes([data[0], data[1], data[2], data[3]])),
+                _ => {
+                    println!("[wdk-mutex-test] [-] Parameters\\{name} is not a REG_DWORD, ignoring it.");
+                    None
+                },
+            }
+        }
+
+        fn string(&self, name: &str) -> Option<String> {
+            let (ty, data) = self.query(name)?;
+            if ty != REG_SZ {
+                println!("[wdk-mutex-test] [-] Parameters\\{name} is not a REG_SZ, ignoring it.");
+                return None;
+            }
+
+            let wide: Vec<u16> = data
+                .chunks_exact(2)
+                .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                .take_while(|&c| c != 0)
+                .collect();
+            let s = String::from_utf16_lossy(&wide);
+
+            // an empty string means no filter, the same as a missing value
+            (!s.is_empty()).then_some(s)
+        }
+    }
+
+    impl Drop for ParametersKey {
+        fn drop(&mut self) {
+            let _ = unsafe { ZwClose(self.0) };
+        }
+    }
+
+    /// A `UNICODE_STRING` over `buf`, which must outlive it. A trailing nul is not counted.
+    fn unicode_string(buf: &mut [u16]) -> UNICODE_STRING {
+        let len = buf.iter().position(|&c| c == 0).unwrap_or(buf.len());
+        UNICODE_STRING {
+            Length: (len * 2) as u16,
+            MaximumLength: (buf.len() * 2) as u16,
+            Buffer: buf.as_mut_ptr(),
+        }
+    }
+}
@@ -0,0 +1,397 @@
+//! Conformance suite every `wdk_mutex` primitive must pass.
+//!
+//! The tests are written once against [`LockPrimitive`], a test-side view of a mutex type and its
+//! Grt registration functions. Each primitive implements it on a unit adapter (see `test_kmutex`
+//! and `test_fast_mutex`) and registers the suite with [`conformance_tests!`], which yields one
+//! [`TestCase`](crate::registry::TestCase) per (primitive, test) pair.
+
+use core::{any::Any, mem, ops::DerefMut, ptr};
+
+use alloc::{boxed::Box, format, sync::Arc};
+
+use crate::{kernel::{self, POOL_TAG}, lock_tracking::{self, LockId}, println, registry::{TestContext, TestError, TestResult}, threads::SystemThreadGroup, wdk_mutex::errors::{DriverMutexError, GrtError}, workers::record_progress};
+
+/// A `wdk_mutex` primitive as seen by the conformance suite.
+pub trait LockPrimitive: 'static {
+    /// The mutex type over `T`, e.g. `KMutex<T>`.
+    type Mutex<T: 'static>: Send + Sync + 'static;
+
+    /// The guard returned by [`lock`](Self::lock).
+    type Guard<'a, T: 'static>: DerefMut<Target = T>;
+
+    /// Highest IRQL `new` is documented to succeed at, above it `IrqlTooHigh` is expected.
+    const MAX_NEW_IRQL: u8;
+
+    /// Highest IRQL `lock` is documented to succeed at, above it `IrqlTooHigh` is expected.
+    const MAX_LOCK_IRQL: u8;
+
+    fn new<T: 'static>(data: T) -> Result<Self::Mutex<T>, DriverMutexError>;
+
+    fn lock<T: 'static>(m: &Self::Mutex<T>) -> Result<Self::Guard<'_, T>, DriverMutexError>;
+
+    /// # Safety
+    ///
+    /// As for the primitive's own `to_owned`.
+    unsafe fn to_owned<T: 'static>(m: Self::Mutex<T>) -> T;
+
+    /// # Safety
+    ///
+    /// As for the primitive's own `to_owned_box`.
+    unsafe fn to_owned_box<T: 'static>(m: Self::Mutex<T>) -> Box<T>;
+
+    fn grt_register<T: Any>(label: &'static str, data: T) -> Result<(), GrtError>;
+
+    fn grt_get<T: Any>(key: &'static str) -> Result<&'static Self::Mutex<T>, GrtError>;
+}
+
+/// The full conformance suite for one primitive, as a `&'static [TestCase]`.
+///
+/// `$prefix` names the primitive in test names and tags, e.g. `"kmutex"`.
+macro_rules! conformance_tests {
+    ($adapter:ty, $prefix:literal, $suite:expr) => {
+        &[
+            $crate::registry::TestCase {
+                name: concat!($prefix, "::multithread_mutex_global_static"),
+                suite: $suite,
+                tags: &["multithread"],
+                run: $crate::conformance::multithread_mutex_global_static::<$adapter>,
+            },
+            $crate::registry::TestCase {
+                name: concat!($prefix, "::multithread_mutex_global_static_manual_pool"),
+                suite: $suite,
+                tags: &["multithread", "pool"],
+                run: $crate::conformance::multithread_mutex_global_static_manual_pool::<$adapter>,
+            },
+            $crate::registry::TestCase {
+                name: concat!($prefix, "::to_owned"),
+                suite: $suite,
+                tags: &["ownership"],
+                run: $crate::conformance::to_owned::<$adapter>,
+            },
+            $crate::registry::TestCase {
+                name: concat!($prefix, "::to_owned_box"),
+                suite: $suite,
+                tags: &["ownership"],
+                run: $crate::conformance::to_owned_box::<$adapter>,
+            },
+            $crate::registry::TestCase {
+                name: concat!($prefix, "::grt_thrice"),
+                suite: $crate::registry::Suite::Grt,
+                tags: &["multithread", $prefix],
+                run: $crate::conformance::grt_thrice::<$adapter>,
+            },
+            $crate::registry::TestCase {
+                name: concat!($prefix, "::grt_churn"),
+                suite: $crate::registry::Suite::Grt,
+                tags: &["multithread", "churn", $prefix],
+                run: $crate::grt_churn::grt_churn::<$adapter>,
+            },
+            $crate::registry::TestCase {
+                name: concat!($prefix, "::new_at_raised_irql"),
+                suite: $suite,
+                tags: &["irql"],
+                run: $crate::irql::new_at_raised_irql::<$adapter>,
+            },
+            $crate::registry::TestCase {
+                name: concat!($prefix, "::lock_at_raised_irql"),
+                suite: $suite,
+                tags: &["irql"],
+                run: $crate::irql::lock_at_raised_irql::<$adapter>,
+            },
+            $crate::registry::TestCase {
+                name: concat!($prefix, "::manual_pool_under_alloc_failure"),
+                suite: $suite,
+                tags: &["alloc", "pool"],
+                run: $crate::alloc_failure::manual_pool_under_alloc_failure::<$adapter>,
+            },
+            $crate::registry::TestCase {
+                name: concat!($prefix, "::manual_pool_under_memory_pressure"),
+                suite: $suite,
+                tags: &["alloc", "pool"],
+                run: $crate::alloc_failure::manual_pool_under_memory_pressure::<$adapter>,
+            },
+            $crate::registry::TestCase {
+                name: concat!($prefix, "::soak"),
+                suite: $suite,
+                tags: &["soak"],
+                run: $crate::soak::soak::<$adapter>,
+            },
+        ]
+    };
+}
+pub(crate) use conformance_tests;
+
+/// Tests the mutex by spawning `ctx.threads` threads, each performing `ctx.iterations` mutable
+/// modifications to the T inside the mutex.
+///
+/// Test passes if the result == threads * iterations.
+pub fn multithread_mutex_global_static<P: LockPrimitive>(ctx: &TestContext) -> TestResult {
+
+    //
+    // Prepare the mutex for access in multiple threads, each thread holds its own Arc.
+    //
+
+    let heap_mtx = Arc::new(P::new(0u32)?);
+
+    let mut threads = SystemThreadGroup::new();
+
+    //
+    // spawn the threads to test
+    //
+    let m = Arc::clone(&heap_mtx);
+    let iterations = ctx.iterations;
+    threads.spawn(ctx.threads, move || callback_multithread_mutex_global_static::<P>(&m, iterations))
+        .map_err(|s| TestError::status("spawning threads", s))?;
+
+
+    //
+    // Wait for every thread to finish
+    //
+
+    threads.join_all().map_err(|s| TestError::status("joining threads", s))?;
+
+
+    //
+    // Check the result
+    //
+    let expected = ctx.expected_total();
+
+    let val = *P::lock(&heap_mtx)?;
+    if val != expected {
+        return Err(TestError::fail(format!("expected {expected}, got {val}")));
+    }
+
+    Ok(())
+
+}
+
+/// Thread body for operating on the shared heap mutex
+fn callback_multithread_mutex_global_static<P: LockPrimitive>(m: &P::Mutex<u32>, iterations: u32) {
+    for _ in 0..iterations {
+        record_progress("locking");
+        let mut lock = lock_tracking::lock(LockId::of(m), || P::lock(m)).unwrap();
+        *lock += 1;
+    }
+}
+
+
+pub fn multithread_mutex_global_static_manual_pool<P: LockPrimitive>(ctx: &TestContext) -> TestResult {
+    //
+    // Prepare a mutex over a manual pool allocation for access in multiple threads.
+    //
+
+    let my_pool_allocation: *mut u32 = unsafe {
+        kernel::ex_allocate_pool2(size_of::<u32>(), POOL_TAG)
+    } as *mut u32;
+    if my_pool_allocation.is_null() {
+        return Err(TestError::fail("ExAllocatePool2 failed"));
+    }
+    // freed on every return, after the threads below are joined as they are declared later
+    let allocation = PoolAllocation(my_pool_allocation as *mut _);
+    unsafe {ptr::write(my_pool_allocation, 0u32)};
+    let my_mutex: Arc<P::Mutex<*mut u32>> = Arc::new(P::new(my_pool_allocation)?);
+
+    let mut threads = SystemThreadGroup::new();
+
+    //
+    // spawn the threads to test
+    //
+    let m = Arc::clone(&my_mutex);
+    let iterations = ctx.iterations;
+    threads.spawn(ctx.threads, move || callback_multithread_mutex_global_static_manual_pool::<P>(&m, iterations))
+        .map_err(|s| TestError::status("spawning threads", s))?;
+
+
+    //
+    // Wait for every thread to finish
+    //
+
+    if let Err(status) = threads.join_all() {
+        // threads still running may yet write to the allocation, leave it to them
+        mem::forget(allocation);
+        return Err(TestError::status("joining threads", status));
+    }
+
+
+    //
+    // Check the result
+    //
+    let expected = ctx.expected_total();
+
+    let y = unsafe { **P::lock(&my_mutex)? };
+
+    if y != expected {
+        return Err(TestError::fail(format!("expected {expected}, got {y}")));
+    }
+
+    Ok(())
+}
+
+/// A pool allocation made through [`kernel::ex_allocate_pool2`], freed when dropped.
+struct PoolAllocation(kernel::PVOID);
+
+impl Drop for PoolAllocation {
+    fn drop(&mut self) {
+        unsafe { kernel::ex_free_pool(self.0) };
+    }
+}
+
+fn callback_multithread_mutex_global_static_manual_pool<P: LockPrimitive>(m: &P::Mutex<*mut u32>, iterations: u32) {
+    for _ in 0..iterations {
+        record_progress("locking");
+        let mut lock = lock_tracking::lock(LockId::of(m), || P::lock(m)).unwrap();
+        unsafe { **lock += 1 };
+
+        // below left in for examples
+        // let val = **lock;
+        // println!("Value after change: {:?}", val);
+    }
+}
+
+
+pub fn to_owned<P: LockPrimitive>(_: &TestContext) -> TestResult {
+
+    // testing to_owned
+    let m = P::new(0u8)?;
+    {
+        let mut lock = P::lock(&m)?;
+        *lock += 1;
+    }
+
+    let x = unsafe { P::to_owned(m) };
+
+    if x == 1 {
+        Ok(())
+    } else {
+        Err(TestError::fail(format!("expected 1, got {x}")))
+    }
+}
+
+pub fn to_owned_box<P: LockPrimitive>(_: &TestContext) -> TestResult {
+
+    // testing to_owned
+    let m = P::new(0u8)?;
+    {
+        let mut lock = P::lock(&m)?;
+        *lock += 1;
+    }
+
+    let x = unsafe { P::to_owned_box(m) };
+
+    if *x == 1 {
+        Ok(())
+    } else {
+        Err(TestError::fail(format!("expected 1, got {x}")))
+    }
+}
+
+/// Three nested Grt registrations, each incremented from `ctx.threads` threads while the next
+/// level runs.
+pub fn grt_thrice<P: LockPrimitive>(ctx: &TestContext) -> TestResult {
+
+    test_grt::<P>(ctx)?;
+
+    Ok(())
+}
+
+fn test_grt<P: LockPrimitive>(ctx: &TestContext) -> TestResult {
+    let key = ctx.grt.key("my_test_mutex");
+    let mut threads = SystemThreadGroup::new();
+
+    if let Err(e) = P::grt_register(key, 0u32) {
+        return Err(TestError::fail(format!("registering {key} failed: {e:?}")));
+    };
+
+    let iterations = ctx.iterations;
+    threads.spawn(ctx.threads, move || callback_fn_grt::<P>(key, iterations))
+        .map_err(|s| TestError::status("spawning threads", s))?;
+
+    test_grt2::<P>(ctx)?;
+
+    //
+    // Wait for every thread to finish
+    //
+
+    threads.join_all().map_err(|s| TestError::status("joining threads", s))?;
+
+    let my_mut = P::grt_get::<u32>(key);
+    let lock = P::lock(my_mut?)?;
+    if *lock != ctx.expected_total() {
+        return Err(TestError::fail(format!("expected {}, got {}", ctx.expected_total(), *lock)));
+    }
+
+    Ok(())
+
+}
+
+fn test_grt2<P: LockPrimitive>(ctx: &TestContext) -> TestResult {
+    let key = ctx.grt.key("my_test_mutex2");
+    let mut threads = SystemThreadGroup::new();
+
+    if let Err(e) = P::grt_register(key, 0u32) {
+        return Err(TestError::fail(format!("registering {key} failed: {e:?}")));
+    };
+
+    test_grt3::<P>(ctx)?;
+
+    let iterations = ctx.iterations;
+    threads.spawn(ctx.threads, move || callback_fn_grt::<P>(key, iterations))
+        .map_err(|s| TestError::status("spawning threads", s))?;
+
+    //
+    // Wait for every thread to finish
+    //
+
+    threads.join_all().map_err(|s| TestError::status("joining threads", s))?;
+
+    let my_mut = P::grt_get::<u32>(key);
+    let lock = P::lock(my_mut?)?;
+    if *lock != ctx.expected_total() {
+        return Err(TestError::fail(format!("expected {}, got {}", ctx.expected_total(), *lock)));
+    }
+
+    Ok(())
+}
+
+fn test_grt3<P: LockPrimitive>(ctx: &TestContext) -> TestResult {
+    let key = ctx.grt.key("my_test_mutex3");
+    let mut threads = SystemThreadGroup::new();
+
+    if let Err(e) = P::grt_register(key, 0u32) {
+        return Err(TestError::fail(format!("registering {key} failed: {e:?}")));
+    };
+
+    let iterations = ctx.iterations;
+    threads.spawn(ctx.threads, move || callback_fn_grt::<P>(key, iterations))
+        .map_err(|s| TestError::status("spawning threads", s))?;
+
+    //
+    // Wait for every thread to finish
+    //
+
+    threads.join_all().map_err(|s| TestError::status("joining threads", s))?;
+
+    let my_mut = P::grt_get::<u32>(key);
+    let lock = P::lock(my_mut?)?;
+    if *lock != ctx.expected_total() {
+        return Err(TestError::fail(format!("expected {}, got {}", ctx.expected_total(), *lock)));
+    }
+
+    Ok(())
+}
+
+fn callback_fn_grt<P: LockPrimitive>(key: &'static str, iterations: u32) {
+    for _ in 0..iterations {
+        record_progress("getting the mutex from the Grt");
+        let my_mut = P::grt_get::<u32>(key);
+        if let Err(e) = my_mut {
+            println!("[wdk-mutex-test] [-] Error in callback: {e:?}");
+            return;
+        }
+
+        record_progress("locking");
+        let m = my_mut.unwrap();
+        let mut lock = lock_tracking::lock(LockId::Key(key), || P::lock(m)).unwrap();
+        *lock += 1;
+    }
+}
@@ -0,0 +1,82 @@
+//! `IRP_MJ_DEVICE_CONTROL` handling for `\Device\WdkMutexTest`, letting user mode list and run
+//! tests and fetch results without reloading the driver.
+//!
+//! The request / response encoding lives in the `wdk-mutex-tests-protocol` crate; this module only
+//! moves bytes between the IRP and the runner.
+
+use alloc::{string::ToString, vec::Vec};
+use wdk_mutex_tests_protocol::{Request, Response, TestInfo, RESPONSE_HEADER_LEN};
+
+use crate::{config, dispatch::Irp, kernel::{NTSTATUS, STATUS_BUFFER_OVERFLOW, STATUS_BUFFER_TOO_SMALL, STATUS_DEVICE_BUSY, STATUS_INVALID_PARAMETER, STATUS_SUCCESS}, println, registry, runner};
+
+/// `IRP_MJ_DEVICE_CONTROL`, returning the status and byte count to complete `irp` with. All
+/// IOCTLs use `METHOD_BUFFERED`.
+pub fn device_control(irp: &mut impl Irp) -> (NTSTATUS, usize) {
+    // the input is decoded into owned values before anything is written back, as with buffered I/O
+    // the input and output share the system buffer
+    match handle_request(irp.ioctl_code(), irp.input()) {
+        Ok(response) => {
+            let (status, written) = output_len(response.len(), irp.output_len());
+            if written != 0 {
+                irp.write_output(&response[..written]);
+            }
+            (status, written)
+        },
+        Err(status) => (status, 0),
+    }
+}
+
+/// How much of a `response_len` byte response fits in an `out_len` byte buffer.
+///
+/// When the whole response does not fit but its header does, only the header is returned with
+/// `STATUS_BUFFER_OVERFLOW`, so the caller can read `total_len` and retry with a larger buffer.
+fn output_len(response_len: usize, out_len: usize) -> (NTSTATUS, usize) {
+    if out_len >= response_len {
+        (STATUS_SUCCESS, response_len)
+    } else if out_len >= RESPONSE_HEADER_LEN {
+        (STATUS_BUFFER_OVERFLOW, RESPONSE_HEADER_LEN)
+    } else {
+        (STATUS_BUFFER_TOO_SMALL, 0)
+    }
+}
+
+/// Decode and carry out a single request, returning the encoded response.
+fn handle_request(ioctl_code: u32, input: &[u8]) -> Result<Vec<u8>, NTSTATUS> {
+    let request = match Request::decode(ioctl_code, input) {
+        Ok(r) => r,
+        Err(e) => {
+            println!("[wdk-mutex-test] [-] Bad control request {ioctl_code:#x}: {e}");
+            return Err(STATUS_INVALID_PARAMETER);
+        },
+    };
+
+    let format = request.format();
+    let response = match request {
+        Request::ListTests { .. } => Response::Tests(
+            registry::all_tests()
+                .map(|t| TestInfo {
+                    name: t.name.to_string(),
+                    suite: t.suite,
+                    tags: t.tags.iter().map(|tag| tag.to_string()).collect(),
+                })
+                .collect(),
+        ),
+        Request::RunTests { filter, .. } => {
+            let Some(_run) = runner::begin_run() else {
+                return Err(STATUS_DEVICE_BUSY);
+            };
+
+            let report = runner::run_tests(registry::matching(&filter), config::current());
+            report.print_summary();
+            let outcomes = report.records.iter().map(|r| r.to_outcome()).collect();
+            runner::store_report(report);
+
+            Response::Results(outcomes)
+        },
+        Request::GetResults { .. } => Response::Results(runner::with_last_report(|report| {
+            report.map_or_else(Vec::new, |r| r.records.iter().map(|r| r.to_outcome()).collect())
+        })),
+    };
+
+    Ok(response.encode(format))
+}
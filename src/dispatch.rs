@@ -0,0 +1,153 @@
+//! IRP dispatch for `\Device\WdkMutexTest`.
+//!
+//! Every major function of the driver object points at [`dispatch_irp`], which hands the IRP to
+//! [`dispatch`] behind the [`Irp`] trait. The handlers only see that trait, so the same code
+//! completes IRPs built by the I/O manager in the driver and the stand-ins in
+//! [`crate::host_sim::irp`] under `cargo test`.
+//!
+//! | Major function          | Handling                                                        |
+//! |-------------------------|-----------------------------------------------------------------|
+//! | `IRP_MJ_CREATE`         | Succeeds, any number of handles may be open                     |
+//! | `IRP_MJ_CLEANUP`        | Succeeds, nothing is queued that would need cancelling          |
+//! | `IRP_MJ_CLOSE`          | Succeeds                                                        |
+//! | `IRP_MJ_READ`           | Drains the harness log, see [`crate::log`], 0 bytes once empty  |
+//! | `IRP_MJ_DEVICE_CONTROL` | Carried out by [`crate::control`]                               |
+//! | anything else           | Fails with `STATUS_INVALID_DEVICE_REQUEST`                      |
+//!
+//! Every IRP is completed before its dispatch routine returns; none are pended.
+
+use crate::{control, kernel::{NTSTATUS, IRP_MJ_CLEANUP, IRP_MJ_CLOSE, IRP_MJ_CREATE, IRP_MJ_DEVICE_CONTROL, IRP_MJ_READ, STATUS_INVALID_DEVICE_REQUEST, STATUS_SUCCESS}, log, println};
+
+#[cfg(feature = "driver")]
+pub use self::driver::dispatch_irp;
+
+/// The parts of an IRP and its current stack location the handlers use.
+///
+/// The device uses buffered I/O, so input and output share one system buffer: [`Irp::input`]
+/// borrows it, and anything decoded from it must be owned before [`Irp::write_output`] is called.
+pub trait Irp {
+    /// `MajorFunction` of the current stack location.
+    fn major_function(&self) -> u8;
+
+    /// `IoControlCode` of an `IRP_MJ_DEVICE_CONTROL` request.
+    fn ioctl_code(&self) -> u32;
+
+    /// The input buffer of an `IRP_MJ_DEVICE_CONTROL` request, empty if there is none.
+    fn input(&self) -> &[u8];
+
+    /// Bytes the caller can receive: `OutputBufferLength` of a control request, or `Length` of a
+    /// read.
+    fn output_len(&self) -> usize;
+
+    /// Copy `data` to the start of the output buffer. `data` is at most [`Irp::output_len`] long.
+    fn write_output(&mut self, data: &[u8]);
+
+    /// Set the final status and byte count and complete the IRP. Called exactly once.
+    fn complete(&mut self, status: NTSTATUS, information: usize);
+}
+
+/// Carry out `irp` and complete it, returning its final status.
+pub fn dispatch(irp: &mut impl Irp) -> NTSTATUS {
+    let (status, information) = match irp.major_function() {
+        IRP_MJ_CREATE | IRP_MJ_CLEANUP | IRP_MJ_CLOSE => (STATUS_SUCCESS, 0),
+        IRP_MJ_READ => read(irp),
+        IRP_MJ_DEVICE_CONTROL => control::device_control(irp),
+        major => {
+            println!("[wdk-mutex-test] [-] Unsupported request, major function {major:#x}.");
+            (STATUS_INVALID_DEVICE_REQUEST, 0)
+        },
+    };
+
+    irp.complete(status, information);
+    status
+}
+
+/// `IRP_MJ_READ`: as many whole lines of the log as fit, removed from it. The offset is ignored,
+/// every read continues where the last one left off; a read that returns no bytes means the log is
+/// empty for now.
+fn read(irp: &mut impl Irp) -> (NTSTATUS, usize) {
+    let lines = log::drain(irp.output_len());
+    if !lines.is_empty() {
+        irp.write_output(&lines);
+    }
+
+    (STATUS_SUCCESS, lines.len())
+}
+
+#[cfg(feature = "driver")]
+mod driver {
+    use core::{ptr::copy_nonoverlapping, slice};
+
+    use wdk_sys::{ntddk::IofCompleteRequest, DEVICE_OBJECT, IO_NO_INCREMENT, NTSTATUS, PIO_STACK_LOCATION, PIRP};
+
+    use super::{dispatch, Irp, IRP_MJ_READ};
+
+    /// An IRP from the I/O manager, at the stack location for this driver.
+    struct KernelIrp {
+        pirp: PIRP,
+        stack: PIO_STACK_LOCATION,
+    }
+
+    impl KernelIrp {
+        /// Equivalent of the `IoGetCurrentIrpStackLocation` inline function from `wdm.h`.
+        unsafe fn new(pirp: PIRP) -> Self {
+            let stack = unsafe { (*pirp).Tail.Overlay.__bindgen_anon_2.__bindgen_anon_1.CurrentStackLocation };
+            Self { pirp, stack }
+        }
+
+        fn system_buffer(&self) -> *mut u8 {
+            unsafe { (*self.pirp).AssociatedIrp.SystemBuffer as *mut u8 }
+        }
+    }
+
+    impl Irp for KernelIrp {
+        fn major_function(&self) -> u8 {
+            unsafe { (*self.stack).MajorFunction }
+        }
+
+        fn ioctl_code(&self) -> u32 {
+            unsafe { (*self.stack).Parameters.DeviceIoControl.IoControlCode }
+        }
+
+        fn input(&self) -> &[u8] {
+            let len = unsafe { (*self.stack).Parameters.DeviceIoControl.InputBufferLength } as usize;
+            let buffer = self.system_buffer();
+            if buffer.is_null() || len == 0 {
+                return &[];
+            }
+            unsafe { slice::from_raw_parts(buffer, len) }
+        }
+
+        fn output_len(&self) -> usize {
+            let len = unsafe {
+                if self.major_function() == IRP_MJ_READ {
+                    (*self.stack).Parameters.Read.Length
+                } else {
+                    (*self.stack).Parameters.DeviceIoControl.OutputBufferLength
+                }
+            };
+            len as usize
+        }
+
+        fn write_output(&mut self, data: &[u8]) {
+            let buffer = self.system_buffer();
+            let len = data.len().min(self.output_len());
+            if len != 0 && !buffer.is_null() {
+                unsafe { copy_nonoverlapping(data.as_ptr(), buffer, len) };
+            }
+        }
+
+        fn complete(&mut self, status: NTSTATUS, information: usize) {
+            unsafe {
+                (*self.pirp).IoStatus.__bindgen_anon_1.Status = status;
+                (*self.pirp).IoStatus.Information = information as u64;
+                IofCompleteRequest(self.pirp, IO_NO_INCREMENT as i8);
+            }
+        }
+    }
+
+    /// Dispatch routine for every major function of the driver object, see [`dispatch`].
+    pub unsafe extern "C" fn dispatch_irp(_device: *mut DEVICE_OBJECT, pirp: PIRP) -> NTSTATUS {
+        dispatch(&mut unsafe { KernelIrp::new(pirp) })
+    }
+}
@@ -0,0 +1,139 @@
+//! Pool allocation-failure injection.
+//!
+//! Pool allocations made through [`crate::kernel::ex_allocate_pool2`] consult the [`Injection`]
+//! armed by a test, which can fail the `n`th allocation, or a random fraction, to drive error
+//! paths that never run on a healthy machine.
+//!
+//! Only allocations made by the thread that armed the injection are counted or failed, so the
+//! harness and any other thread carry on unaffected. While armed, the injection also counts the
+//! allocations it let through and the frees, so a test can assert nothing was leaked by the calls
+//! it made.
+//!
+//! This only reaches the harness's own pool allocations, see [`crate::alloc_failure`].
+//! `wdk_mutex` calls `ExAllocatePool2` itself, so the error paths of `new`, `Grt::register_*` and
+//! `Grt::init` are not exercised by any test. The global allocator is not wrapped either: a
+//! failed `Box::new` bugchecks the machine rather than returning an error to check.
+
+use core::{marker::PhantomData, sync::atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering}};
+
+use crate::kernel;
+
+/// Which allocations an [`Injection`] fails.
+#[derive(Debug, Clone, Copy)]
+pub enum Plan {
+    /// Fail the `n`th allocation, counting from 1, and none after it.
+    Nth(u64),
+    /// Fail each allocation with a probability of `per_million` in a million, drawn from a
+    /// generator seeded with `seed`.
+    Fraction { per_million: u32, seed: u64 },
+}
+
+const MODE_NTH: u8 = 0;
+const MODE_FRACTION: u8 = 1;
+
+/// Thread the injection is armed for, 0 when disarmed.
+static ARMED_THREAD: AtomicUsize = AtomicUsize::new(0);
+static MODE: AtomicU8 = AtomicU8::new(MODE_NTH);
+/// `n` of [`Plan::Nth`], or `per_million` of [`Plan::Fraction`].
+static PARAM: AtomicU64 = AtomicU64::new(0);
+static RNG: AtomicU64 = AtomicU64::new(1);
+/// Allocations seen so far, failed or not.
+static SEEN: AtomicU64 = AtomicU64::new(0);
+static INJECTED: AtomicU64 = AtomicU64::new(0);
+static ALLOCATED: AtomicU64 = AtomicU64::new(0);
+static FREED: AtomicU64 = AtomicU64::new(0);
+
+/// An armed injection, disarmed when dropped. Only one is armed at a time.
+pub struct Injection {
+    // tied to the arming thread, the only one it affects
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Injection {
+    /// Start failing allocations made by the current thread according to `plan`.
+    ///
+    /// Replaces any injection already armed.
+    pub fn arm(plan: Plan) -> Self {
+        ARMED_THREAD.store(0, Ordering::SeqCst);
+
+        let (mode, param, seed) = match plan {
+            Plan::Nth(n) => (MODE_NTH, n, 1),
+            Plan::Fraction { per_million, seed } => (MODE_FRACTION, per_million as u64, seed),
+        };
+        MODE.store(mode, Ordering::SeqCst);
+        PARAM.store(param, Ordering::SeqCst);
+        // xorshift must not start from zero
+        RNG.store(seed | 1, Ordering::SeqCst);
+        for counter in [&SEEN, &INJECTED, &ALLOCATED, &FREED] {
+            counter.store(0, Ordering::SeqCst);
+        }
+
+        ARMED_THREAD.store(kernel::current_thread_id(), Ordering::SeqCst);
+        Self { _not_send: PhantomData }
+    }
+
+    /// Allocations failed so far.
+    pub fn injected(&self) -> u64 {
+        INJECTED.load(Ordering::SeqCst)
+    }
+
+    /// Allocations let through since arming that have not been freed since. Frees of allocations
+    /// made before arming count against it too, so arm just before the calls being checked.
+    pub fn outstanding(&self) -> i64 {
+        ALLOCATED.load(Ordering::SeqCst) as i64 - FREED.load(Ordering::SeqCst) as i64
+    }
+}
+
+impl Drop for Injection {
+    fn drop(&mut self) {
+        ARMED_THREAD.store(0, Ordering::SeqCst);
+    }
+}
+
+/// Whether the armed injection applies to an allocation on this thread.
+fn applies() -> bool {
+    let armed = ARMED_THREAD.load(Ordering::SeqCst);
+    armed != 0 && armed == kernel::current_thread_id()
+}
+
+/// Called before allocating, `true` if the allocation must fail instead.
+pub fn should_fail() -> bool {
+    if !applies() {
+        return false;
+    }
+
+    let seen = SEEN.fetch_add(1, Ordering::SeqCst) + 1;
+    let fail = match MODE.load(Ordering::SeqCst) {
+        MODE_NTH => seen == PARAM.load(Ordering::SeqCst),
+        _ => next_random() % 1_000_000 < PARAM.load(Ordering::SeqCst),
+    };
+
+    if fail {
+        INJECTED.fetch_add(1, Ordering::SeqCst);
+    }
+    fail
+}
+
+/// Called after an allocation succeeded.
+pub fn record_alloc() {
+    if applies() {
+        ALLOCATED.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// Called when an allocation is freed.
+pub fn record_free() {
+    if applies() {
+        FREED.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// xorshift64*, only ever advanced by the armed thread.
+fn next_random() -> u64 {
+    let mut x = RNG.load(Ordering::Relaxed);
+    x ^= x >> 12;
+    x ^= x << 25;
+    x ^= x >> 27;
+    RNG.store(x, Ordering::Relaxed);
+    x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+}
@@ -0,0 +1,201 @@
+//! Concurrent registration and lookup churn on the global registry, [`Grt`](crate::wdk_mutex::grt::Grt).
+//!
+//! The other Grt tests register every key from the test thread before any worker starts, so the
+//! registry is only ever read concurrently. Here `ctx.threads` threads start together and, while
+//! the others are doing the same:
+//!
+//! - register keys of their own, [`OWN_KEYS`] each;
+//! - race every other thread to register the same [`CONTESTED_KEYS`];
+//! - look up keys belonging to the other threads, `ctx.iterations` times.
+//!
+//! Every entry records which thread registered it under which key. A lookup may find a key not yet
+//! registered, but anything it does find must be a complete entry for that key, and every
+//! contested key must have been won by exactly one registration. Once the threads are joined the
+//! registry must hold exactly the expected entries, with the lookups' increments all accounted for.
+
+use core::{hint::spin_loop, sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering}};
+
+use alloc::{format, sync::Arc, vec::Vec};
+
+use crate::{conformance::LockPrimitive, lock_tracking::{self, LockId}, println, registry::{TestContext, TestError, TestResult}, threads::SystemThreadGroup, wdk_mutex::errors::GrtError, workers::record_progress};
+
+/// Keys each thread registers for itself.
+const OWN_KEYS: usize = 8;
+
+/// Keys every thread tries to register.
+const CONTESTED_KEYS: usize = 4;
+
+/// Recorded in [`Entry::index`] of contested keys' entries, which have no per-thread index.
+const CONTESTED: u32 = u32::MAX;
+
+/// What each key maps to.
+struct Entry {
+    /// Id of the thread whose registration made it.
+    owner: u32,
+    /// Index of the key within its owner's keys, or [`CONTESTED`].
+    index: u32,
+    /// Successful lookups of this entry.
+    hits: u64,
+}
+
+/// State shared by the churning threads.
+struct Shared {
+    /// `own[t * OWN_KEYS + i]` is thread `t`'s `i`th key.
+    own: Vec<&'static str>,
+    contested: Vec<&'static str>,
+    threads: usize,
+    lookups: u32,
+    next_id: AtomicUsize,
+    started: AtomicUsize,
+    /// Registrations that succeeded, per contested key.
+    wins: [AtomicU32; CONTESTED_KEYS],
+    /// Id of the last thread to win each contested key.
+    winners: [AtomicU32; CONTESTED_KEYS],
+    /// Lookups that found an entry and incremented its hits.
+    hits: AtomicU64,
+    violations: AtomicU64,
+}
+
+impl Shared {
+    fn violation(&self, what: &str) {
+        println!("[wdk-mutex-test] [-] Grt churn: {what}");
+        self.violations.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// Churn the Grt through `P`, see the module documentation.
+pub fn grt_churn<P: LockPrimitive>(ctx: &TestContext) -> TestResult {
+    let shared = Arc::new(Shared {
+        own: (0..ctx.threads * OWN_KEYS)
+            .map(|k| ctx.grt.key(&format!("churn_{}_{}", k / OWN_KEYS, k % OWN_KEYS)))
+            .collect(),
+        contested: (0..CONTESTED_KEYS).map(|k| ctx.grt.key(&format!("contested_{k}"))).collect(),
+        threads: ctx.threads,
+        lookups: ctx.iterations,
+        next_id: AtomicUsize::new(0),
+        started: AtomicUsize::new(0),
+        wins: Default::default(),
+        winners: Default::default(),
+        hits: AtomicU64::new(0),
+        violations: AtomicU64::new(0),
+    });
+
+    let mut threads = SystemThreadGroup::new();
+    let s = Arc::clone(&shared);
+    if let Err(status) = threads.spawn(ctx.threads, move || churn_thread::<P>(&s)) {
+        // release whichever threads did start from waiting on the rest, they are joined on drop
+        shared.started.fetch_add(ctx.threads, Ordering::SeqCst);
+        return Err(TestError::status("spawning threads", status));
+    }
+    threads.join_all().map_err(|s| TestError::status("joining threads", s))?;
+
+    //
+    // Check the registry ended up holding exactly what was registered
+    //
+
+    let mut hits = 0;
+    for (k, &key) in shared.own.iter().enumerate() {
+        let entry = P::lock(P::grt_get::<Entry>(key)?)?;
+        if entry.owner as usize != k / OWN_KEYS || entry.index as usize != k % OWN_KEYS {
+            shared.violation(&format!("{key} holds the entry of thread {} key {}", entry.owner, entry.index));
+        }
+        hits += entry.hits;
+    }
+
+    for (k, &key) in shared.contested.iter().enumerate() {
+        let wins = shared.wins[k].load(Ordering::SeqCst);
+        if wins != 1 {
+            shared.violation(&format!("{key} was registered {wins} times"));
+        }
+
+        let entry = P::lock(P::grt_get::<Entry>(key)?)?;
+        let winner = shared.winners[k].load(Ordering::SeqCst);
+        if entry.owner != winner || entry.index != CONTESTED {
+            shared.violation(&format!("{key} holds the entry of thread {}, but thread {winner} won it", entry.owner));
+        }
+        hits += entry.hits;
+    }
+
+    let looked_up = shared.hits.load(Ordering::SeqCst);
+    if hits != looked_up {
+        shared.violation(&format!("entries counted {hits} hits for {looked_up} successful lookups"));
+    }
+
+    let violations = shared.violations.load(Ordering::SeqCst);
+    if violations != 0 {
+        return Err(TestError::fail(format!("{violations} violations, see the log")));
+    }
+
+    Ok(())
+}
+
+fn churn_thread<P: LockPrimitive>(s: &Shared) {
+    let id = s.next_id.fetch_add(1, Ordering::SeqCst);
+
+    // start together, so registrations overlap with the other threads' lookups
+    record_progress("waiting for the other threads to start");
+    s.started.fetch_add(1, Ordering::SeqCst);
+    while s.started.load(Ordering::SeqCst) < s.threads {
+        spin_loop();
+    }
+
+    for i in 0..OWN_KEYS {
+        record_progress("registering");
+        let entry = Entry { owner: id as u32, index: i as u32, hits: 0 };
+        if let Err(e) = P::grt_register(s.own[id * OWN_KEYS + i], entry) {
+            s.violation(&format!("thread {id} failed to register its key {i}: {e:?}"));
+        }
+
+        let k = (id + i) % CONTESTED_KEYS;
+        let entry = Entry { owner: id as u32, index: CONTESTED, hits: 0 };
+        match P::grt_register(s.contested[k], entry) {
+            Ok(()) => {
+                s.wins[k].fetch_add(1, Ordering::SeqCst);
+                s.winners[k].store(id as u32, Ordering::SeqCst);
+            },
+            Err(GrtError::KeyExists) => {},
+            Err(e) => s.violation(&format!("thread {id} registering contested key {k}: {e:?}")),
+        }
+
+        // look at whatever the others have registered so far
+        lookup::<P>(s, id, i);
+    }
+
+    for n in 0..s.lookups as usize {
+        record_progress("looking up");
+        lookup::<P>(s, id, n);
+    }
+}
+
+/// Look up the `n`th key in a walk over every key, starting from the thread after `id`'s.
+fn lookup<P: LockPrimitive>(s: &Shared, id: usize, n: usize) {
+    let total = s.own.len() + CONTESTED_KEYS;
+    let k = ((id + 1) * OWN_KEYS + n) % total;
+
+    let (key, owner, index) = match s.own.get(k) {
+        Some(&key) => (key, Some((k / OWN_KEYS) as u32), (k % OWN_KEYS) as u32),
+        None => (s.contested[k - s.own.len()], None, CONTESTED),
+    };
+
+    let m = match P::grt_get::<Entry>(key) {
+        Ok(m) => m,
+        // not registered yet
+        Err(GrtError::KeyNotFound) => return,
+        Err(e) => return s.violation(&format!("thread {id} looking up {key}: {e:?}")),
+    };
+
+    let mut entry = match lock_tracking::lock(LockId::Key(key), || P::lock(m)) {
+        Ok(guard) => guard,
+        Err(e) => return s.violation(&format!("thread {id} locking {key}: {e:?}")),
+    };
+
+    let owner_ok = owner.map_or((entry.owner as usize) < s.threads, |o| entry.owner == o);
+    if !owner_ok || entry.index != index {
+        let (o, i) = (entry.owner, entry.index);
+        drop(entry);
+        return s.violation(&format!("thread {id} found thread {o} key {i} under {key}"));
+    }
+
+    entry.hits += 1;
+    s.hits.fetch_add(1, Ordering::SeqCst);
+}
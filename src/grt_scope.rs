@@ -0,0 +1,150 @@
+//! Per-test namespaces in the global registry.
+//!
+//! `Grt` keys are global `&'static str`s that live until the registry is destroyed, so tests that
+//! registered fixed keys collided with each other and could not be run twice in one driver load.
+//! Instead, each test gets a [`GrtScope`] through its [`TestContext`](crate::registry::TestContext)
+//! and asks it for keys: `ctx.grt.key("counter")` yields a key unique to that run of the test.
+//!
+//! The key strings are leaked on purpose: they are copied into chunks of pool taken outside the
+//! tracked allocators, so the leak check does not see them, and only freed by [`free_keys`] on
+//! driver unload, after the Grt itself. A key is therefore valid for as long as anything, the
+//! Grt or a thread of an abandoned test, can refer to it.
+//!
+//! The `Grt` has no way to remove a single key, so once a test that used its scope has finished,
+//! the runner tears the scope down by destroying and re-initialising the registry. Every earlier
+//! scope has been torn down the same way, so at that point the registry holds only this scope's
+//! keys and nothing registered survives from one test to the next, except after a test timed out,
+//! see [`GrtScope::abandon`].
+
+use core::{cell::RefCell, hint::spin_loop, mem::size_of, ptr::{copy_nonoverlapping, null_mut}, slice, str, sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, Ordering}};
+
+use alloc::{collections::BTreeSet, format};
+
+use crate::{kernel::{self, POOL_TAG}, wdk_mutex::{errors::GrtError, grt::Grt}};
+
+/// Source of scope ids, unique for the driver's lifetime.
+static NEXT_SCOPE_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Bytes of key strings held by one chunk, unless a single key needs more.
+const CHUNK_CAPACITY: usize = 4096;
+
+/// Header of a chunk of key strings, the strings follow it.
+struct KeyChunk {
+    /// The chunk filled before this one, null for the first. Only walked on driver unload.
+    #[cfg_attr(feature = "host-sim", allow(dead_code))]
+    previous: *mut KeyChunk,
+    capacity: usize,
+    used: usize,
+}
+
+/// The chunk keys are currently copied into, the last of a list linked by `previous`.
+static KEY_CHUNKS: AtomicPtr<KeyChunk> = AtomicPtr::new(null_mut());
+
+/// Serialises access to [`KEY_CHUNKS`], held at `DISPATCH_LEVEL` like the log lock.
+static KEY_LOCK: AtomicBool = AtomicBool::new(false);
+
+/// Copy `key` into the current chunk, or a new one if it does not fit, for the driver's lifetime.
+fn leak_key(key: &str) -> &'static str {
+    let raised = kernel::raise_irql(kernel::DISPATCH_LEVEL);
+    while KEY_LOCK
+        .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+        spin_loop();
+    }
+
+    let mut chunk = KEY_CHUNKS.load(Ordering::SeqCst);
+    if chunk.is_null() || unsafe { (*chunk).capacity - (*chunk).used } < key.len() {
+        let capacity = CHUNK_CAPACITY.max(key.len());
+        let fresh = unsafe { kernel::pool_alloc(size_of::<KeyChunk>() + capacity, POOL_TAG) } as *mut KeyChunk;
+        if fresh.is_null() {
+            KEY_LOCK.store(false, Ordering::Release);
+            panic!("no pool left for a Grt key");
+        }
+        unsafe { fresh.write(KeyChunk { previous: chunk, capacity, used: 0 }) };
+        KEY_CHUNKS.store(fresh, Ordering::SeqCst);
+        chunk = fresh;
+    }
+
+    let leaked = unsafe {
+        let dest = (chunk.add(1) as *mut u8).add((*chunk).used);
+        copy_nonoverlapping(key.as_ptr(), dest, key.len());
+        (*chunk).used += key.len();
+        str::from_utf8_unchecked(slice::from_raw_parts(dest, key.len()))
+    };
+
+    KEY_LOCK.store(false, Ordering::Release);
+    drop(raised);
+    leaked
+}
+
+/// Free every key string, called on driver unload once the Grt has been destroyed and no thread
+/// is left that could use a key.
+#[cfg(feature = "driver")]
+pub fn free_keys() {
+    let mut chunk = KEY_CHUNKS.swap(null_mut(), Ordering::SeqCst);
+    while !chunk.is_null() {
+        let (previous, capacity) = unsafe { ((*chunk).previous, (*chunk).capacity) };
+        unsafe { kernel::pool_free(chunk as _, size_of::<KeyChunk>() + capacity) };
+        chunk = previous;
+    }
+}
+
+/// Grt keys issued to a single run of a single test.
+#[derive(Debug)]
+pub struct GrtScope {
+    id: u64,
+    /// Every key issued, so asking again for a name returns the same string.
+    keys: RefCell<BTreeSet<&'static str>>,
+}
+
+impl GrtScope {
+    pub fn new() -> Self {
+        Self {
+            id: NEXT_SCOPE_ID.fetch_add(1, Ordering::Relaxed),
+            keys: RefCell::new(BTreeSet::new()),
+        }
+    }
+
+    /// The key for `name` in this scope, e.g. `t12::counter`. Asking twice for the same name
+    /// returns the same key.
+    pub fn key(&self, name: &str) -> &'static str {
+        let qualified = format!("t{}::{name}", self.id);
+        let mut keys = self.keys.borrow_mut();
+
+        if let Some(key) = keys.get(qualified.as_str()) {
+            return key;
+        }
+        let key = leak_key(&qualified);
+        keys.insert(key);
+        key
+    }
+
+    /// Remove everything registered under this scope, by destroying and re-initialising the Grt
+    /// if any key was issued.
+    ///
+    /// A test that left the Grt destroyed is not an error here; it is initialised again.
+    pub fn teardown(self) -> Result<(), GrtError> {
+        if self.keys.borrow().is_empty() {
+            return Ok(());
+        }
+
+        match unsafe { Grt::destroy() } {
+            Ok(()) | Err(GrtError::GrtIsNull) => {},
+            Err(e) => return Err(e),
+        }
+        Grt::init()
+    }
+
+    /// Leave everything registered under this scope in the Grt, for when threads that may still
+    /// be using it were abandoned, see [`crate::threads`].
+    pub fn abandon(self) {
+        drop(self);
+    }
+}
+
+impl Default for GrtScope {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,90 @@
+//! Host simulation of the kernel, enabled with the `host-sim` feature.
+//!
+//! Provides `std` backed stand-ins for the kernel services in [`crate::kernel`] and for the parts
+//! of `wdk_mutex` the tests use, so the whole suite can run under `cargo test` on a machine
+//! without the WDK. It exists to catch bugs in the harness and in test bodies before deploying to
+//! a VM; it says nothing about the correctness of `wdk_mutex` itself.
+
+pub(crate) mod services;
+pub(crate) mod wdk_mutex;
+pub mod irp;
+
+use alloc::vec::Vec;
+use std::sync::Once;
+use wdk_mutex_tests_protocol::{TestFilter, TestOutcome};
+
+use crate::{dispatch, log, registry, runner, threads};
+
+pub use crate::{config::{TestConfig, TimeoutPolicy, MAX_ROUNDS, MAX_THREADS}, lock_tracking::{lock as tracked_lock, LockId, Tracked}, registry::{Suite, TestCase, TestContext, TestError, TestResult}, threads::SystemThreadGroup, watchdog::{start as start_watchdog, stop as stop_watchdog}, workers::record_progress, log_ring::{Level, LogRecord, LogRing, HEADER_LEN as LOG_RECORD_HEADER_LEN}};
+pub use self::{irp::SimIrp, services::{fail_next_thread_reference, NTSTATUS, IRP_MJ_CLEANUP, IRP_MJ_CLOSE, IRP_MJ_CREATE, IRP_MJ_DEVICE_CONTROL, IRP_MJ_READ, STATUS_BUFFER_OVERFLOW, STATUS_BUFFER_TOO_SMALL, STATUS_INVALID_DEVICE_REQUEST, STATUS_INVALID_PARAMETER, STATUS_SUCCESS}};
+
+/// Run the registered tests selected by `filter` in-process with the parameters in `config`, the
+/// same way `IOCTL_RUN_TESTS` does in the driver, and return their outcomes.
+pub fn run_suite(config: &TestConfig, filter: &TestFilter) -> Vec<TestOutcome> {
+    run(config, registry::matching(filter))
+}
+
+/// Run `tests`, which need not be registered, as [`run_suite`] runs the registered ones. For
+/// exercising the runner itself with tests that should never be part of the driver.
+pub fn run_cases(config: &TestConfig, tests: &'static [TestCase]) -> Vec<TestOutcome> {
+    run(config, tests.iter())
+}
+
+fn run(config: &TestConfig, tests: impl Iterator<Item = &'static TestCase>) -> Vec<TestOutcome> {
+    let _run = runner::begin_run().expect("a host-sim run is already in progress");
+    install_log();
+
+    if let Err(e) = wdk_mutex::grt::Grt::init() {
+        panic!("Error creating Grt! {e:?}");
+    }
+
+    let report = runner::run_tests(tests, config);
+    report.print_summary();
+    runner::store_report(report);
+
+    // abandoned threads may still use the Grt, it is destroyed once they have been joined
+    if threads::abandoned() == 0 {
+        destroy_grt();
+    }
+
+    // the report stays stored until the next run, as in the driver, so it can be read back
+    // through dispatch_irp
+    runner::with_last_report(|report| {
+        report.map_or_else(Vec::new, |r| r.records.iter().map(|r| r.to_outcome()).collect())
+    })
+}
+
+fn destroy_grt() {
+    if let Err(e) = unsafe { wdk_mutex::grt::Grt::destroy() } {
+        panic!("Error destroying Grt: {e:?}");
+    }
+}
+
+/// Number of threads abandoned by timed out tests and still not joined.
+pub fn abandoned_threads() -> usize {
+    threads::abandoned()
+}
+
+/// Wait for the threads abandoned by timed out tests, then destroy the Grt a run left for them,
+/// as unloading the driver does. Returns how many threads there were.
+pub fn join_abandoned_threads() -> usize {
+    let joined = threads::join_abandoned();
+    if joined != 0 {
+        destroy_grt();
+    }
+    joined
+}
+
+/// Send `irp` to the device's dispatch routine, as the I/O manager would, and return the status
+/// it was completed with.
+pub fn dispatch_irp(irp: &mut SimIrp) -> NTSTATUS {
+    install_log();
+    dispatch::dispatch(irp)
+}
+
+/// Allocate the harness log once per process, as `DriverEntry` does at load, so it outlives every
+/// test's leak check.
+fn install_log() {
+    static INSTALLED: Once = Once::new();
+    INSTALLED.call_once(log::install);
+}
@@ -0,0 +1,97 @@
+//! A stand-in for the IRPs the I/O manager sends `\Device\WdkMutexTest`, so
+//! [`crate::dispatch`] can be driven from `cargo test`.
+
+use alloc::vec::Vec;
+
+use crate::{dispatch::Irp, kernel::{NTSTATUS, IRP_MJ_DEVICE_CONTROL, IRP_MJ_READ}};
+
+/// An IRP at the driver's stack location, with a buffered I/O system buffer.
+///
+/// Completing one twice panics, as would a double `IoCompleteRequest` in the kernel.
+#[derive(Debug)]
+pub struct SimIrp {
+    major_function: u8,
+    ioctl_code: u32,
+    input_len: usize,
+    output_len: usize,
+    /// Shared by input and output, sized for the larger of the two like the I/O manager's.
+    system_buffer: Vec<u8>,
+    completion: Option<(NTSTATUS, usize)>,
+}
+
+impl SimIrp {
+    /// A request without parameters, such as `IRP_MJ_CREATE`.
+    pub fn new(major_function: u8) -> Self {
+        Self {
+            major_function,
+            ioctl_code: 0,
+            input_len: 0,
+            output_len: 0,
+            system_buffer: Vec::new(),
+            completion: None,
+        }
+    }
+
+    /// `IRP_MJ_DEVICE_CONTROL` for `ioctl_code` with `input`, able to return `output_len` bytes.
+    pub fn device_control(ioctl_code: u32, input: &[u8], output_len: usize) -> Self {
+        let mut system_buffer = input.to_vec();
+        system_buffer.resize(input.len().max(output_len), 0);
+
+        Self {
+            ioctl_code,
+            input_len: input.len(),
+            output_len,
+            system_buffer,
+            ..Self::new(IRP_MJ_DEVICE_CONTROL)
+        }
+    }
+
+    /// `IRP_MJ_READ` of up to `len` bytes.
+    pub fn read(len: usize) -> Self {
+        Self {
+            output_len: len,
+            system_buffer: alloc::vec![0; len],
+            ..Self::new(IRP_MJ_READ)
+        }
+    }
+
+    /// The status the IRP was completed with, or `None` if it was not completed.
+    pub fn status(&self) -> Option<NTSTATUS> {
+        self.completion.map(|(status, _)| status)
+    }
+
+    /// The bytes returned to the caller, as given by `IoStatus.Information`.
+    pub fn output(&self) -> &[u8] {
+        let information = self.completion.map_or(0, |(_, information)| information);
+        &self.system_buffer[..information]
+    }
+}
+
+impl Irp for SimIrp {
+    fn major_function(&self) -> u8 {
+        self.major_function
+    }
+
+    fn ioctl_code(&self) -> u32 {
+        self.ioctl_code
+    }
+
+    fn input(&self) -> &[u8] {
+        &self.system_buffer[..self.input_len]
+    }
+
+    fn output_len(&self) -> usize {
+        self.output_len
+    }
+
+    fn write_output(&mut self, data: &[u8]) {
+        assert!(data.len() <= self.output_len, "{} bytes written to a {} byte buffer", data.len(), self.output_len);
+        self.system_buffer[..data.len()].copy_from_slice(data);
+    }
+
+    fn complete(&mut self, status: NTSTATUS, information: usize) {
+        assert!(self.completion.is_none(), "IRP completed twice");
+        assert!(information <= self.output_len, "{information} bytes returned from a {} byte buffer", self.output_len);
+        self.completion = Some((status, information));
+    }
+}
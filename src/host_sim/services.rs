@@ -0,0 +1,199 @@
+//! `std` implementations of [`crate::kernel`].
+//!
+//! A thread "handle" and a referenced thread "object" are both raw pointers to the same
+//! reference counted [`SimThread`]; `ObReferenceObjectByHandle` takes a reference, while
+//! `ZwClose` and `ObfDereferenceObject` each release one.
+
+use core::{cell::Cell, ffi::c_void, sync::atomic::{AtomicBool, Ordering}};
+use std::{alloc::{GlobalAlloc, Layout, System}, sync::{Arc, Condvar, Mutex, OnceLock}, thread::JoinHandle, time::{Duration, Instant}};
+
+use crate::kernel::StartRoutine;
+
+// named after the kernel types they stand in for
+#[allow(clippy::upper_case_acronyms)]
+pub type NTSTATUS = i32;
+#[allow(clippy::upper_case_acronyms)]
+pub type HANDLE = *mut c_void;
+#[allow(clippy::upper_case_acronyms)]
+pub type PVOID = *mut c_void;
+
+pub const STATUS_SUCCESS: NTSTATUS = 0;
+pub const STATUS_TIMEOUT: NTSTATUS = 0x0000_0102;
+pub const STATUS_BUFFER_OVERFLOW: NTSTATUS = 0x8000_0005_u32 as i32;
+pub const STATUS_DEVICE_BUSY: NTSTATUS = 0x8000_0011_u32 as i32;
+pub const STATUS_INVALID_PARAMETER: NTSTATUS = 0xC000_000D_u32 as i32;
+pub const STATUS_INVALID_DEVICE_REQUEST: NTSTATUS = 0xC000_0010_u32 as i32;
+pub const STATUS_BUFFER_TOO_SMALL: NTSTATUS = 0xC000_0023_u32 as i32;
+pub const STATUS_INVALID_DEVICE_STATE: NTSTATUS = 0xC000_0184_u32 as i32;
+const STATUS_INSUFFICIENT_RESOURCES: NTSTATUS = 0xC000_009A_u32 as i32;
+
+pub const IRP_MJ_CREATE: u8 = 0x00;
+pub const IRP_MJ_CLOSE: u8 = 0x02;
+pub const IRP_MJ_READ: u8 = 0x03;
+pub const IRP_MJ_DEVICE_CONTROL: u8 = 0x0e;
+pub const IRP_MJ_CLEANUP: u8 = 0x12;
+
+/// Alignment of pool allocations on x64.
+const POOL_ALIGN: usize = 16;
+
+const PASSIVE_LEVEL: u8 = 0;
+pub const APC_LEVEL: u8 = 1;
+pub const DISPATCH_LEVEL: u8 = 2;
+
+std::thread_local! {
+    /// Simulated IRQL of the current thread. Nothing is masked by raising it, it only drives the
+    /// IRQL checks in the simulated `wdk_mutex`.
+    static IRQL: Cell<u8> = const { Cell::new(PASSIVE_LEVEL) };
+}
+
+pub fn nt_success(status: NTSTATUS) -> bool {
+    status >= 0
+}
+
+/// The simulated thread object, signalled once the start routine returns.
+struct SimThread {
+    exited: Mutex<bool>,
+    signal: Condvar,
+    /// Joined by the first wait that sees the thread exit, so the thread's own teardown has
+    /// finished by the time any wait returns, as it has once a kernel thread object is signalled.
+    handle: Mutex<Option<JoinHandle<()>>>,
+}
+
+/// Raw pointers are not `Send`; the start context is handed over as an address instead, exactly
+/// as the kernel would.
+struct SendPtr(usize);
+
+pub unsafe fn ps_create_system_thread(
+    thread_handle: &mut HANDLE,
+    start_routine: StartRoutine,
+    start_context: PVOID,
+) -> NTSTATUS {
+    let thread = Arc::new(SimThread {
+        exited: Mutex::new(false),
+        signal: Condvar::new(),
+        handle: Mutex::new(None),
+    });
+
+    let worker = Arc::clone(&thread);
+    let context = SendPtr(start_context as usize);
+    let spawned = std::thread::Builder::new().spawn(move || {
+        let context = context;
+        unsafe { start_routine(context.0 as *mut c_void) };
+
+        *worker.exited.lock().unwrap() = true;
+        worker.signal.notify_all();
+    });
+
+    match spawned {
+        Ok(handle) => {
+            *thread.handle.lock().unwrap() = Some(handle);
+            *thread_handle = Arc::into_raw(thread) as HANDLE;
+            STATUS_SUCCESS
+        },
+        Err(_) => STATUS_INSUFFICIENT_RESOURCES,
+    }
+}
+
+/// Set by [`fail_next_thread_reference`].
+static FAIL_NEXT_REFERENCE: AtomicBool = AtomicBool::new(false);
+
+/// Make the next `ObReferenceObjectByHandle` fail, leaving the thread it was for running with
+/// only its handle.
+pub fn fail_next_thread_reference() {
+    FAIL_NEXT_REFERENCE.store(true, Ordering::SeqCst);
+}
+
+pub unsafe fn ob_reference_object_by_handle(handle: HANDLE, object: &mut PVOID) -> NTSTATUS {
+    if FAIL_NEXT_REFERENCE.swap(false, Ordering::SeqCst) {
+        return STATUS_INSUFFICIENT_RESOURCES;
+    }
+
+    unsafe { Arc::increment_strong_count(handle as *const SimThread) };
+    *object = handle;
+    STATUS_SUCCESS
+}
+
+pub unsafe fn ob_dereference_object(object: PVOID) {
+    drop(unsafe { Arc::from_raw(object as *const SimThread) });
+}
+
+pub unsafe fn zw_close(handle: HANDLE) -> NTSTATUS {
+    drop(unsafe { Arc::from_raw(handle as *const SimThread) });
+    STATUS_SUCCESS
+}
+
+pub unsafe fn ke_wait_for_single_object(object: PVOID, timeout_us: Option<u64>) -> NTSTATUS {
+    let thread = unsafe { &*(object as *const SimThread) };
+    let deadline = timeout_us.map(|us| Instant::now() + Duration::from_micros(us));
+
+    let mut exited = thread.exited.lock().unwrap();
+    while !*exited {
+        exited = match deadline {
+            None => thread.signal.wait(exited).unwrap(),
+            Some(deadline) => {
+                let now = Instant::now();
+                if now >= deadline {
+                    return STATUS_TIMEOUT;
+                }
+                thread.signal.wait_timeout(exited, deadline - now).unwrap().0
+            },
+        };
+    }
+    drop(exited);
+
+    if let Some(handle) = thread.handle.lock().unwrap().take() {
+        let _ = handle.join();
+    }
+    STATUS_SUCCESS
+}
+
+pub unsafe fn zw_wait_for_single_object(handle: HANDLE, timeout_us: Option<u64>) -> NTSTATUS {
+    unsafe { ke_wait_for_single_object(handle, timeout_us) }
+}
+
+pub fn ke_get_current_irql() -> u8 {
+    IRQL.get()
+}
+
+pub fn current_thread_id() -> usize {
+    // every thread has its own IRQL, so its address tells the threads apart
+    IRQL.with(|irql| irql as *const Cell<u8> as usize)
+}
+
+pub fn current_processor() -> u32 {
+    // std has no portable way to ask, every simulated thread reports the first processor
+    0
+}
+
+pub unsafe fn ke_raise_irql(new_irql: u8) -> u8 {
+    let old = IRQL.replace(new_irql);
+    assert!(old <= new_irql, "KeRaiseIrql to {new_irql} from {old}");
+    old
+}
+
+pub unsafe fn ke_lower_irql(new_irql: u8) {
+    let old = IRQL.replace(new_irql);
+    assert!(new_irql <= old, "KeLowerIrql to {new_irql} from {old}");
+}
+
+/// Taken from the system allocator directly, as in the kernel pool is not the global allocator's:
+/// [`crate::kernel::ex_allocate_pool2`] tracks it as pool, so it must not be counted as heap too.
+pub unsafe fn pool_alloc(size: usize, _tag: u32) -> PVOID {
+    match Layout::from_size_align(size, POOL_ALIGN) {
+        Ok(layout) => unsafe { System.alloc(layout) as PVOID },
+        Err(_) => core::ptr::null_mut(),
+    }
+}
+
+pub unsafe fn pool_free(p: PVOID, size: usize) {
+    unsafe { System.dealloc(p as *mut u8, Layout::from_size_align_unchecked(size, POOL_ALIGN)) };
+}
+
+pub fn perf_counter_us() -> u64 {
+    perf_counter_ns() / 1_000
+}
+
+pub fn perf_counter_ns() -> u64 {
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed().as_nanos() as u64
+}
@@ -0,0 +1,178 @@
+//! Stand-in for the parts of `wdk_mutex` used by the tests, backed by `std::sync::Mutex`.
+//!
+//! The module layout and signatures mirror the real crate so that test code importing
+//! `crate::wdk_mutex::...` compiles unchanged against either.
+
+pub mod errors {
+    // not every variant is produced by the simulation, they mirror the real crate
+    #![allow(dead_code)]
+
+    #[derive(Debug)]
+    pub enum DriverMutexError {
+        IrqlTooHigh,
+        PagedPoolAllocFailed,
+    }
+
+    #[derive(Debug)]
+    pub enum GrtError {
+        GrtAlreadyExists,
+        GrtIsNull,
+        GrtIsEmpty,
+        KeyExists,
+        KeyNotFound,
+        DowncastError,
+        DriverMutexError(DriverMutexError),
+    }
+}
+
+macro_rules! sim_mutex {
+    ($module:ident, $mutex:ident, $guard:ident) => {
+        pub mod $module {
+            use std::{ops::{Deref, DerefMut}, sync::{Mutex, MutexGuard}};
+
+            use super::errors::DriverMutexError;
+            use crate::host_sim::services::{ke_get_current_irql, APC_LEVEL, DISPATCH_LEVEL};
+
+            pub struct $mutex<T> {
+                inner: Mutex<T>,
+            }
+
+            pub struct $guard<'a, T> {
+                inner: MutexGuard<'a, T>,
+            }
+
+            // names and receivers match the real crate, not clippy's conventions
+            #[allow(clippy::wrong_self_convention)]
+            impl<T> $mutex<T> {
+                /// Fails above DISPATCH_LEVEL, where the real mutex cannot allocate its pool.
+                pub fn new(data: T) -> Result<Self, DriverMutexError> {
+                    if ke_get_current_irql() > DISPATCH_LEVEL {
+                        return Err(DriverMutexError::IrqlTooHigh);
+                    }
+                    Ok(Self { inner: Mutex::new(data) })
+                }
+
+                /// Fails above APC_LEVEL, where the real mutex cannot wait.
+                pub fn lock(&self) -> Result<$guard<'_, T>, DriverMutexError> {
+                    if ke_get_current_irql() > APC_LEVEL {
+                        return Err(DriverMutexError::IrqlTooHigh);
+                    }
+                    let inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+                    Ok($guard { inner })
+                }
+
+                /// # Safety
+                ///
+                /// Mirrors the real API; always safe in the simulation.
+                pub unsafe fn to_owned(self) -> T {
+                    self.inner.into_inner().unwrap_or_else(|e| e.into_inner())
+                }
+
+                /// # Safety
+                ///
+                /// Mirrors the real API; always safe in the simulation.
+                pub unsafe fn to_owned_box(self) -> Box<T> {
+                    Box::new(unsafe { self.to_owned() })
+                }
+            }
+
+            // the kernel mutexes hold raw pointers happily, mirror the real crate's bounds
+            unsafe impl<T> Send for $mutex<T> {}
+            unsafe impl<T> Sync for $mutex<T> {}
+
+            impl<T> Deref for $guard<'_, T> {
+                type Target = T;
+
+                fn deref(&self) -> &T {
+                    &self.inner
+                }
+            }
+
+            impl<T> DerefMut for $guard<'_, T> {
+                fn deref_mut(&mut self) -> &mut T {
+                    &mut self.inner
+                }
+            }
+        }
+    };
+}
+
+sim_mutex!(kmutex, KMutex, KMutexGuard);
+sim_mutex!(fast_mutex, FastMutex, FastMutexGuard);
+
+pub mod grt {
+    use std::{any::Any, collections::BTreeMap, sync::Mutex};
+
+    use super::{errors::GrtError, fast_mutex::FastMutex, kmutex::KMutex};
+
+    type Table = BTreeMap<&'static str, Box<dyn Any>>;
+
+    /// The registered mutexes, `None` until [`Grt::init`] and after [`Grt::destroy`].
+    struct Registry(Mutex<Option<Table>>);
+
+    // entries are only ever handed out as shared references to the (Sync) mutexes they hold
+    unsafe impl Sync for Registry {}
+
+    static GRT: Registry = Registry(Mutex::new(None));
+
+    /// Global registry of named mutexes.
+    pub struct Grt;
+
+    impl Grt {
+        pub fn init() -> Result<(), GrtError> {
+            let mut grt = GRT.0.lock().unwrap();
+            if grt.is_some() {
+                return Err(GrtError::GrtAlreadyExists);
+            }
+            *grt = Some(BTreeMap::new());
+            Ok(())
+        }
+
+        fn register(label: &'static str, entry: Box<dyn Any>) -> Result<(), GrtError> {
+            let mut grt = GRT.0.lock().unwrap();
+            let table = grt.as_mut().ok_or(GrtError::GrtIsNull)?;
+            if table.contains_key(label) {
+                return Err(GrtError::KeyExists);
+            }
+            table.insert(label, entry);
+            Ok(())
+        }
+
+        fn get<M: Any>(key: &'static str) -> Result<&'static M, GrtError> {
+            let grt = GRT.0.lock().unwrap();
+            let table = grt.as_ref().ok_or(GrtError::GrtIsNull)?;
+            let entry = table.get(key).ok_or(GrtError::KeyNotFound)?;
+            let m = entry.downcast_ref::<M>().ok_or(GrtError::DowncastError)?;
+
+            // boxed entries do not move and live until Grt::destroy, as with the real Grt
+            Ok(unsafe { &*(m as *const M) })
+        }
+
+        pub fn register_kmutex<T: Any>(label: &'static str, data: T) -> Result<(), GrtError> {
+            let m = KMutex::new(data).map_err(GrtError::DriverMutexError)?;
+            Self::register(label, Box::new(m))
+        }
+
+        pub fn register_fast_mutex<T: Any>(label: &'static str, data: T) -> Result<(), GrtError> {
+            let m = FastMutex::new(data).map_err(GrtError::DriverMutexError)?;
+            Self::register(label, Box::new(m))
+        }
+
+        pub fn get_kmutex<T: Any>(key: &'static str) -> Result<&'static KMutex<T>, GrtError> {
+            Self::get::<KMutex<T>>(key)
+        }
+
+        pub fn get_fast_mutex<T: Any>(key: &'static str) -> Result<&'static FastMutex<T>, GrtError> {
+            Self::get::<FastMutex<T>>(key)
+        }
+
+        /// # Safety
+        ///
+        /// No reference previously returned by a `get_*` call may be used afterwards.
+        pub unsafe fn destroy() -> Result<(), GrtError> {
+            let table = GRT.0.lock().unwrap().take().ok_or(GrtError::GrtIsNull)?;
+            drop(table);
+            Ok(())
+        }
+    }
+}
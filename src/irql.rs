@@ -0,0 +1,72 @@
+//! IRQL misuse tests for a [`LockPrimitive`].
+//!
+//! Driver code reaches for a mutex from DPCs and other raised IRQL paths by mistake. The
+//! primitives document a maximum IRQL for `new` and `lock` and promise `IrqlTooHigh` above it
+//! rather than a bugcheck. These tests raise the current IRQL, call the operation, lower it again,
+//! and compare the outcome with the documented limit, then check the mutex is still usable at
+//! PASSIVE_LEVEL.
+//!
+//! Nothing that allocates or prints runs while raised; outcomes are only inspected once lowered.
+
+use alloc::format;
+
+use crate::{conformance::LockPrimitive, kernel::{self, APC_LEVEL, DISPATCH_LEVEL}, registry::{TestContext, TestError, TestResult}, wdk_mutex::errors::DriverMutexError};
+
+/// The raised levels every operation is probed at.
+const PROBED_LEVELS: [u8; 2] = [APC_LEVEL, DISPATCH_LEVEL];
+
+/// Calls `new` at each probed level.
+pub fn new_at_raised_irql<P: LockPrimitive>(_: &TestContext) -> TestResult {
+    for irql in PROBED_LEVELS {
+        let raised = kernel::raise_irql(irql)
+            .ok_or_else(|| TestError::skip(format!("already above IRQL {irql}")))?;
+        let result = P::new(0u32).map(drop);
+        drop(raised);
+
+        check("new", irql, P::MAX_NEW_IRQL, result)?;
+    }
+
+    // the failed attempts left nothing behind that stops a mutex being made normally
+    let m = P::new(0u32)?;
+    *P::lock(&m)? += 1;
+
+    Ok(())
+}
+
+/// Calls `lock` at each probed level on a mutex created at PASSIVE_LEVEL.
+pub fn lock_at_raised_irql<P: LockPrimitive>(_: &TestContext) -> TestResult {
+    let m = P::new(0u32)?;
+    let mut acquired = 0;
+
+    for irql in PROBED_LEVELS {
+        let raised = kernel::raise_irql(irql)
+            .ok_or_else(|| TestError::skip(format!("already above IRQL {irql}")))?;
+        // the guard is released at the IRQL it was acquired at
+        let result = P::lock(&m).map(|mut guard| *guard += 1);
+        drop(raised);
+
+        if result.is_ok() {
+            acquired += 1;
+        }
+        check("lock", irql, P::MAX_LOCK_IRQL, result)?;
+    }
+
+    // a refused lock must not leave the mutex held, or this waits forever
+    let mut guard = P::lock(&m)?;
+    if *guard != acquired {
+        return Err(TestError::fail(format!("expected {acquired} increments while raised, got {}", *guard)));
+    }
+    *guard += 1;
+
+    Ok(())
+}
+
+/// Compare the outcome of `op` at `irql` with its documented limit of `max_irql`.
+fn check(op: &str, irql: u8, max_irql: u8, result: Result<(), DriverMutexError>) -> TestResult {
+    match (irql <= max_irql, result) {
+        (true, Ok(())) | (false, Err(DriverMutexError::IrqlTooHigh)) => Ok(()),
+        (true, Err(e)) => Err(TestError::fail(format!("{op} at IRQL {irql} failed with {e:?}, the limit is {max_irql}"))),
+        (false, Ok(())) => Err(TestError::fail(format!("{op} at IRQL {irql} succeeded, expected IrqlTooHigh above {max_irql}"))),
+        (false, Err(e)) => Err(TestError::fail(format!("{op} at IRQL {irql} returned {e:?}, expected IrqlTooHigh"))),
+    }
+}
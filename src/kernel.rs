@@ -0,0 +1,253 @@
+//! Kernel services used by the tests.
+//!
+//! Tests never call `wdk_sys` directly for threads, waits or pool memory; they go through the thin
+//! wrappers here instead. With the `driver` feature these forward straight to the real kernel
+//! APIs. With `host-sim` they are backed by `std` threads and the system allocator (see
+//! [`crate::host_sim`]) so the same test bodies can run under `cargo test` on a dev box.
+//!
+//! The wrappers keep the shape of the routines they stand in for, status codes and out-parameters
+//! included, so test code reads the same as it would against the raw API.
+
+use core::ffi::c_void;
+
+use crate::{alloc_tracking, fault};
+
+/// Entry point of a system thread, as passed to `PsCreateSystemThread`.
+pub type StartRoutine = unsafe extern "C" fn(*mut c_void);
+
+/// Pool tag used for every allocation made by the tests.
+pub const POOL_TAG: u32 = u32::from_be_bytes(*b"kmtx");
+
+#[cfg(feature = "driver")]
+pub use self::driver::*;
+
+/// The current thread's IRQL raised by [`raise_irql`], lowered back when dropped.
+///
+/// Drop anything acquired while raised before this, so it is released at the IRQL it was
+/// acquired at.
+pub struct RaisedIrql {
+    old: u8,
+}
+
+/// Raise the current IRQL to `new_irql`, or `None` if it is already above it, which
+/// `KeRaiseIrql` would bugcheck on.
+pub fn raise_irql(new_irql: u8) -> Option<RaisedIrql> {
+    if ke_get_current_irql() > new_irql {
+        return None;
+    }
+
+    let old = unsafe { ke_raise_irql(new_irql) };
+    Some(RaisedIrql { old })
+}
+
+impl Drop for RaisedIrql {
+    fn drop(&mut self) {
+        unsafe { ke_lower_irql(self.old) };
+    }
+}
+
+/// Bytes in front of every allocation made through [`ex_allocate_pool2`], recording its size and
+/// tag for [`ex_free_pool`]. A multiple of 16 keeps the alignment pool allocations come with.
+const POOL_HEADER: usize = 16;
+
+/// `ExAllocatePool2` from the non paged pool. Returns null on failure, including one injected by
+/// [`crate::fault`].
+///
+/// Allocations tagged [`POOL_TAG`] are counted by [`crate::alloc_tracking`].
+pub unsafe fn ex_allocate_pool2(size: usize, tag: u32) -> PVOID {
+    if fault::should_fail() {
+        return core::ptr::null_mut();
+    }
+
+    let base = unsafe { pool_alloc(size + POOL_HEADER, tag) } as *mut u8;
+    if base.is_null() {
+        return core::ptr::null_mut();
+    }
+
+    fault::record_alloc();
+    alloc_tracking::record_pool_alloc(tag, size);
+    unsafe {
+        (base as *mut usize).write(size);
+        (base.add(size_of::<usize>()) as *mut u32).write(tag);
+        base.add(POOL_HEADER) as PVOID
+    }
+}
+
+/// Free an allocation made by [`ex_allocate_pool2`].
+pub unsafe fn ex_free_pool(p: PVOID) {
+    let base = unsafe { (p as *mut u8).sub(POOL_HEADER) };
+    let (size, tag) = unsafe { ((base as *mut usize).read(), (base.add(size_of::<usize>()) as *mut u32).read()) };
+
+    fault::record_free();
+    alloc_tracking::record_pool_free(tag, size);
+    unsafe { pool_free(base as PVOID, size + POOL_HEADER) };
+}
+
+#[cfg(feature = "host-sim")]
+pub use crate::host_sim::services::*;
+
+#[cfg(feature = "driver")]
+mod driver {
+    use core::ptr::null_mut;
+
+    use wdk_sys::{ntddk::{ExAllocatePool2, ExFreePool, KeGetCurrentIrql, KeLowerIrql, KeQueryPerformanceCounter, KfRaiseIrql, KeWaitForSingleObject, ObReferenceObjectByHandle, ObfDereferenceObject, KeGetCurrentProcessorNumberEx, PsCreateSystemThread, PsGetCurrentThreadId, ZwClose, ZwWaitForSingleObject}, CLIENT_ID, FALSE, LARGE_INTEGER, OBJECT_ATTRIBUTES, OBJ_KERNEL_HANDLE, POOL_FLAG_NON_PAGED, THREAD_ALL_ACCESS, _KWAIT_REASON::Executive, _MODE::KernelMode};
+
+    use super::StartRoutine;
+
+    pub use wdk::nt_success;
+    pub use wdk_sys::{HANDLE, NTSTATUS, PVOID, STATUS_BUFFER_OVERFLOW, STATUS_BUFFER_TOO_SMALL, STATUS_DEVICE_BUSY, STATUS_INVALID_DEVICE_REQUEST, STATUS_INVALID_DEVICE_STATE, STATUS_INVALID_PARAMETER, STATUS_SUCCESS, STATUS_TIMEOUT, STATUS_UNSUCCESSFUL};
+
+    pub const APC_LEVEL: u8 = wdk_sys::APC_LEVEL as u8;
+    pub const DISPATCH_LEVEL: u8 = wdk_sys::DISPATCH_LEVEL as u8;
+
+    // as found in IO_STACK_LOCATION::MajorFunction
+    pub const IRP_MJ_CREATE: u8 = wdk_sys::IRP_MJ_CREATE as u8;
+    pub const IRP_MJ_CLOSE: u8 = wdk_sys::IRP_MJ_CLOSE as u8;
+    pub const IRP_MJ_READ: u8 = wdk_sys::IRP_MJ_READ as u8;
+    pub const IRP_MJ_DEVICE_CONTROL: u8 = wdk_sys::IRP_MJ_DEVICE_CONTROL as u8;
+    pub const IRP_MJ_CLEANUP: u8 = wdk_sys::IRP_MJ_CLEANUP as u8;
+
+    /// `PsCreateSystemThread` in the system process, returning a kernel handle, valid whichever
+    /// process it is later used or closed in.
+    pub unsafe fn ps_create_system_thread(
+        thread_handle: &mut HANDLE,
+        start_routine: StartRoutine,
+        start_context: PVOID,
+    ) -> NTSTATUS {
+        let mut attributes = OBJECT_ATTRIBUTES {
+            Length: size_of::<OBJECT_ATTRIBUTES>() as u32,
+            RootDirectory: null_mut(),
+            ObjectName: null_mut(),
+            Attributes: OBJ_KERNEL_HANDLE,
+            SecurityDescriptor: null_mut(),
+            SecurityQualityOfService: null_mut(),
+        };
+
+        unsafe {
+            PsCreateSystemThread(
+                thread_handle,
+                0,
+                &mut attributes,
+                null_mut(),
+                null_mut::<CLIENT_ID>(),
+                Some(start_routine),
+                start_context,
+            )
+        }
+    }
+
+    /// `ObReferenceObjectByHandle` for a thread handle from kernel mode.
+    pub unsafe fn ob_reference_object_by_handle(handle: HANDLE, object: &mut PVOID) -> NTSTATUS {
+        unsafe {
+            ObReferenceObjectByHandle(
+                handle,
+                THREAD_ALL_ACCESS,
+                null_mut(),
+                KernelMode as i8,
+                object,
+                null_mut(),
+            )
+        }
+    }
+
+    pub unsafe fn ob_dereference_object(object: PVOID) {
+        unsafe { ObfDereferenceObject(object) };
+    }
+
+    pub unsafe fn zw_close(handle: HANDLE) -> NTSTATUS {
+        unsafe { ZwClose(handle) }
+    }
+
+    /// Non-alertable kernel mode `KeWaitForSingleObject`, giving up with `STATUS_TIMEOUT` after
+    /// `timeout_us` microseconds, or never with `None`.
+    pub unsafe fn ke_wait_for_single_object(object: PVOID, timeout_us: Option<u64>) -> NTSTATUS {
+        let mut timeout = LARGE_INTEGER::default();
+        let timeout = relative_timeout(&mut timeout, timeout_us);
+
+        unsafe {
+            KeWaitForSingleObject(
+                object,
+                Executive,
+                KernelMode as i8,
+                FALSE as u8,
+                timeout,
+            )
+        }
+    }
+
+    /// Non-alertable `ZwWaitForSingleObject` on a kernel handle, with the timeout of
+    /// [`ke_wait_for_single_object`].
+    pub unsafe fn zw_wait_for_single_object(handle: HANDLE, timeout_us: Option<u64>) -> NTSTATUS {
+        let mut timeout = LARGE_INTEGER::default();
+        let timeout = relative_timeout(&mut timeout, timeout_us);
+
+        unsafe { ZwWaitForSingleObject(handle, FALSE as u8, timeout) }
+    }
+
+    /// Point at `timeout` set to `timeout_us` microseconds from now, or null for no timeout.
+    fn relative_timeout(timeout: &mut LARGE_INTEGER, timeout_us: Option<u64>) -> *mut LARGE_INTEGER {
+        // relative timeouts are negative, in 100 ns units
+        match timeout_us {
+            Some(us) => {
+                timeout.QuadPart = -((us.min(i64::MAX as u64 / 10) * 10) as i64);
+                timeout
+            },
+            None => null_mut(),
+        }
+    }
+
+    pub fn ke_get_current_irql() -> u8 {
+        unsafe { KeGetCurrentIrql() }
+    }
+
+    /// `KeRaiseIrql`, returning the previous IRQL. Prefer [`super::raise_irql`].
+    pub unsafe fn ke_raise_irql(new_irql: u8) -> u8 {
+        unsafe { KfRaiseIrql(new_irql) }
+    }
+
+    pub unsafe fn ke_lower_irql(new_irql: u8) {
+        unsafe { KeLowerIrql(new_irql) };
+    }
+
+    /// `ExAllocatePool2` from the non paged pool, without the bookkeeping of
+    /// [`super::ex_allocate_pool2`].
+    pub unsafe fn pool_alloc(size: usize, tag: u32) -> PVOID {
+        unsafe { ExAllocatePool2(POOL_FLAG_NON_PAGED, size as u64, tag) }
+    }
+
+    /// Free an allocation from [`pool_alloc`] of `size` bytes.
+    pub unsafe fn pool_free(p: PVOID, _size: usize) {
+        unsafe { ExFreePool(p) };
+    }
+
+    /// Id of the current thread, unique among running threads.
+    pub fn current_thread_id() -> usize {
+        unsafe { PsGetCurrentThreadId() as usize }
+    }
+
+    /// Number of the processor the current thread is running on, across all processor groups.
+    pub fn current_processor() -> u32 {
+        unsafe { KeGetCurrentProcessorNumberEx(null_mut()) }
+    }
+
+    /// Microseconds elapsed since boot according to the performance counter.
+    pub fn perf_counter_us() -> u64 {
+        perf_counter_ns() / 1_000
+    }
+
+    /// Nanoseconds elapsed since boot according to the performance counter, for timing operations
+    /// too short for [`perf_counter_us`]. The resolution is that of the counter, typically 100 ns.
+    pub fn perf_counter_ns() -> u64 {
+        let mut freq = LARGE_INTEGER::default();
+        let counter = unsafe { KeQueryPerformanceCounter(&mut freq) };
+        let (counter, freq) = unsafe { (counter.QuadPart, freq.QuadPart) };
+        if freq <= 0 {
+            return 0;
+        }
+
+        // split to avoid overflowing the multiplication on machines with a fast counter
+        let secs = counter / freq;
+        let rem = counter % freq;
+        (secs * 1_000_000_000 + rem * 1_000_000_000 / freq) as u64
+    }
+}
@@ -1,133 +1,200 @@
 //! Crate for testing the wdk-mutex crate found at:
 //! https://github.com/0xflux/wdk-mutex
+//!
+//! Built with the default `driver` feature this is the test driver itself. Built with
+//! `--no-default-features --features host-sim` the same tests run in-process against `std` stand-ins
+//! for the kernel, see [`host_sim`].
 
-#![no_std]
+#![cfg_attr(not(feature = "host-sim"), no_std)]
 extern crate alloc;
 
-#[cfg(not(test))]
+#[cfg(all(feature = "driver", feature = "host-sim"))]
+compile_error!("`driver` and `host-sim` are mutually exclusive, build host-sim with `--no-default-features`");
+
+#[cfg(all(feature = "driver", not(test)))]
 extern crate wdk_panic;
 
+#[cfg(feature = "driver")]
 use core::ptr::null_mut;
 
-use alloc::boxed::Box;
-use test_fast_mutex::{FastMutexTest, HEAP_FMTX_PTR, PTR_TO_MANUAL_POOL_FM};
-use test_kmutex::{KMutexTest, HEAP_MTX_PTR, PTR_TO_MANUAL_POOL};
+use alloc_tracking::TrackingAllocator;
+#[cfg(feature = "driver")]
+use runner::ResultPolicy;
+#[cfg(feature = "driver")]
+use teardown::Teardown;
+#[cfg(feature = "driver")]
 use utils::ToU16Vec;
-use wdk::{nt_success, println};
+#[cfg(feature = "driver")]
+use wdk::nt_success;
+#[cfg(feature = "driver")]
 use wdk_alloc::WdkAllocator;
+#[cfg(feature = "driver")]
 use wdk_mutex::grt::Grt;
-use wdk_sys::{ntddk::{IoCreateDevice, IoCreateSymbolicLink, IoDeleteDevice, IoDeleteSymbolicLink, IofCompleteRequest, RtlInitUnicodeString}, DEVICE_OBJECT, DO_BUFFERED_IO, DRIVER_OBJECT, FILE_DEVICE_SECURE_OPEN, FILE_DEVICE_UNKNOWN, IO_NO_INCREMENT, IRP_MJ_CREATE, NTSTATUS, PCUNICODE_STRING, PDEVICE_OBJECT, PIRP, PUNICODE_STRING, STATUS_SUCCESS, STATUS_UNSUCCESSFUL, UNICODE_STRING};
-
+#[cfg(feature = "driver")]
+use wdk_sys::{ntddk::{IoCreateDevice, IoCreateSymbolicLink, IoDeleteDevice, IoDeleteSymbolicLink, RtlInitUnicodeString}, DO_BUFFERED_IO, DRIVER_OBJECT, FILE_DEVICE_SECURE_OPEN, FILE_DEVICE_UNKNOWN, NTSTATUS, PCUNICODE_STRING, PDEVICE_OBJECT, PUNICODE_STRING, STATUS_DEVICE_BUSY, STATUS_UNSUCCESSFUL, UNICODE_STRING};
+
+// Tests reach the mutexes under test through `crate::wdk_mutex` and print through `crate::println`,
+// so they compile unchanged against the real crates or the host simulation. What they print is
+// also kept in the log user mode reads back, see the log module.
+#[cfg(feature = "driver")]
+use ::wdk_mutex;
+#[cfg(feature = "host-sim")]
+use host_sim::wdk_mutex;
+pub(crate) use log::println;
+
+#[cfg(feature = "driver")]
 mod utils;
+mod kernel;
+mod fault;
+mod alloc_tracking;
+mod log_ring;
+mod log;
+mod config;
+mod registry;
+mod grt_scope;
+mod runner;
+mod conformance;
+mod soak;
+mod grt_churn;
+mod irql;
+mod alloc_failure;
+mod threads;
+mod workers;
+mod lock_tracking;
+mod lock_order;
+mod watchdog;
+mod control;
+mod dispatch;
+#[cfg(feature = "driver")]
+mod teardown;
+#[cfg(feature = "host-sim")]
+pub mod host_sim;
 mod test_kmutex;
 mod test_fast_mutex;
+mod test_grt;
+mod test_grt_lifecycle;
+mod test_lock_order;
+mod bench_grt;
 
+#[cfg(feature = "driver")]
 #[global_allocator]
-static GLOBAL_ALLOCATOR: WdkAllocator = WdkAllocator;
+static GLOBAL_ALLOCATOR: TrackingAllocator<WdkAllocator> = TrackingAllocator(WdkAllocator);
+#[cfg(feature = "host-sim")]
+#[global_allocator]
+static GLOBAL_ALLOCATOR: TrackingAllocator<std::alloc::System> = TrackingAllocator(std::alloc::System);
+
+/// Whether a failing run should fail the driver load, or keep it loaded for inspection. Staying
+/// loaded keeps `\\.\WdkMutexTest` available so results can be fetched and tests re-run.
+#[cfg(feature = "driver")]
+const RESULT_POLICY: ResultPolicy = ResultPolicy::StayLoaded;
 
+/// Name of the symbolic link user mode opens the control device through.
+#[cfg(feature = "driver")]
+const DOS_NAME: &str = "\\??\\WdkMutexTest";
+
+#[cfg(feature = "driver")]
 #[unsafe(export_name = "DriverEntry")]
 pub unsafe extern "system" fn driver_entry(
     driver: &mut DRIVER_OBJECT,
     registry_path: PCUNICODE_STRING,
 ) -> NTSTATUS {
     //
-    // Do basic driver initialisation
+    // Do basic driver initialisation, anything done is undone if a later step fails
     //
     println!("[wdk-mutex-test] [i] Starting wdk-mutex-test");
 
-    let status = unsafe { configure_driver(driver, registry_path as *mut _) };
-    if !nt_success(status) {
-        return status;
-    }
+    let mut teardown = match unsafe { configure_driver(driver, registry_path as *mut _) } {
+        Ok(teardown) => teardown,
+        Err(status) => return status,
+    };
 
-
-    //
-    // Run Kmutex tests
-    //
-
-    if KMutexTest::test_multithread_mutex_global_static() == false {
-        println!("[wdk-mutex-test] [-] Test test_multithread_mutex_global_static failed.");
-        return STATUS_UNSUCCESSFUL;
-    }
-
-    if KMutexTest::test_multithread_mutex_global_static_manual_pool() == false {
-        println!("[wdk-mutex-test] [-] Test test_multithread_mutex_global_static_manual_pool failed.");
-        return STATUS_UNSUCCESSFUL;
-    }
-
-    if KMutexTest::test_to_owned() == false {
-        println!("[wdk-mutex-test] [-] Test test_to_owned failed.");
-        return STATUS_UNSUCCESSFUL;
-    }
-
-    if KMutexTest::test_to_owned_box() == false {
-        println!("[wdk-mutex-test] [-] Test test_to_owned_box failed.");
-        return STATUS_UNSUCCESSFUL;
-    }
-
-    if KMutexTest::test_grt_thrice().is_err() {
-        println!("[wdk-mutex-test] [-] Test KMutexTest::test_grt_thrice failed.");
-        return STATUS_UNSUCCESSFUL;
-    }
+    // threads a timed out test left running, waited for before anything they may use is undone
+    teardown.push("the abandoned threads", || {
+        threads::join_abandoned();
+    });
 
 
     //
-    // Run FastMutex tests
+    // Run the registered tests selected by the Parameters key, see the registry and config
+    // modules, and report on all of them.
     //
 
-    if FastMutexTest::test_multithread_mutex_global_static() == false {
-        println!("[wdk-mutex-test] [-] Test test_multithread_mutex_global_static failed.");
-        return STATUS_UNSUCCESSFUL;
-    }
-
-    if FastMutexTest::test_multithread_mutex_global_static_manual_pool() == false {
-        println!("[wdk-mutex-test] [-] Test test_multithread_mutex_global_static_manual_pool failed.");
-        return STATUS_UNSUCCESSFUL;
-    }
+    let config = config::current();
+    // the device already exists, so a run requested through it could otherwise overlap this one
+    let Some(run) = runner::begin_run() else {
+        println!("[wdk-mutex-test] [-] A run requested through the device is already in progress.");
+        return STATUS_DEVICE_BUSY;
+    };
+    let report = runner::run_tests(registry::matching(&config.filter()), config);
+    drop(run);
+    report.print_summary();
 
-    if FastMutexTest::test_to_owned() == false {
-        println!("[wdk-mutex-test] [-] Test test_to_owned failed.");
-        return STATUS_UNSUCCESSFUL;
+    let status = report.status(RESULT_POLICY);
+    if report.failed() == 0 {
+        println!("[wdk-mutex-test] [+] All tests passed! NTSTATUS: {}", status);
+    } else {
+        println!("[wdk-mutex-test] [-] {} test(s) failed. NTSTATUS: {}", report.failed(), status);
     }
 
-    if FastMutexTest::test_to_owned_box() == false {
-        println!("[wdk-mutex-test] [-] Test test_to_owned_box failed.");
-        return STATUS_UNSUCCESSFUL;
+    // a failed load drops the teardown here, DriverUnload will not be called
+    if nt_success(status) {
+        runner::store_report(report);
+        teardown.push("the stored report", runner::free_last_report);
+        teardown::install(teardown);
     }
 
-    if FastMutexTest::test_grt_thrice().is_err() {
-        println!("[wdk-mutex-test] [-] Test FastMutexTest::test_grt_thrice failed.");
-        return STATUS_UNSUCCESSFUL;
-    }
-
-    println!("[wdk-mutex-test] [+] All tests passed! NTSTATUS: {}", status);
     status
 }
 
-/// Configuration of the driver
-pub unsafe extern "C" fn configure_driver(
+/// Configuration of the driver, returning the actions that undo it.
+///
+/// # Safety
+///
+/// `driver` and `registry_path` are the arguments `DriverEntry` was called with.
+#[cfg(feature = "driver")]
+unsafe fn configure_driver(
     driver: *mut DRIVER_OBJECT,
-    _registry_path: PUNICODE_STRING,
-) -> NTSTATUS {
+    registry_path: PUNICODE_STRING,
+) -> Result<Teardown, NTSTATUS> {
+    let mut teardown = Teardown::new();
 
-    // GRT
+    // the log, first so every later message is kept
+    log::install();
+    teardown.push("the log", log::free);
+
+    // test parameters, before the device exists so every run sees the same values
+    config::install(config::read_parameters(registry_path));
+    teardown.push("the test parameters", config::free);
+
+    // GRT, its keys freed only once the registry itself is gone
+    teardown.push("the Grt keys", grt_scope::free_keys);
     if let Err(e) = Grt::init() {
         println!("Error creating Grt! {:?}", e);
-        return STATUS_UNSUCCESSFUL;
+        return Err(STATUS_UNSUCCESSFUL);
     }
+    teardown.push("the Grt", || {
+        if let Err(e) = unsafe { Grt::destroy() } {
+            println!("Error destroying Grt: {:?}", e);
+        }
+    });
 
 
     let mut dos_name = UNICODE_STRING::default();
     let mut nt_name = UNICODE_STRING::default();
 
-    let dos_name_u16 = "\\??\\WdkMutexTest".to_u16_vec();
+    let dos_name_u16 = DOS_NAME.to_u16_vec();
     let device_name_u16 = "\\Device\\WdkMutexTest".to_u16_vec();
     
     unsafe { RtlInitUnicodeString(&mut dos_name, dos_name_u16.as_ptr()) };
     unsafe { RtlInitUnicodeString(&mut nt_name, device_name_u16.as_ptr()) };
 
-    (unsafe { *driver }).MajorFunction[IRP_MJ_CREATE as usize] = Some(create_close);
-    (unsafe { *driver }).DriverUnload = Some(driver_exit);
+    unsafe {
+        // unsupported major functions are failed by dispatch rather than left to the I/O manager
+        for major in &mut (*driver).MajorFunction {
+            *major = Some(dispatch::dispatch_irp);
+        }
+        (*driver).DriverUnload = Some(driver_exit);
+    }
 
     let mut device_object: PDEVICE_OBJECT = null_mut();
     let res = unsafe { IoCreateDevice(
@@ -141,73 +208,46 @@ pub unsafe extern "C" fn configure_driver(
     ) };
     if !nt_success(res) {
         println!("[wdk-mutex-test] [-] Unable to create device via IoCreateDevice. Failed with code: {res}.");
-        return res;
+        return Err(res);
+    }
+    teardown.push("the device", move || unsafe { IoDeleteDevice(device_object) });
+
+    // the watchdog, once the device its work item belongs to exists; it only reports, so the
+    // tests still run without it
+    match unsafe { watchdog::start(device_object, config::current().watchdog_seconds) } {
+        Ok(()) => teardown.push("the watchdog", watchdog::stop),
+        Err(res) => println!("[wdk-mutex-test] [-] Unable to start the watchdog, running without it. Error: {res}"),
     }
     
 
     let res = unsafe { IoCreateSymbolicLink(&mut dos_name, &mut nt_name) };
     if res != 0 {
         println!("[wdk-mutex-test] [-] Failed to create driver symbolic link. Error: {res}");
-        return res;
+        return Err(res);
     }
+    teardown.push("the symbolic link", || {
+        let mut dos_name = UNICODE_STRING::default();
+        let dos_name_u16 = DOS_NAME.to_u16_vec();
+        unsafe {
+            RtlInitUnicodeString(&mut dos_name, dos_name_u16.as_ptr());
+        }
+        let _ = unsafe { IoDeleteSymbolicLink(&mut dos_name) };
+    });
 
     //
     // Driver loaded and configured, now we can run tests outlined in the tests module.
     //
 
 
-    (unsafe { *device_object }).Flags |= DO_BUFFERED_IO;
+    unsafe { (*device_object).Flags |= DO_BUFFERED_IO };
 
-    STATUS_SUCCESS
+    Ok(teardown)
 }
 
-/// Driver exit callback
-extern "C" fn driver_exit(driver: *mut DRIVER_OBJECT) {
-
-    // rm symbolic link
-    let mut dos_name = UNICODE_STRING::default();
-    let dos_name_u16 = "\\??\\WdkMutexTest".to_u16_vec();
-    unsafe {
-        RtlInitUnicodeString(&mut dos_name, dos_name_u16.as_ptr());
-    }
-    let _ = unsafe { IoDeleteSymbolicLink(&mut dos_name) };
-
-    //
-    // Clear up memory via RAII & Box
-    //
-    let p = HEAP_MTX_PTR.load(core::sync::atomic::Ordering::SeqCst);
-    if !p.is_null() {
-        let _ = unsafe { Box::from_raw(p) };
-    }
-
-    let p = PTR_TO_MANUAL_POOL.load(core::sync::atomic::Ordering::SeqCst);
-    if !p.is_null() {
-        let _ = unsafe { Box::from_raw(p) };
-    }
-
-    let p = HEAP_FMTX_PTR.load(core::sync::atomic::Ordering::SeqCst);
-    if !p.is_null() {
-        let _ = unsafe { Box::from_raw(p) };
-    }
-
-    let p = PTR_TO_MANUAL_POOL_FM.load(core::sync::atomic::Ordering::SeqCst);
-    if !p.is_null() {
-        let _ = unsafe { Box::from_raw(p) };
-    }
-
-    if let Err(e) = unsafe { Grt::destroy() } {
-        println!("Error destroying Grt: {:?}", e);
-    }
-
-    // delete the device
-    unsafe { IoDeleteDevice((*driver).DeviceObject);}
+/// Driver exit callback, undoes everything `DriverEntry` set up.
+#[cfg(feature = "driver")]
+extern "C" fn driver_exit(_driver: *mut DRIVER_OBJECT) {
+    teardown::run_installed();
 
     println!("[wdk-mutex-test] [+] Driver unloaded.");
 }
-
-unsafe extern "C" fn create_close(_device: *mut DEVICE_OBJECT, pirp: PIRP) -> NTSTATUS {
-    (unsafe { *pirp }).IoStatus.__bindgen_anon_1.Status = STATUS_SUCCESS;
-    (unsafe { *pirp }).IoStatus.Information = 0;
-    unsafe { IofCompleteRequest(pirp, IO_NO_INCREMENT as i8) };
-    STATUS_SUCCESS
-}
\ No newline at end of file
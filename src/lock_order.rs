@@ -0,0 +1,260 @@
+//! Lock-order checking of the mutexes tests lock from their threads, in the manner of lockdep.
+//!
+//! Product drivers nest `KMutex` and `FastMutex`, and two code paths that nest the same pair in
+//! opposite orders can deadlock, though most runs never hit the window. So instead of waiting for
+//! the deadlock, each lock taken through [`crate::lock_tracking::lock`] by a group thread adds an
+//! ordering to a graph: every mutex the thread already holds, see [`crate::workers`], comes before
+//! the one it is about to take. A new ordering that closes a cycle in the graph, `a` before `b`
+//! taken here while `b` before `a` was seen earlier by any thread, possibly through other mutexes,
+//! is an inversion. It is printed the moment it is observed, before the lock is even attempted,
+//! and fails the running test, see [`crate::runner::run_test`].
+//!
+//! Mutexes are identified as by [`crate::lock_tracking`], by Grt key or address, and the graph only
+//! lives for one test: it is cleared before each test starts, as addresses are reused and keys are
+//! freed once a test ends. Locks taken on threads that do not belong to a group, and mutexes held
+//! beyond [`MAX_HELD`](crate::workers::MAX_HELD), are not checked. The graph is a fixed table, so
+//! checking never allocates; orderings past [`MAX_ORDERINGS`] in one test are not checked either.
+
+use core::{cell::UnsafeCell, fmt, hint::spin_loop, sync::atomic::{AtomicBool, Ordering}};
+
+use crate::{kernel::{self, DISPATCH_LEVEL}, lock_tracking::LockId, println, workers};
+
+/// Most distinct orderings recorded in one test.
+pub const MAX_ORDERINGS: usize = 128;
+
+/// `before` was held by thread `thread_id` while it took `after`.
+#[derive(Clone, Copy)]
+struct Edge {
+    before: LockId,
+    after: LockId,
+    thread_id: usize,
+    /// Closed a cycle when it was seen. Kept so it is only reported once, but never followed, so
+    /// the rest of the graph stays acyclic.
+    inverted: bool,
+}
+
+/// The first inversion seen in a test.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Inversion {
+    /// Thread that took `taking` while holding `holding`.
+    pub thread_id: usize,
+    pub taking: LockId,
+    pub holding: LockId,
+    /// Thread that recorded the first ordering on the path from `taking` to `holding`, which for
+    /// an ABBA inversion is the one that took `holding` while holding `taking`.
+    pub earlier_thread_id: usize,
+    /// Other mutexes on that path, 0 for an ABBA inversion.
+    pub through: usize,
+}
+
+impl fmt::Display for Inversion {
+    /// e.g. ``thread 0x1a2c took `t3::b` while holding `t3::a`, but thread 0x1a30 took `t3::a`
+    /// while holding `t3::b` ``
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "thread {:#x} took {} while holding {}, but ", self.thread_id, self.taking, self.holding)?;
+        if self.through == 0 {
+            write!(f, "thread {:#x} took {} while holding {}", self.earlier_thread_id, self.holding, self.taking)
+        } else {
+            write!(f, "{} was taken before {} through {} other mutex(es)", self.taking, self.holding, self.through)
+        }
+    }
+}
+
+/// The inversions seen in a test, see [`take`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Report {
+    pub first: Inversion,
+    /// Distinct inversions, `first` included.
+    pub count: u32,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.first)?;
+        if self.count > 1 {
+            write!(f, ", and {} more", self.count - 1)?;
+        }
+        Ok(())
+    }
+}
+
+struct Graph {
+    edges: [Option<Edge>; MAX_ORDERINGS],
+    len: usize,
+    /// Set once an ordering did not fit.
+    full: bool,
+    first: Option<Inversion>,
+    count: u32,
+    /// Scratch space of the search in [`Graph::path`], kept here rather than on the stack.
+    queue: [usize; MAX_ORDERINGS],
+    parent: [usize; MAX_ORDERINGS],
+    seen: [bool; MAX_ORDERINGS],
+}
+
+/// What [`Graph::add`] made of an ordering.
+enum Added {
+    New,
+    /// Seen before, or not recorded as the graph is full.
+    Known,
+    Inverted(Inversion),
+    Full,
+}
+
+impl Graph {
+    fn edges(&self) -> impl Iterator<Item = &Edge> {
+        self.edges[..self.len].iter().flatten()
+    }
+
+    /// The shortest path of orderings from `from` to `to`, as the first edge on it and the number
+    /// of mutexes in between.
+    fn path(&mut self, from: LockId, to: LockId) -> Option<(usize, usize)> {
+        self.seen = [false; MAX_ORDERINGS];
+        let (mut head, mut tail) = (0, 0);
+        for i in 0..self.len {
+            if let Some(e) = self.edges[i].filter(|e| !e.inverted && e.before == from) {
+                if e.after == to {
+                    return Some((i, 0));
+                }
+                self.seen[i] = true;
+                self.parent[i] = i;
+                self.queue[tail] = i;
+                tail += 1;
+            }
+        }
+
+        while head < tail {
+            let at = self.queue[head];
+            head += 1;
+            let node = self.edges[at].unwrap().after;
+
+            for i in 0..self.len {
+                let Some(e) = self.edges[i] else { continue };
+                if self.seen[i] || e.inverted || e.before != node {
+                    continue;
+                }
+                self.seen[i] = true;
+                self.parent[i] = at;
+                if e.after == to {
+                    // walk back to the edge out of from, counting the mutexes passed
+                    let (mut first, mut through) = (at, 1);
+                    while self.parent[first] != first {
+                        first = self.parent[first];
+                        through += 1;
+                    }
+                    return Some((first, through));
+                }
+                self.queue[tail] = i;
+                tail += 1;
+            }
+        }
+        None
+    }
+
+    /// Record that thread `thread_id` took `after` while holding `before`.
+    fn add(&mut self, before: LockId, after: LockId, thread_id: usize) -> Added {
+        if self.edges().any(|e| e.before == before && e.after == after) {
+            return Added::Known;
+        }
+        if self.len == MAX_ORDERINGS {
+            // only reported the first time
+            let first = !self.full;
+            self.full = true;
+            return if first { Added::Full } else { Added::Known };
+        }
+
+        let inversion = self.path(after, before).map(|(first, through)| Inversion {
+            thread_id,
+            taking: after,
+            holding: before,
+            earlier_thread_id: self.edges[first].unwrap().thread_id,
+            through,
+        });
+
+        self.edges[self.len] = Some(Edge { before, after, thread_id, inverted: inversion.is_some() });
+        self.len += 1;
+
+        match inversion {
+            Some(inversion) => {
+                self.first.get_or_insert(inversion);
+                self.count += 1;
+                Added::Inverted(inversion)
+            },
+            None => Added::New,
+        }
+    }
+}
+
+/// Serialises access to [`GRAPH`]. Held at `DISPATCH_LEVEL`, as the worker slots' locks are, and
+/// independent of the mutexes under test.
+static GRAPH_LOCK: AtomicBool = AtomicBool::new(false);
+
+struct GraphCell(UnsafeCell<Graph>);
+
+// only reached under GRAPH_LOCK
+unsafe impl Sync for GraphCell {}
+
+static GRAPH: GraphCell = GraphCell(UnsafeCell::new(Graph {
+    edges: [None; MAX_ORDERINGS],
+    len: 0,
+    full: false,
+    first: None,
+    count: 0,
+    queue: [0; MAX_ORDERINGS],
+    parent: [0; MAX_ORDERINGS],
+    seen: [false; MAX_ORDERINGS],
+}));
+
+fn with_graph<R>(f: impl FnOnce(&mut Graph) -> R) -> R {
+    let raised = kernel::raise_irql(DISPATCH_LEVEL);
+    while GRAPH_LOCK
+        .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+        spin_loop();
+    }
+
+    let r = f(unsafe { &mut *GRAPH.0.get() });
+
+    GRAPH_LOCK.store(false, Ordering::Release);
+    drop(raised);
+    r
+}
+
+/// Check taking `id` against the mutexes the current thread holds, called before it waits for
+/// `id`. Prints any inversion found.
+pub(crate) fn before_lock(id: LockId) {
+    let Some(locks) = workers::current_locks() else { return };
+    let thread_id = kernel::current_thread_id();
+
+    for held in locks.held().filter(|&held| held != id) {
+        // printed once the graph is released, as printing allocates
+        match with_graph(|g| g.add(held, id, thread_id)) {
+            Added::New | Added::Known => {},
+            Added::Inverted(inversion) => {
+                println!("[wdk-mutex-test] [-] Lock order inversion: {inversion}");
+            },
+            Added::Full => {
+                println!("[wdk-mutex-test] [i] Lock order: more than {MAX_ORDERINGS} orderings in one test, later ones are not checked.");
+            },
+        }
+    }
+}
+
+/// Whether `before` has been held while taking `after` in the current test, in that order.
+pub fn ordered(before: LockId, after: LockId) -> bool {
+    with_graph(|g| g.edges().any(|e| !e.inverted && e.before == before && e.after == after))
+}
+
+/// The inversions seen since the last call, clearing the graph. Called by the runner before and
+/// after each test, and by tests that cause inversions on purpose.
+pub fn take() -> Option<Report> {
+    with_graph(|g| {
+        let report = g.first.map(|first| Report { first, count: g.count });
+        g.edges = [None; MAX_ORDERINGS];
+        g.len = 0;
+        g.full = false;
+        g.first = None;
+        g.count = 0;
+        report
+    })
+}
@@ -0,0 +1,81 @@
+//! Acquire and release events of the mutexes tests lock from their threads.
+//!
+//! A lock taken through [`lock`] records on the current worker, see [`crate::workers`], that it is
+//! waiting for the mutex, then that it holds it until the returned [`Tracked`] guard is dropped.
+//! The mutex is named by its Grt key when it was reached through the Grt, and by its address
+//! otherwise, so a stalled run can be matched to the code and keys of the test. Before waiting,
+//! the lock is also checked against those the thread holds, see [`crate::lock_order`].
+
+use core::{fmt, mem::ManuallyDrop, ops::{Deref, DerefMut}};
+
+use crate::{lock_order, workers};
+
+/// How a mutex is named in reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockId {
+    /// Reached through the Grt under this key.
+    Key(&'static str),
+    /// Anything else, by address.
+    Addr(usize),
+}
+
+impl LockId {
+    /// `m` by its address.
+    pub fn of<M>(m: &M) -> Self {
+        LockId::Addr(m as *const M as usize)
+    }
+}
+
+impl fmt::Display for LockId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LockId::Key(key) => write!(f, "`{key}`"),
+            LockId::Addr(addr) => write!(f, "{addr:#x}"),
+        }
+    }
+}
+
+/// A guard whose release is recorded once it is dropped.
+pub struct Tracked<G> {
+    guard: ManuallyDrop<G>,
+    id: LockId,
+}
+
+impl<G: Deref> Deref for Tracked<G> {
+    type Target = G::Target;
+
+    fn deref(&self) -> &G::Target {
+        &self.guard
+    }
+}
+
+impl<G: DerefMut> DerefMut for Tracked<G> {
+    fn deref_mut(&mut self) -> &mut G::Target {
+        &mut self.guard
+    }
+}
+
+impl<G> Drop for Tracked<G> {
+    fn drop(&mut self) {
+        // released before it is recorded, so a report never misses a holder
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        workers::record_released(self.id);
+    }
+}
+
+/// Take the mutex named `id` with `lock`, e.g. `lock(LockId::of(m), || P::lock(m))`, recording
+/// the wait and, if it succeeds, the acquisition.
+pub fn lock<G, E>(id: LockId, lock: impl FnOnce() -> Result<G, E>) -> Result<Tracked<G>, E> {
+    lock_order::before_lock(id);
+    workers::record_waiting(id);
+    match lock() {
+        Ok(guard) => {
+            workers::record_acquired(id);
+            Ok(Tracked { guard: ManuallyDrop::new(guard), id })
+        },
+        Err(e) => {
+            workers::record_not_acquired(id);
+            Err(e)
+        },
+    }
+}
@@ -0,0 +1,171 @@
+//! Harness output, printed to the debugger and kept in an in-memory log user mode can drain.
+//!
+//! Every message the harness prints goes through [`println`], which passes it on to `DbgPrint`
+//! (or stdout under host-sim) as before and also records it in a [`LogRing`] together with the
+//! time, the level given by its `[i]` / `[+]` / `[-]` marker, the running test and the CPU it was
+//! printed on. `ReadFile` on the device drains the ring as text, one record per line, so results
+//! can be collected without a debugger attached and nothing printed under load is lost to it.
+//!
+//! The ring is allocated by [`install`] before anything runs, so recording a message never
+//! allocates and the leak check of a test is not charged for it. Messages printed before then
+//! or after [`free`] only reach the debugger.
+
+use core::{fmt::{self, Write}, hint::spin_loop, ptr::null_mut, sync::atomic::{AtomicBool, AtomicPtr, Ordering}};
+
+use alloc::{boxed::Box, string::String, vec::Vec};
+
+use crate::{kernel::{self, perf_counter_us, DISPATCH_LEVEL}, log_ring::{decimal_digits, Level, LogRecord, LogRing, HEADER_LEN as LOG_RECORD_HEADER_LEN}, runner};
+
+/// Size of the ring, enough for the output of a full run with default parameters.
+pub const LOG_CAPACITY: usize = 256 * 1024;
+
+/// Print to the debugger and record in the log, with the same syntax as `std::println!`.
+macro_rules! println {
+    ($($arg:tt)*) => {
+        $crate::log::print(::alloc::format!($($arg)*))
+    };
+}
+pub(crate) use println;
+
+/// The installed ring, see [`install`].
+static LOG: AtomicPtr<LogRing> = AtomicPtr::new(null_mut());
+
+/// Serialises access to [`LOG`]. Held at `DISPATCH_LEVEL` so a holder cannot be preempted by a
+/// thread that then spins on it, and, like the report lock, independent of the mutexes under test.
+static LOG_LOCK: AtomicBool = AtomicBool::new(false);
+
+/// Allocate the ring, before the first message that should be kept.
+pub fn install() {
+    let old = LOG.swap(Box::into_raw(Box::new(LogRing::with_capacity(LOG_CAPACITY))), Ordering::SeqCst);
+    if !old.is_null() {
+        drop(unsafe { Box::from_raw(old) });
+    }
+}
+
+/// Free the ring, called on driver unload when nothing is printing any more.
+#[cfg(feature = "driver")]
+pub fn free() {
+    let old = LOG.swap(null_mut(), Ordering::SeqCst);
+    if !old.is_null() {
+        drop(unsafe { Box::from_raw(old) });
+    }
+}
+
+/// Call `f` with the ring, if one is installed.
+fn with_log<R>(f: impl FnOnce(&mut LogRing) -> R) -> Option<R> {
+    // None if already above DISPATCH_LEVEL, where nothing in the harness runs
+    let raised = kernel::raise_irql(DISPATCH_LEVEL);
+    while LOG_LOCK
+        .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+        spin_loop();
+    }
+
+    let r = unsafe { LOG.load(Ordering::SeqCst).as_mut() }.map(f);
+
+    LOG_LOCK.store(false, Ordering::Release);
+    drop(raised);
+    r
+}
+
+/// Print `message` and record it, see [`println`].
+pub fn print(message: String) {
+    #[cfg(feature = "driver")]
+    wdk::println!("{message}");
+    #[cfg(feature = "host-sim")]
+    std::println!("{message}");
+
+    let level = if message.contains("[-]") || message.contains(" FAIL ") {
+        Level::Error
+    } else if message.contains("[+]") {
+        Level::Success
+    } else {
+        Level::Info
+    };
+    let test = runner::current_test().map_or("", |t| t.name);
+    let timestamp_us = perf_counter_us();
+    let cpu = kernel::current_processor();
+
+    with_log(|log| log.push(timestamp_us, cpu, level, test, &message));
+}
+
+/// Remove the oldest records from the log and return them as text, one line per record, in at most
+/// `max_len` bytes. A record too long for `max_len` on its own is cut short rather than stalling
+/// the reader.
+///
+/// If records were dropped because the ring was full since the last drain, a line saying how many
+/// comes first.
+pub fn drain(max_len: usize) -> Vec<u8> {
+    if max_len == 0 {
+        return Vec::new();
+    }
+
+    // The log's lock is held at DISPATCH_LEVEL, so under it records are only copied out as they are
+    // stored, into memory allocated beforehand, and formatted once it is released. Every record
+    // copied was in the ring at once, and takes fewer bytes stored than as a line, so this is
+    // enough and a record cut short still fills the line.
+    let mut raw = Vec::with_capacity(max_len.clamp(LOG_RECORD_HEADER_LEN, LOG_CAPACITY));
+    let mut dropped = 0;
+
+    with_log(|log| {
+        let mut len = 0;
+        if log.dropped() != 0 {
+            len = dropped_notice_len(log.dropped());
+            if len > max_len {
+                return;
+            }
+            dropped = log.take_dropped();
+        }
+
+        while let Some(line_len) = log.peek_line_len() {
+            // the line and its newline, unless it is the first and is cut short
+            if len != 0 && len + line_len + 1 > max_len {
+                break;
+            }
+            if !log.pop_raw(&mut raw) {
+                break;
+            }
+            len += line_len + 1;
+        }
+    });
+
+    let mut out = String::new();
+    if dropped != 0 {
+        let _ = writeln!(out, "{}", DroppedNotice(dropped));
+    }
+    let mut rest = &raw[..];
+    while let Some((record, len)) = LogRecord::decode(rest) {
+        let _ = writeln!(out, "{record}");
+        rest = &rest[len..];
+    }
+    out.truncate(floor_char_boundary(&out, max_len));
+
+    out.into_bytes()
+}
+
+/// The line [`drain`] starts with when records were dropped.
+struct DroppedNotice(u64);
+
+const DROPPED_NOTICE_PREFIX: &str = "[wdk-mutex-test] [-] ";
+const DROPPED_NOTICE_SUFFIX: &str = " log message(s) dropped, the log was full.";
+
+impl fmt::Display for DroppedNotice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{DROPPED_NOTICE_PREFIX}{}{DROPPED_NOTICE_SUFFIX}", self.0)
+    }
+}
+
+/// Length of [`DroppedNotice`] for `dropped` records, newline included, without formatting it.
+fn dropped_notice_len(dropped: u64) -> usize {
+    DROPPED_NOTICE_PREFIX.len() + decimal_digits(dropped) + DROPPED_NOTICE_SUFFIX.len() + 1
+}
+
+/// The largest index of at most `i` on a character boundary of `s`.
+fn floor_char_boundary(s: &str, i: usize) -> usize {
+    let mut i = i.min(s.len());
+    while !s.is_char_boundary(i) {
+        i -= 1;
+    }
+    i
+}
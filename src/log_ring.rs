@@ -0,0 +1,338 @@
+//! Fixed size ring of log records, the storage behind [`crate::log`].
+//!
+//! Plain data with no kernel dependencies or locking of its own, so it can be exercised directly
+//! under host-sim. Records are packed back to back into one buffer allocated up front; pushing
+//! never allocates, so it is safe at any IRQL the buffer's memory is, and when the buffer is full
+//! the oldest records are evicted to make room and counted as dropped.
+//!
+//! Each record is a [`HEADER_LEN`] byte little-endian header followed by the test name and the
+//! message:
+//!
+//! | Offset | Size | Field          |
+//! |--------|------|----------------|
+//! | 0      | 8    | `seq`          |
+//! | 8      | 8    | `timestamp_us` |
+//! | 16     | 4    | `cpu`          |
+//! | 20     | 1    | `level`        |
+//! | 21     | 2    | test name len  |
+//! | 23     | 2    | message len    |
+
+use core::fmt;
+
+use alloc::{string::String, vec, vec::Vec};
+
+/// Bytes in front of every record, see the module documentation.
+pub const HEADER_LEN: usize = 25;
+
+/// Severity of a log record, taken from the `[i]`, `[+]` and `[-]` markers of harness messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Level {
+    Info = 0,
+    Success = 1,
+    Error = 2,
+}
+
+impl Level {
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Level::Info => "INFO",
+            Level::Success => "OK",
+            Level::Error => "ERROR",
+        }
+    }
+
+    pub fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            0 => Some(Level::Info),
+            1 => Some(Level::Success),
+            2 => Some(Level::Error),
+            _ => None,
+        }
+    }
+}
+
+/// A record taken out of the ring.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogRecord {
+    /// Position of the record among all ever pushed, gaps are records that were dropped.
+    pub seq: u64,
+    pub timestamp_us: u64,
+    pub cpu: u32,
+    pub level: Level,
+    /// The test running when the record was pushed, empty outside of a test.
+    pub test: String,
+    pub message: String,
+}
+
+impl fmt::Display for LogRecord {
+    /// One line without a trailing newline, e.g.
+    /// `[   12.345678] cpu1 ERROR kmutex::to_owned: Bad value`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let test = if self.test.is_empty() { "-" } else { self.test.as_str() };
+        write!(
+            f,
+            "[{:>5}.{:06}] cpu{} {} {test}: {}",
+            self.timestamp_us / 1_000_000,
+            self.timestamp_us % 1_000_000,
+            self.cpu,
+            self.level.name(),
+            self.message,
+        )
+    }
+}
+
+impl LogRecord {
+    /// The record at the start of `bytes`, stored as in the ring, see [`LogRing::pop_raw`], and the
+    /// number of bytes it takes.
+    pub fn decode(bytes: &[u8]) -> Option<(LogRecord, usize)> {
+        let header: &[u8; HEADER_LEN] = bytes.get(..HEADER_LEN)?.try_into().ok()?;
+        let (test_len, message_len) = text_lens(header);
+        let len = HEADER_LEN + test_len + message_len;
+        let text = bytes.get(HEADER_LEN..len)?;
+        let (test, message) = text.split_at(test_len);
+
+        let record = LogRecord {
+            seq: u64::from_le_bytes(header[0..8].try_into().unwrap()),
+            timestamp_us: u64::from_le_bytes(header[8..16].try_into().unwrap()),
+            cpu: u32::from_le_bytes(header[16..20].try_into().unwrap()),
+            level: Level::from_u8(header[20]).unwrap_or(Level::Info),
+            // both were cut on character boundaries when pushed
+            test: String::from_utf8(test.to_vec()).unwrap_or_default(),
+            message: String::from_utf8(message.to_vec()).unwrap_or_default(),
+        };
+        Some((record, len))
+    }
+}
+
+/// Lengths of the test name and message following `header`.
+fn text_lens(header: &[u8; HEADER_LEN]) -> (usize, usize) {
+    let u16_at = |i: usize| u16::from_le_bytes([header[i], header[i + 1]]) as usize;
+    (u16_at(21), u16_at(23))
+}
+
+/// Length of the line [`LogRecord`]'s `Display` writes for the record with `header`, worked out
+/// without formatting it.
+fn line_len(header: &[u8; HEADER_LEN]) -> usize {
+    let timestamp_us = u64::from_le_bytes(header[8..16].try_into().unwrap());
+    let cpu = u32::from_le_bytes(header[16..20].try_into().unwrap());
+    let level = Level::from_u8(header[20]).unwrap_or(Level::Info);
+    let (test_len, message_len) = text_lens(header);
+
+    // `[`, `.`, six digits of microseconds, `] cpu`, the spaces around the level and `: `
+    let punctuation = 1 + 1 + 6 + 5 + 2 + 2;
+    punctuation
+        + decimal_digits(timestamp_us / 1_000_000).max(5)
+        + decimal_digits(cpu as u64)
+        + level.name().len()
+        + test_len.max(1)
+        + message_len
+}
+
+/// Digits in the decimal representation of `n`.
+pub(crate) fn decimal_digits(mut n: u64) -> usize {
+    let mut digits = 1;
+    while n >= 10 {
+        n /= 10;
+        digits += 1;
+    }
+    digits
+}
+
+/// Records packed into a fixed `capacity` byte buffer, oldest first.
+#[derive(Debug)]
+pub struct LogRing {
+    buf: Vec<u8>,
+    /// Offset of the oldest record.
+    head: usize,
+    /// Bytes taken by records, from `head` on, wrapping around the end of `buf`.
+    used: usize,
+    records: usize,
+    next_seq: u64,
+    dropped: u64,
+}
+
+impl LogRing {
+    /// A ring of `capacity` bytes, at least enough for one record with an empty message.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            buf: vec![0; capacity.max(HEADER_LEN)],
+            head: 0,
+            used: 0,
+            records: 0,
+            next_seq: 0,
+            dropped: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Records evicted to make room since the last [`LogRing::take_dropped`].
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// [`LogRing::dropped`], resetting it to zero.
+    pub fn take_dropped(&mut self) -> u64 {
+        core::mem::take(&mut self.dropped)
+    }
+
+    /// Append a record, evicting the oldest ones if there is no room, and return its `seq`.
+    ///
+    /// The test name and then the message are cut short, on a character boundary, if the record
+    /// would not fit in the ring on its own.
+    pub fn push(&mut self, timestamp_us: u64, cpu: u32, level: Level, test: &str, message: &str) -> u64 {
+        let room = self.capacity() - HEADER_LEN;
+        let test = truncate(test, room.min(u16::MAX as usize));
+        let message = truncate(message, (room - test.len()).min(u16::MAX as usize));
+        let len = HEADER_LEN + test.len() + message.len();
+
+        while self.capacity() - self.used < len {
+            self.evict();
+        }
+
+        let seq = self.next_seq;
+        self.next_seq += 1;
+
+        let mut header = [0u8; HEADER_LEN];
+        header[0..8].copy_from_slice(&seq.to_le_bytes());
+        header[8..16].copy_from_slice(&timestamp_us.to_le_bytes());
+        header[16..20].copy_from_slice(&cpu.to_le_bytes());
+        header[20] = level as u8;
+        header[21..23].copy_from_slice(&(test.len() as u16).to_le_bytes());
+        header[23..25].copy_from_slice(&(message.len() as u16).to_le_bytes());
+
+        let mut at = (self.head + self.used) % self.capacity();
+        for part in [&header[..], test.as_bytes(), message.as_bytes()] {
+            self.write_at(at, part);
+            at = (at + part.len()) % self.capacity();
+        }
+        self.used += len;
+        self.records += 1;
+
+        seq
+    }
+
+    /// The oldest record, without removing it.
+    ///
+    /// This and [`LogRing::pop`] are for the host-sim tests; [`crate::log`] drains the ring with
+    /// [`LogRing::pop_raw`].
+    #[cfg(feature = "host-sim")]
+    pub fn peek(&self) -> Option<LogRecord> {
+        let header = self.peek_header()?;
+        let (test_len, message_len) = text_lens(&header);
+
+        let mut raw = vec![0; HEADER_LEN + test_len + message_len];
+        self.read_at(self.head, &mut raw);
+        LogRecord::decode(&raw).map(|(record, _)| record)
+    }
+
+    /// Remove and return the oldest record.
+    #[cfg(feature = "host-sim")]
+    pub fn pop(&mut self) -> Option<LogRecord> {
+        let record = self.peek()?;
+        self.remove_head();
+        Some(record)
+    }
+
+    /// Length of the line the oldest record is displayed as, without reading it out of the ring.
+    pub fn peek_line_len(&self) -> Option<usize> {
+        self.peek_header().map(|header| line_len(&header))
+    }
+
+    /// Remove the oldest record, appending it to `out` as it is stored, to be read back with
+    /// [`LogRecord::decode`]. `false` if the ring is empty.
+    ///
+    /// Never grows `out`, so it can be called where allocating is not allowed: a record longer than
+    /// the room left in `out` has its test name and then its message cut short, on a character
+    /// boundary, to fit, and nothing is removed if not even its header fits.
+    pub fn pop_raw(&mut self, out: &mut Vec<u8>) -> bool {
+        let Some(mut header) = self.peek_header() else { return false };
+        let room = out.capacity() - out.len();
+        if room < HEADER_LEN {
+            return false;
+        }
+
+        let (test_len, message_len) = text_lens(&header);
+        let start = out.len();
+        out.extend_from_slice(&header);
+
+        let mut at = (self.head + HEADER_LEN) % self.capacity();
+        let mut room = room - HEADER_LEN;
+        let mut kept = [0; 2];
+        for (kept, len) in kept.iter_mut().zip([test_len, message_len]) {
+            let from = out.len();
+            out.resize(from + len.min(room), 0);
+            self.read_at(at, &mut out[from..]);
+            // the text was valid when pushed, so only a character cut in two at the end can fail
+            let whole = core::str::from_utf8(&out[from..]).map_or_else(|e| e.valid_up_to(), str::len);
+            out.truncate(from + whole);
+
+            *kept = out.len() - from;
+            room -= *kept;
+            at = (at + len) % self.capacity();
+        }
+
+        header[21..23].copy_from_slice(&(kept[0] as u16).to_le_bytes());
+        header[23..25].copy_from_slice(&(kept[1] as u16).to_le_bytes());
+        out[start..start + HEADER_LEN].copy_from_slice(&header);
+
+        self.remove_head();
+        true
+    }
+
+    fn peek_header(&self) -> Option<[u8; HEADER_LEN]> {
+        if self.records == 0 {
+            return None;
+        }
+
+        let mut header = [0u8; HEADER_LEN];
+        self.read_at(self.head, &mut header);
+        Some(header)
+    }
+
+    /// Drop the oldest record to make room.
+    fn evict(&mut self) {
+        self.remove_head();
+        self.dropped += 1;
+    }
+
+    fn remove_head(&mut self) {
+        let mut lens = [0u8; 4];
+        self.read_at((self.head + 21) % self.capacity(), &mut lens);
+        let len = HEADER_LEN + u16::from_le_bytes([lens[0], lens[1]]) as usize + u16::from_le_bytes([lens[2], lens[3]]) as usize;
+
+        self.head = (self.head + len) % self.capacity();
+        self.used -= len;
+        self.records -= 1;
+        if self.records == 0 {
+            self.head = 0;
+        }
+    }
+
+    /// Copy `data` into the buffer starting at `at`, wrapping around its end.
+    fn write_at(&mut self, at: usize, data: &[u8]) {
+        let first = data.len().min(self.capacity() - at);
+        self.buf[at..at + first].copy_from_slice(&data[..first]);
+        self.buf[..data.len() - first].copy_from_slice(&data[first..]);
+    }
+
+    /// Fill `out` from the buffer starting at `at`, wrapping around its end.
+    fn read_at(&self, at: usize, out: &mut [u8]) {
+        let first = out.len().min(self.capacity() - at);
+        out[..first].copy_from_slice(&self.buf[at..at + first]);
+        let rest = out.len() - first;
+        out[first..].copy_from_slice(&self.buf[..rest]);
+    }
+}
+
+/// The longest prefix of `s` of at most `max` bytes ending on a character boundary.
+fn truncate(s: &str, max: usize) -> &str {
+    let mut end = s.len().min(max);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
@@ -0,0 +1,118 @@
+//! Declarative registry of every test the driver knows how to run.
+//!
+//! Each test module exposes a `TESTS` table of [`TestCase`]s describing its tests once; the runner
+//! in `driver_entry` only ever walks [`all_tests`]. Adding a test to an existing module therefore
+//! never requires touching `lib.rs`, only its own table.
+
+use alloc::{format, string::String};
+use wdk_mutex_tests_protocol::TestFilter;
+
+use crate::{bench_grt, config::TestConfig, grt_scope::GrtScope, kernel::NTSTATUS, test_fast_mutex, test_grt, test_grt_lifecycle, test_kmutex, test_lock_order, wdk_mutex::errors::{DriverMutexError, GrtError}};
+
+pub use wdk_mutex_tests_protocol::Suite;
+
+/// A single registered test.
+pub struct TestCase {
+    /// Unique name of the test, by convention `<primitive>::<test>`.
+    pub name: &'static str,
+    pub suite: Suite,
+    /// Free-form tags used for grouping, e.g. `multithread`.
+    pub tags: &'static [&'static str],
+    /// Entry point of the test.
+    pub run: fn(&TestContext) -> TestResult,
+}
+
+/// Parameters a test runs with, derived from the run's [`TestConfig`], and its Grt namespace.
+///
+/// A fresh context is made for every test the runner starts.
+pub struct TestContext {
+    /// Number of threads multithreaded tests should spawn.
+    pub threads: usize,
+    /// Lock / increment cycles each thread performs.
+    pub iterations: u32,
+    /// How long soak tests run for, 0 when they should be skipped.
+    pub soak_seconds: u32,
+    /// Whether tests that deliberately exercise undefined behaviour may run.
+    pub allow_unsound: bool,
+    /// Whether benchmarks should run, they are skipped otherwise.
+    pub benchmarks: bool,
+    /// Where the test takes its Grt keys from, torn down by the runner once the test returns.
+    pub grt: GrtScope,
+}
+
+impl TestContext {
+    pub fn new(config: &TestConfig) -> Self {
+        Self {
+            threads: config.threads as usize,
+            iterations: config.iterations,
+            soak_seconds: config.soak_seconds,
+            allow_unsound: config.allow_unsound,
+            benchmarks: config.benchmarks,
+            grt: GrtScope::new(),
+        }
+    }
+
+    /// The value a counter incremented once per iteration by every thread should end on.
+    pub fn expected_total(&self) -> u32 {
+        self.threads as u32 * self.iterations
+    }
+}
+
+/// The outcome of running a single test body.
+pub type TestResult = Result<(), TestError>;
+
+/// Why a test did not pass.
+#[derive(Debug)]
+pub enum TestError {
+    /// The test ran and an assertion did not hold.
+    Failed(String),
+    /// The test decided it cannot run in the current environment.
+    Skipped(String),
+}
+
+impl TestError {
+    pub fn fail(reason: impl Into<String>) -> Self {
+        TestError::Failed(reason.into())
+    }
+
+    pub fn skip(reason: impl Into<String>) -> Self {
+        TestError::Skipped(reason.into())
+    }
+
+    /// Fail because a kernel call the test depends on returned `status`.
+    pub fn status(what: &str, status: NTSTATUS) -> Self {
+        TestError::Failed(format!("{what} failed: {status:#010x}"))
+    }
+}
+
+impl From<DriverMutexError> for TestError {
+    fn from(e: DriverMutexError) -> Self {
+        TestError::Failed(format!("DriverMutexError: {e:?}"))
+    }
+}
+
+impl From<GrtError> for TestError {
+    fn from(e: GrtError) -> Self {
+        TestError::Failed(format!("GrtError: {e:?}"))
+    }
+}
+
+/// Every test table in the crate, in the order they should run.
+static TABLES: &[&[TestCase]] = &[
+    test_kmutex::TESTS,
+    test_fast_mutex::TESTS,
+    test_grt::TESTS,
+    test_grt_lifecycle::TESTS,
+    test_lock_order::TESTS,
+    bench_grt::TESTS,
+];
+
+/// Iterate every registered test in run order.
+pub fn all_tests() -> impl Iterator<Item = &'static TestCase> {
+    TABLES.iter().flat_map(|table| table.iter())
+}
+
+/// Iterate the registered tests selected by `filter`, in run order.
+pub fn matching(filter: &TestFilter) -> impl Iterator<Item = &'static TestCase> + '_ {
+    all_tests().filter(|t| filter.matches(t.name, t.suite, t.tags))
+}
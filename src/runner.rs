@@ -0,0 +1,377 @@
+//! Runs registered tests to completion and reports on them.
+//!
+//! Unlike the original `driver_entry` if-chain, a failing test no longer stops the run: every test
+//! is executed, its outcome and duration recorded, and a summary is printed at the end. What the
+//! driver does with a failing report is decided by a [`ResultPolicy`].
+//!
+//! Each test gets a deadline from [`TestConfig::timeout_us`]. A test whose threads are still
+//! running by then is recorded as timed out, with the threads and what they last recorded, see
+//! [`crate::threads`], and the run goes on or stops according to its [`TimeoutPolicy`]. A test whose
+//! threads nested mutexes in orders that could deadlock fails too, see [`crate::lock_order`].
+//!
+//! Alongside the human-readable output, the start and end of the run and of each test, and any
+//! [`metric`] a test reports, are printed as records of the stable line format described at
+//! [`Event`], so a captured debugger transcript can be turned back into results.
+
+use core::{fmt::Write, hint::spin_loop, ptr::null_mut, sync::atomic::{AtomicBool, AtomicPtr, Ordering}};
+
+use alloc::{boxed::Box, format, string::{String, ToString}, vec::Vec};
+use wdk_mutex_tests_protocol::{Event, Status, TestOutcome};
+
+#[cfg(feature = "driver")]
+use crate::kernel::{NTSTATUS, STATUS_SUCCESS, STATUS_UNSUCCESSFUL};
+use crate::{alloc_tracking, config::{TestConfig, TimeoutPolicy}, kernel::perf_counter_us, lock_order, println, registry::{Suite, TestCase, TestContext, TestError}, threads};
+
+/// What the driver should do with its load status once a run has completed.
+#[cfg(feature = "driver")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResultPolicy {
+    /// Return `STATUS_UNSUCCESSFUL` from `DriverEntry` when any test failed, unless threads
+    /// abandoned by a timed out test are still running the driver's code.
+    FailLoad,
+    /// Always stay loaded so the results can be queried after the run.
+    StayLoaded,
+}
+
+/// Outcome of a single test.
+#[derive(Debug)]
+pub enum TestStatus {
+    Passed,
+    Failed(String),
+    Skipped(String),
+    /// Its threads missed the deadline, the reason says which were still running.
+    TimedOut(String),
+}
+
+/// Result of a single test within a run.
+#[derive(Debug)]
+pub struct TestRecord {
+    pub name: &'static str,
+    pub suite: Suite,
+    pub status: TestStatus,
+    pub duration_us: u64,
+}
+
+impl TestRecord {
+    /// Convert to the wire representation sent over the control device.
+    pub fn to_outcome(&self) -> TestOutcome {
+        let (status, reason) = match &self.status {
+            TestStatus::Passed => (Status::Passed, String::new()),
+            TestStatus::Failed(reason) => (Status::Failed, reason.clone()),
+            TestStatus::Skipped(reason) => (Status::Skipped, reason.clone()),
+            TestStatus::TimedOut(reason) => (Status::Failed, format!("timed out: {reason}")),
+        };
+
+        TestOutcome {
+            name: self.name.to_string(),
+            suite: self.suite,
+            status,
+            reason,
+            duration_us: self.duration_us,
+        }
+    }
+}
+
+/// All records of a single run, in execution order.
+#[derive(Debug, Default)]
+pub struct RunReport {
+    pub records: Vec<TestRecord>,
+}
+
+impl RunReport {
+    pub fn passed(&self) -> usize {
+        self.records.iter().filter(|r| matches!(r.status, TestStatus::Passed)).count()
+    }
+
+    pub fn failed(&self) -> usize {
+        self.records.iter().filter(|r| matches!(r.status, TestStatus::Failed(_) | TestStatus::TimedOut(_))).count()
+    }
+
+    pub fn skipped(&self) -> usize {
+        self.records.iter().filter(|r| matches!(r.status, TestStatus::Skipped(_))).count()
+    }
+
+    /// The status `DriverEntry` should return for this report under `policy`.
+    #[cfg(feature = "driver")]
+    pub fn status(&self, policy: ResultPolicy) -> NTSTATUS {
+        match policy {
+            ResultPolicy::FailLoad if self.failed() != 0 && threads::abandoned() == 0 => STATUS_UNSUCCESSFUL,
+            _ => STATUS_SUCCESS,
+        }
+    }
+
+    /// One line per test followed by per-suite pass counts, e.g. `KMutex 4/5`, each line ending
+    /// in a newline. This is what [`Self::print_summary`] prints and `IRP_MJ_READ` returns.
+    pub fn summary(&self) -> String {
+        let mut out = String::new();
+        out.push_str("[wdk-mutex-test] [i] ---------------- Results ----------------\n");
+        for r in &self.records {
+            let (tag, reason) = match &r.status {
+                TestStatus::Passed => ("PASS", ""),
+                TestStatus::Failed(reason) => ("FAIL", reason.as_str()),
+                TestStatus::Skipped(reason) => ("SKIP", reason.as_str()),
+                TestStatus::TimedOut(reason) => ("TIME", reason.as_str()),
+            };
+            let _ = writeln!(
+                out,
+                "[wdk-mutex-test] {tag} {:<10} {:<55} {:>9} us {reason}",
+                r.suite.name(),
+                r.name,
+                r.duration_us,
+            );
+        }
+
+        for suite in Suite::ALL {
+            let ran = self.records
+                .iter()
+                .filter(|r| r.suite == suite && !matches!(r.status, TestStatus::Skipped(_)));
+            let total = ran.clone().count();
+            let passed = ran.filter(|r| matches!(r.status, TestStatus::Passed)).count();
+            if total != 0 {
+                let _ = writeln!(out, "[wdk-mutex-test] [i] {suite} {passed}/{total}");
+            }
+        }
+
+        let _ = writeln!(
+            out,
+            "[wdk-mutex-test] [i] Passed: {}, failed: {}, skipped: {}.",
+            self.passed(),
+            self.failed(),
+            self.skipped(),
+        );
+        out
+    }
+
+    /// Print [`Self::summary`].
+    pub fn print_summary(&self) {
+        for line in self.summary().lines() {
+            println!("{line}");
+        }
+    }
+}
+
+/// The test [`run_test`] is running, null between tests.
+static CURRENT_TEST: AtomicPtr<TestCase> = AtomicPtr::new(null_mut());
+
+/// The test being run, for tagging what it prints, see [`crate::log`].
+pub fn current_test() -> Option<&'static TestCase> {
+    unsafe { CURRENT_TEST.load(Ordering::SeqCst).as_ref() }
+}
+
+/// Run a single test in a fresh [`TestContext`] and record how it went.
+///
+/// Whatever the test registered in the Grt is removed once it returns; failing to do so fails the
+/// test, as the next one would no longer start from a clean registry. A test that passed but left
+/// more memory allocated through the harness than it started with fails with the leaked amount,
+/// see [`crate::alloc_tracking`].
+///
+/// A test whose threads missed the deadline is timed out whatever it returned. From then on the
+/// Grt is no longer torn down between tests and leaks are not checked, as the abandoned threads
+/// may still be using what they reach.
+pub fn run_test(test: &'static TestCase, config: &TestConfig) -> TestRecord {
+    CURRENT_TEST.store(test as *const TestCase as *mut TestCase, Ordering::SeqCst);
+    println!("[wdk-mutex-test] [i] Running {}...", test.name);
+    println!("[wdk-mutex-test] {}", Event::Start { suite: test.suite, test: test.name.to_string() });
+
+    let before = alloc_tracking::snapshot();
+    let ctx = TestContext::new(config);
+    // whatever an earlier test or an abandoned thread ordered is not this test's
+    lock_order::take();
+    let start = perf_counter_us();
+    threads::set_join_deadline(config.timeout_us().map(|us| start + us));
+    let mut result = (test.run)(&ctx);
+    let duration_us = perf_counter_us().saturating_sub(start);
+    threads::set_join_deadline(None);
+
+    // taken before the teardown frees the keys it names, an inversion fails the test even though
+    // it never deadlocked
+    if let Some(report) = lock_order::take() {
+        let reason = format!("lock order inversion: {report}");
+        if result.is_ok() {
+            result = Err(TestError::fail(reason));
+        } else {
+            println!("[wdk-mutex-test] [-] Test {}::{}: {reason}", test.suite, test.name);
+        }
+    }
+
+    // threads given up on, by this test or an earlier one, may still use the Grt and the heap,
+    // so the Grt is left as it is and nothing is checked for leaks until they are gone
+    let abandoned = threads::abandoned() != 0;
+    let timed_out = threads::take_timeout();
+    if abandoned {
+        ctx.grt.abandon();
+    } else if let Err(e) = ctx.grt.teardown() {
+        let reason = format!("tearing down the test's Grt scope failed: {e:?}");
+        if result.is_ok() {
+            result = Err(TestError::fail(reason));
+        } else {
+            println!("[wdk-mutex-test] [-] Test {}::{}: {reason}", test.suite, test.name);
+        }
+    }
+
+    if let Some(reason) = timed_out {
+        let reason = format!("after {} s, {reason}", duration_us / 1_000_000);
+        println!("[wdk-mutex-test] [-] Test {}::{} timed out {reason}", test.suite, test.name);
+        return finish(test, TestStatus::TimedOut(reason), duration_us);
+    }
+
+    // a failed or skipped test still holds its reason, only a pass is expected to break even
+    if result.is_ok() && !abandoned {
+        if let Some(leak) = alloc_tracking::snapshot().since(&before).leak() {
+            result = Err(TestError::fail(leak));
+        }
+    }
+
+    let status = match result {
+        Ok(()) => TestStatus::Passed,
+        Err(TestError::Failed(reason)) => {
+            println!("[wdk-mutex-test] [-] Test {}::{} failed: {reason}", test.suite, test.name);
+            TestStatus::Failed(reason)
+        },
+        Err(TestError::Skipped(reason)) => TestStatus::Skipped(reason),
+    };
+
+    finish(test, status, duration_us)
+}
+
+/// Record `test` as having ended with `status`, printing its result record, and end it.
+fn finish(test: &'static TestCase, status: TestStatus, duration_us: u64) -> TestRecord {
+    let record = TestRecord {
+        name: test.name,
+        suite: test.suite,
+        status,
+        duration_us,
+    };
+    let outcome = record.to_outcome();
+    println!(
+        "[wdk-mutex-test] {}",
+        Event::Result {
+            suite: outcome.suite,
+            test: outcome.name,
+            status: outcome.status,
+            duration_us,
+            reason: outcome.reason,
+        },
+    );
+    CURRENT_TEST.store(null_mut(), Ordering::SeqCst);
+
+    record
+}
+
+/// Report a measurement made by the running test, e.g. `metric("get_ns_per_op", 85, "ns")`, as a
+/// `METRIC` record of the line format, see [`Event`]. Spaces in `name` are replaced by `_`.
+pub fn metric(name: &str, value: u64, unit: &str) {
+    let test = current_test().map_or("-", |t| t.name);
+    let name = name.replace(' ', "_");
+    println!("[wdk-mutex-test] {}", Event::Metric { test: test.to_string(), name, value, unit: unit.to_string() });
+}
+
+/// Run every test yielded by `tests` with the parameters in `config`.
+///
+/// The tests are run `config.repeat` times over. Earlier failures do not stop the run unless
+/// `config.fail_fast` is set, in which case nothing after the first failing test is run, or a
+/// test timed out and `config.on_timeout` is [`TimeoutPolicy::Abort`].
+pub fn run_tests(tests: impl Iterator<Item = &'static TestCase>, config: &TestConfig) -> RunReport {
+    let tests: Vec<_> = tests.collect();
+    let mut report = RunReport::default();
+
+    println!(
+        "[wdk-mutex-test] [i] Running {} test(s) x{} with {} thread(s), {} iteration(s) each.",
+        tests.len(),
+        config.repeat,
+        config.threads,
+        config.iterations,
+    );
+    println!("[wdk-mutex-test] {}", Event::RunStart { tests: tests.len() as u32 * config.repeat });
+
+    'run: for _ in 0..config.repeat {
+        for &test in &tests {
+            let record = run_test(test, config);
+            let timed_out = matches!(record.status, TestStatus::TimedOut(_));
+            let failed = timed_out || matches!(record.status, TestStatus::Failed(_));
+            report.records.push(record);
+
+            if timed_out && config.on_timeout == TimeoutPolicy::Abort {
+                println!("[wdk-mutex-test] [-] A test timed out and AbortOnTimeout is set, stopping the run.");
+                break 'run;
+            }
+            if failed && config.fail_fast {
+                println!("[wdk-mutex-test] [-] Fail fast is set, stopping the run.");
+                break 'run;
+            }
+        }
+    }
+
+    println!(
+        "[wdk-mutex-test] {}",
+        Event::RunEnd { passed: report.passed() as u32, failed: report.failed() as u32, skipped: report.skipped() as u32 },
+    );
+    report
+}
+
+/// Set while a run is in progress, so the run at load and on-demand runs from the control device
+/// cannot overlap.
+static RUN_IN_PROGRESS: AtomicBool = AtomicBool::new(false);
+
+/// Held for the duration of a run, see [`begin_run`].
+pub struct RunGuard(());
+
+impl Drop for RunGuard {
+    fn drop(&mut self) {
+        RUN_IN_PROGRESS.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Claim the right to run tests, or `None` if another run is already in progress.
+pub fn begin_run() -> Option<RunGuard> {
+    RUN_IN_PROGRESS
+        .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+        .ok()
+        .map(|_| RunGuard(()))
+}
+
+/// The report of the most recent run, kept so results can be queried while the driver stays loaded.
+static LAST_REPORT: AtomicPtr<RunReport> = AtomicPtr::new(null_mut());
+
+/// Protects [`LAST_REPORT`] from being freed while it is being read. Only ever held briefly and
+/// at PASSIVE_LEVEL, so a spin is sufficient and keeps the harness independent of the mutexes
+/// under test.
+static REPORT_LOCK: AtomicBool = AtomicBool::new(false);
+
+fn with_report_lock<R>(f: impl FnOnce() -> R) -> R {
+    while REPORT_LOCK
+        .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+        spin_loop();
+    }
+    let r = f();
+    REPORT_LOCK.store(false, Ordering::Release);
+    r
+}
+
+/// Keep `report` as the most recent run, freeing the one it replaces.
+pub fn store_report(report: RunReport) {
+    let new = Box::into_raw(Box::new(report));
+    let old = with_report_lock(|| LAST_REPORT.swap(new, Ordering::SeqCst));
+    if !old.is_null() {
+        let _ = unsafe { Box::from_raw(old) };
+    }
+}
+
+/// Call `f` with the most recent report, if there is one.
+pub fn with_last_report<R>(f: impl FnOnce(Option<&RunReport>) -> R) -> R {
+    with_report_lock(|| {
+        let p = LAST_REPORT.load(Ordering::SeqCst);
+        f(unsafe { p.as_ref() })
+    })
+}
+
+/// Free the stored report, called on driver unload.
+#[cfg(feature = "driver")]
+pub fn free_last_report() {
+    let old = with_report_lock(|| LAST_REPORT.swap(null_mut(), Ordering::SeqCst));
+    if !old.is_null() {
+        let _ = unsafe { Box::from_raw(old) };
+    }
+}
@@ -0,0 +1,200 @@
+//! Timed soak of a [`LockPrimitive`] under randomised contention.
+//!
+//! The conformance tests finish in milliseconds, far too quickly to shake out rare ordering bugs.
+//! The soak instead runs rounds of mixed work until `Parameters\SoakSeconds` have elapsed. Each
+//! round picks a random thread count, and every thread performs a random number of operations on
+//! either a directly owned mutex or one registered in the Grt, holding the lock for a random time.
+//!
+//! The protected state is a pair of counters which every operation reads, checks are equal, and
+//! increments together; seeing them differ means two threads were inside the lock at once. At the
+//! end of each round the counters must also have advanced by exactly the number of operations
+//! performed. Any violation fails the test, and the summary reports how many were seen.
+//!
+//! The soak is skipped unless `SoakSeconds` is set. Run it at load with `TagFilter` set to `soak`,
+//! or on demand through the control device.
+
+use core::{hint::spin_loop, sync::atomic::{AtomicU64, Ordering}};
+
+use alloc::{format, sync::Arc};
+
+use crate::{config::MAX_THREADS, conformance::LockPrimitive, kernel::perf_counter_us, lock_tracking::{self, LockId}, println, registry::{TestContext, TestError, TestResult}, runner, threads::SystemThreadGroup, workers::record_progress};
+
+/// How often progress is printed while soaking.
+const PROGRESS_INTERVAL_US: u64 = 10_000_000;
+
+/// Upper bound on the operations a thread performs in one round, keeping rounds short enough that
+/// the deadline is not overshot by much.
+const MAX_OPS_PER_THREAD: u64 = 2_000;
+
+/// Upper bound on the spin iterations an operation holds the lock for.
+const MAX_HOLD_SPINS: u64 = 256;
+
+/// The state protected by each soaked mutex.
+#[derive(Default)]
+struct Pair {
+    a: u64,
+    b: u64,
+}
+
+/// Counters shared by the soak threads of a round.
+#[derive(Default)]
+struct Stats {
+    ops: AtomicU64,
+    direct_ops: AtomicU64,
+    grt_ops: AtomicU64,
+    violations: AtomicU64,
+    seed: AtomicU64,
+}
+
+/// xorshift64*, plenty for picking workloads and cheap enough to call inside the lock.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // xorshift must not start from zero
+        Self(seed | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// A value in `lo..=hi`.
+    fn range(&mut self, lo: u64, hi: u64) -> u64 {
+        lo + self.next() % (hi - lo + 1)
+    }
+}
+
+/// Soak `P` for `ctx.soak_seconds`, see the module documentation.
+pub fn soak<P: LockPrimitive>(ctx: &TestContext) -> TestResult {
+    if ctx.soak_seconds == 0 {
+        return Err(TestError::skip("soak disabled, set Parameters\\SoakSeconds"));
+    }
+
+    let grt_key = ctx.grt.key("soak");
+    P::grt_register(grt_key, Pair::default())?;
+    let grt_mtx = P::grt_get::<Pair>(grt_key)?;
+    let direct_mtx = Arc::new(P::new(Pair::default())?);
+
+    let start = perf_counter_us();
+    let deadline = start + ctx.soak_seconds as u64 * 1_000_000;
+    let mut last_progress = start;
+    let mut rng = Rng::new(start);
+    let stats = Arc::new(Stats::default());
+    let mut rounds = 0u64;
+
+    println!("[wdk-mutex-test] [i] Soaking for {} s...", ctx.soak_seconds);
+
+    while perf_counter_us() < deadline {
+        let threads = rng.range(2, (ctx.threads as u64 * 2).clamp(2, MAX_THREADS as u64)) as usize;
+        let ops_per_thread = rng.range(1, MAX_OPS_PER_THREAD);
+        let grt_percent = rng.range(0, 100);
+
+        let direct_before = P::lock(&direct_mtx)?.a;
+        let grt_before = P::lock(grt_mtx)?.a;
+        let direct_ops_before = stats.direct_ops.load(Ordering::SeqCst);
+        let grt_ops_before = stats.grt_ops.load(Ordering::SeqCst);
+
+        let mut group = SystemThreadGroup::new();
+        let (m, s) = (Arc::clone(&direct_mtx), Arc::clone(&stats));
+        group.spawn(threads, move || soak_thread::<P>(&m, grt_key, &s, ops_per_thread, grt_percent))
+            .map_err(|s| TestError::status("spawning threads", s))?;
+        group.join_all().map_err(|s| TestError::status("joining threads", s))?;
+        rounds += 1;
+
+        // every operation of the round is accounted for in both counters
+        let direct_ops = stats.direct_ops.load(Ordering::SeqCst) - direct_ops_before;
+        let grt_ops = stats.grt_ops.load(Ordering::SeqCst) - grt_ops_before;
+        let direct_after = P::lock(&direct_mtx)?.a;
+        let grt_after = P::lock(grt_mtx)?.a;
+        if direct_after - direct_before != direct_ops {
+            stats.violations.fetch_add(1, Ordering::SeqCst);
+            println!("[wdk-mutex-test] [-] Round {rounds}: direct mutex advanced {} for {direct_ops} ops", direct_after - direct_before);
+        }
+        if grt_after - grt_before != grt_ops {
+            stats.violations.fetch_add(1, Ordering::SeqCst);
+            println!("[wdk-mutex-test] [-] Round {rounds}: Grt mutex advanced {} for {grt_ops} ops", grt_after - grt_before);
+        }
+
+        let now = perf_counter_us();
+        if now - last_progress >= PROGRESS_INTERVAL_US {
+            last_progress = now;
+            println!(
+                "[wdk-mutex-test] [i] Soak: {} s / {} s, {rounds} rounds, {} ops, {} violations.",
+                (now - start) / 1_000_000,
+                ctx.soak_seconds,
+                stats.ops.load(Ordering::SeqCst),
+                stats.violations.load(Ordering::SeqCst),
+            );
+        }
+    }
+
+    let ops = stats.ops.load(Ordering::SeqCst);
+    let violations = stats.violations.load(Ordering::SeqCst);
+    println!(
+        "[wdk-mutex-test] [i] Soak done: {rounds} rounds, {ops} ops ({} direct, {} Grt), {violations} invariant violations.",
+        stats.direct_ops.load(Ordering::SeqCst),
+        stats.grt_ops.load(Ordering::SeqCst),
+    );
+
+    runner::metric("ops", ops, "ops");
+    runner::metric("rounds", rounds, "rounds");
+
+    if violations != 0 {
+        return Err(TestError::fail(format!("{violations} invariant violations in {ops} ops over {rounds} rounds")));
+    }
+
+    Ok(())
+}
+
+fn soak_thread<P: LockPrimitive>(direct: &P::Mutex<Pair>, grt_key: &'static str, stats: &Stats, ops: u64, grt_percent: u64) {
+    let mut rng = Rng::new(stats.seed.fetch_add(0x9E37_79B9_7F4A_7C15, Ordering::SeqCst) ^ perf_counter_us());
+
+    for _ in 0..ops {
+        let use_grt = rng.range(1, 100) <= grt_percent;
+        let (m, id) = if use_grt {
+            record_progress("getting the mutex from the Grt");
+            match P::grt_get::<Pair>(grt_key) {
+                Ok(m) => (m, LockId::Key(grt_key)),
+                Err(e) => {
+                    println!("[wdk-mutex-test] [-] Soak thread lost the Grt mutex: {e:?}");
+                    stats.violations.fetch_add(1, Ordering::SeqCst);
+                    return;
+                },
+            }
+        } else {
+            (direct, LockId::of(direct))
+        };
+
+        let hold = rng.range(0, MAX_HOLD_SPINS);
+        record_progress("locking");
+        let mut pair = match lock_tracking::lock(id, || P::lock(m)) {
+            Ok(guard) => guard,
+            Err(e) => {
+                println!("[wdk-mutex-test] [-] Soak thread failed to lock: {e:?}");
+                stats.violations.fetch_add(1, Ordering::SeqCst);
+                return;
+            },
+        };
+
+        if pair.a != pair.b {
+            stats.violations.fetch_add(1, Ordering::SeqCst);
+        }
+        pair.a += 1;
+        for _ in 0..hold {
+            spin_loop();
+        }
+        pair.b += 1;
+        drop(pair);
+
+        stats.ops.fetch_add(1, Ordering::SeqCst);
+        if use_grt {
+            stats.grt_ops.fetch_add(1, Ordering::SeqCst);
+        } else {
+            stats.direct_ops.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
@@ -0,0 +1,63 @@
+//! Undo actions for driver initialisation.
+//!
+//! `DriverEntry` sets up in steps: parameters, the Grt, the device, its symbolic link, and finally
+//! the stored report. Each step that succeeds pushes the action that undoes it onto a [`Teardown`].
+//! Dropping the teardown runs those actions newest first, so an early return from any step unwinds
+//! exactly what was done before it, and a failed `DriverEntry`, after which `DriverUnload` never
+//! runs, leaves nothing behind.
+//!
+//! Once `DriverEntry` succeeds the teardown is [`install`]ed, and `DriverUnload` runs it through
+//! [`run_installed`], so load failure and unload share the same undo code.
+
+use core::{ptr::null_mut, sync::atomic::{AtomicPtr, Ordering}};
+
+use alloc::{boxed::Box, vec::Vec};
+
+use crate::println;
+
+/// Undoes one initialisation step.
+type Undo = Box<dyn FnOnce()>;
+
+/// Undo actions of the initialisation steps done so far, run in reverse when dropped.
+pub struct Teardown {
+    steps: Vec<(&'static str, Undo)>,
+}
+
+impl Teardown {
+    pub fn new() -> Self {
+        Self { steps: Vec::new() }
+    }
+
+    /// Record that step `name` succeeded and is undone by `undo`.
+    pub fn push(&mut self, name: &'static str, undo: impl FnOnce() + 'static) {
+        self.steps.push((name, Box::new(undo)));
+    }
+}
+
+impl Drop for Teardown {
+    fn drop(&mut self) {
+        while let Some((name, undo)) = self.steps.pop() {
+            println!("[wdk-mutex-test] [i] Undoing {name}.");
+            undo();
+        }
+    }
+}
+
+/// The teardown of a successful `DriverEntry`, see [`install`].
+static INSTALLED: AtomicPtr<Teardown> = AtomicPtr::new(null_mut());
+
+/// Keep `teardown` to be run at unload, called once `DriverEntry` can no longer fail.
+pub fn install(teardown: Teardown) {
+    let old = INSTALLED.swap(Box::into_raw(Box::new(teardown)), Ordering::SeqCst);
+    if !old.is_null() {
+        drop(unsafe { Box::from_raw(old) });
+    }
+}
+
+/// Undo everything the installed teardown recorded, called from `DriverUnload`.
+pub fn run_installed() {
+    let teardown = INSTALLED.swap(null_mut(), Ordering::SeqCst);
+    if !teardown.is_null() {
+        drop(unsafe { Box::from_raw(teardown) });
+    }
+}
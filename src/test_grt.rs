@@ -0,0 +1,148 @@
+//! Error paths of the global registry, [`Grt`].
+//!
+//! Each test asserts the specific [`GrtError`] variant returned for a misuse, not just that the
+//! call failed. Keys come from the test's [`GrtScope`](crate::grt_scope::GrtScope).
+
+use alloc::format;
+
+use crate::{registry::{Suite, TestCase, TestContext, TestError, TestResult}, wdk_mutex::{errors::GrtError, grt::Grt}};
+
+/// Tests registered with the runner, see [`crate::registry`].
+pub static TESTS: &[TestCase] = &[
+    TestCase {
+        name: "grt::get_missing_key",
+        suite: Suite::Grt,
+        tags: &["errors"],
+        run: test_get_missing_key,
+    },
+    TestCase {
+        name: "grt::get_wrong_type",
+        suite: Suite::Grt,
+        tags: &["errors"],
+        run: test_get_wrong_type,
+    },
+    TestCase {
+        name: "grt::get_wrong_primitive",
+        suite: Suite::Grt,
+        tags: &["errors"],
+        run: test_get_wrong_primitive,
+    },
+    TestCase {
+        name: "grt::duplicate_register",
+        suite: Suite::Grt,
+        tags: &["errors"],
+        run: test_duplicate_register,
+    },
+    TestCase {
+        name: "grt::use_after_destroy",
+        suite: Suite::Grt,
+        tags: &["errors", "destructive"],
+        run: test_use_after_destroy,
+    },
+];
+
+/// Fail the test unless `$call` returned `Err` matching `$expected`.
+macro_rules! assert_grt_error {
+    ($call:expr, $expected:pat) => {
+        match $call {
+            Err($expected) => {},
+            Err(e) => return Err($crate::registry::TestError::fail(alloc::format!(
+                "{}: expected {}, got {e:?}", stringify!($call), stringify!($expected),
+            ))),
+            Ok(_) => return Err($crate::registry::TestError::fail(alloc::format!(
+                "{}: expected {}, got Ok", stringify!($call), stringify!($expected),
+            ))),
+        }
+    };
+}
+pub(crate) use assert_grt_error;
+
+/// Destroy the Grt, run `checks` against the destroyed registry, then initialise it again.
+///
+/// Every registration the test made so far is lost. The Grt is usable again on return whatever
+/// `checks` found.
+pub(crate) fn while_destroyed(checks: impl FnOnce() -> TestResult) -> TestResult {
+    // nothing obtained from the Grt is held across this call
+    unsafe { Grt::destroy() }?;
+
+    let result = checks();
+    Grt::init()?;
+
+    result
+}
+
+/// `get_*` on a key that was never registered.
+fn test_get_missing_key(ctx: &TestContext) -> TestResult {
+    let (present, missing) = (ctx.grt.key("present"), ctx.grt.key("missing"));
+
+    // keep the table non-empty, so the lookup itself is what fails
+    Grt::register_kmutex(present, 0u32)?;
+
+    assert_grt_error!(Grt::get_kmutex::<u32>(missing), GrtError::KeyNotFound);
+    assert_grt_error!(Grt::get_fast_mutex::<u32>(missing), GrtError::KeyNotFound);
+
+    Ok(())
+}
+
+/// `get_*` with a different `T` than the key was registered with.
+fn test_get_wrong_type(ctx: &TestContext) -> TestResult {
+    let (kmutex_u32, fast_mutex_u32) = (ctx.grt.key("kmutex_u32"), ctx.grt.key("fast_mutex_u32"));
+
+    Grt::register_kmutex(kmutex_u32, 0u32)?;
+    Grt::register_fast_mutex(fast_mutex_u32, 0u32)?;
+
+    assert_grt_error!(Grt::get_kmutex::<u64>(kmutex_u32), GrtError::DowncastError);
+    assert_grt_error!(Grt::get_fast_mutex::<u64>(fast_mutex_u32), GrtError::DowncastError);
+
+    // the right type still works after a failed downcast
+    *Grt::get_kmutex::<u32>(kmutex_u32)?.lock()? += 1;
+
+    Ok(())
+}
+
+/// `get_*` for the other primitive than the key was registered as.
+fn test_get_wrong_primitive(ctx: &TestContext) -> TestResult {
+    let (as_kmutex, as_fast_mutex) = (ctx.grt.key("as_kmutex"), ctx.grt.key("as_fast_mutex"));
+
+    Grt::register_kmutex(as_kmutex, 0u32)?;
+    Grt::register_fast_mutex(as_fast_mutex, 0u32)?;
+
+    assert_grt_error!(Grt::get_fast_mutex::<u32>(as_kmutex), GrtError::DowncastError);
+    assert_grt_error!(Grt::get_kmutex::<u32>(as_fast_mutex), GrtError::DowncastError);
+
+    Ok(())
+}
+
+/// `register_*` on a key that is already taken, by either primitive.
+fn test_duplicate_register(ctx: &TestContext) -> TestResult {
+    let duplicate = ctx.grt.key("duplicate");
+
+    Grt::register_kmutex(duplicate, 1u32)?;
+
+    assert_grt_error!(Grt::register_kmutex(duplicate, 2u32), GrtError::KeyExists);
+    assert_grt_error!(Grt::register_fast_mutex(duplicate, 3u32), GrtError::KeyExists);
+
+    // the original registration is untouched
+    let val = *Grt::get_kmutex::<u32>(duplicate)?.lock()?;
+    if val != 1 {
+        return Err(TestError::fail(format!("expected the first registration's 1, got {val}")));
+    }
+
+    Ok(())
+}
+
+/// Every call after `Grt::destroy`, until the next `Grt::init`.
+fn test_use_after_destroy(ctx: &TestContext) -> TestResult {
+    let destroyed = ctx.grt.key("destroyed");
+
+    Grt::register_kmutex(destroyed, 0u32)?;
+
+    while_destroyed(|| {
+        assert_grt_error!(Grt::get_kmutex::<u32>(destroyed), GrtError::GrtIsNull);
+        assert_grt_error!(Grt::get_fast_mutex::<u32>(destroyed), GrtError::GrtIsNull);
+        assert_grt_error!(Grt::register_kmutex(destroyed, 0u32), GrtError::GrtIsNull);
+        assert_grt_error!(Grt::register_fast_mutex(destroyed, 0u32), GrtError::GrtIsNull);
+        assert_grt_error!(unsafe { Grt::destroy() }, GrtError::GrtIsNull);
+        Ok(())
+    })
+}
@@ -0,0 +1,165 @@
+//! Lifecycle of the global registry, [`Grt`]: the edges of `init` and `destroy`.
+//!
+//! Expected outcomes, each asserted by the test of the same name:
+//!
+//! - `init_twice`: the second `init` returns `GrtAlreadyExists` and existing registrations survive.
+//! - `reinit_after_destroy`: `init` after `destroy` succeeds with an empty registry, so earlier
+//!   keys are `KeyNotFound` and can be registered again.
+//! - `destroy_drops_payloads`: `destroy` drops every registered payload exactly once.
+//! - `destroy_with_live_guard`: `destroy` does not wait for guards, it returns `Ok` while a worker
+//!   still holds one. Releasing that guard afterwards touches freed memory, which is undefined
+//!   behaviour. The test is skipped unless `Parameters\AllowUnsound` is set, and is meant to be
+//!   run under Driver Verifier with special pool, which should catch the release.
+//!
+//! Apart from `init_twice`, every test here destroys the Grt and initialises it again. Their keys
+//! come from the test's [`GrtScope`](crate::grt_scope::GrtScope), so only that test's
+//! registrations are at stake.
+
+use core::{hint::spin_loop, sync::atomic::{AtomicBool, AtomicUsize, Ordering}};
+
+use alloc::{format, sync::Arc};
+
+use crate::{kernel::perf_counter_us, lock_tracking::{self, LockId}, registry::{Suite, TestCase, TestContext, TestError, TestResult}, test_grt::{assert_grt_error, while_destroyed}, threads::SystemThreadGroup, wdk_mutex::{errors::GrtError, grt::Grt}, workers::record_progress};
+
+/// Tests registered with the runner, see [`crate::registry`].
+pub static TESTS: &[TestCase] = &[
+    TestCase {
+        name: "grt::init_twice",
+        suite: Suite::Grt,
+        tags: &["lifecycle"],
+        run: test_init_twice,
+    },
+    TestCase {
+        name: "grt::reinit_after_destroy",
+        suite: Suite::Grt,
+        tags: &["lifecycle", "destructive"],
+        run: test_reinit_after_destroy,
+    },
+    TestCase {
+        name: "grt::destroy_drops_payloads",
+        suite: Suite::Grt,
+        tags: &["lifecycle", "destructive"],
+        run: test_destroy_drops_payloads,
+    },
+    TestCase {
+        name: "grt::destroy_with_live_guard",
+        suite: Suite::Grt,
+        tags: &["lifecycle", "destructive", "unsound"],
+        run: test_destroy_with_live_guard,
+    },
+];
+
+/// How long to wait for a worker to take its guard before giving up.
+const HANDSHAKE_TIMEOUT_US: u64 = 5_000_000;
+
+fn test_init_twice(ctx: &TestContext) -> TestResult {
+    let survivor = ctx.grt.key("survivor");
+    Grt::register_kmutex(survivor, 7u32)?;
+
+    assert_grt_error!(Grt::init(), GrtError::GrtAlreadyExists);
+
+    let val = *Grt::get_kmutex::<u32>(survivor)?.lock()?;
+    if val != 7 {
+        return Err(TestError::fail(format!("registration did not survive the second init, got {val}")));
+    }
+
+    Ok(())
+}
+
+fn test_reinit_after_destroy(ctx: &TestContext) -> TestResult {
+    let reinit = ctx.grt.key("reinit");
+    Grt::register_kmutex(reinit, 1u32)?;
+
+    while_destroyed(|| Ok(()))?;
+
+    assert_grt_error!(Grt::get_kmutex::<u32>(reinit), GrtError::KeyNotFound);
+    Grt::register_kmutex(reinit, 2u32)?;
+
+    let val = *Grt::get_kmutex::<u32>(reinit)?.lock()?;
+    if val != 2 {
+        return Err(TestError::fail(format!("expected the new registration's 2, got {val}")));
+    }
+
+    Ok(())
+}
+
+/// Number of [`DropCounted`] values dropped so far.
+static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+/// Payload that counts its drops in [`DROPS`].
+struct DropCounted;
+
+impl Drop for DropCounted {
+    fn drop(&mut self) {
+        DROPS.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+fn test_destroy_drops_payloads(ctx: &TestContext) -> TestResult {
+    let before = DROPS.load(Ordering::SeqCst);
+
+    Grt::register_kmutex(ctx.grt.key("drop_kmutex"), DropCounted)?;
+    Grt::register_fast_mutex(ctx.grt.key("drop_fast_mutex"), DropCounted)?;
+    if DROPS.load(Ordering::SeqCst) != before {
+        return Err(TestError::fail("payloads were dropped on registration"));
+    }
+
+    while_destroyed(|| Ok(()))?;
+
+    let dropped = DROPS.load(Ordering::SeqCst) - before;
+    if dropped != 2 {
+        return Err(TestError::fail(format!("expected 2 payloads dropped by destroy, got {dropped}")));
+    }
+
+    Ok(())
+}
+
+fn test_destroy_with_live_guard(ctx: &TestContext) -> TestResult {
+    if !ctx.allow_unsound {
+        return Err(TestError::skip("undefined behaviour, set Parameters\\AllowUnsound and run under Driver Verifier"));
+    }
+
+    let live_guard = ctx.grt.key("live_guard");
+    Grt::register_kmutex(live_guard, 0u32)?;
+
+    let holding = Arc::new(AtomicBool::new(false));
+    let release = Arc::new(AtomicBool::new(false));
+
+    let mut threads = SystemThreadGroup::new();
+    let (h, r) = (Arc::clone(&holding), Arc::clone(&release));
+    threads.spawn(1, move || {
+        let Ok(m) = Grt::get_kmutex::<u32>(live_guard) else {
+            return;
+        };
+        record_progress("locking");
+        let Ok(guard) = lock_tracking::lock(LockId::Key(live_guard), || m.lock()) else {
+            return;
+        };
+        record_progress("holding the guard until released");
+        h.store(true, Ordering::SeqCst);
+        while !r.load(Ordering::SeqCst) {
+            spin_loop();
+        }
+        drop(guard);
+    }).map_err(|s| TestError::status("spawning threads", s))?;
+
+    let start = perf_counter_us();
+    while !holding.load(Ordering::SeqCst) {
+        if perf_counter_us() - start > HANDSHAKE_TIMEOUT_US {
+            release.store(true, Ordering::SeqCst);
+            return Err(TestError::fail("worker never took its guard"));
+        }
+        spin_loop();
+    }
+
+    let destroyed = unsafe { Grt::destroy() };
+
+    // the guard is released into freed memory here, Driver Verifier should stop the machine
+    release.store(true, Ordering::SeqCst);
+    let joined = threads.join_all();
+    // restored before anything can fail, the tests after this one need a Grt
+    Grt::init()?;
+    joined.map_err(|s| TestError::status("joining threads", s))?;
+
+    destroyed.map_err(|e| TestError::fail(format!("destroy with a live guard returned {e:?}, expected Ok")))
+}
@@ -0,0 +1,139 @@
+//! Nesting a `KMutex` and a `FastMutex`, consistently and not, to check the lock-order checker, see
+//! [`crate::lock_order`].
+//!
+//! Expected outcomes, each asserted by the test of the same name:
+//!
+//! - `consistent`: `ctx.threads` threads each take a `FastMutex` inside a `KMutex`,
+//!   `ctx.iterations` times. The ordering is recorded and no inversion is reported.
+//! - `abba`: as `consistent`, then, once those threads are joined, as many take the `KMutex` inside
+//!   the `FastMutex`. The two groups never run together, so nothing can deadlock, yet the first
+//!   thread of the second group to nest them is reported.
+//! - `cycle_of_three`: one group nests `a` then `b`, the next `b` then `c`, the last `c` then `a`,
+//!   closing a cycle through `b` which is reported.
+//!
+//! The mutexes are registered in the Grt so reports name them by key. The inversions `abba` and
+//! `cycle_of_three` cause on purpose are taken from the checker by the tests themselves, so they
+//! do not fail them.
+
+use alloc::format;
+
+use crate::{config::MAX_ROUNDS, conformance::LockPrimitive, lock_order, lock_tracking::{self, LockId}, registry::{Suite, TestCase, TestContext, TestError, TestResult}, test_fast_mutex::FastMutexPrimitive, test_kmutex::KMutexPrimitive, threads::SystemThreadGroup, workers::record_progress};
+
+/// Tests registered with the runner, see [`crate::registry`].
+pub static TESTS: &[TestCase] = &[
+    TestCase {
+        name: "lock_order::consistent",
+        suite: Suite::LockOrder,
+        tags: &["multithread"],
+        run: test_consistent,
+    },
+    TestCase {
+        name: "lock_order::abba",
+        suite: Suite::LockOrder,
+        tags: &["multithread"],
+        run: test_abba,
+    },
+    TestCase {
+        name: "lock_order::cycle_of_three",
+        suite: Suite::LockOrder,
+        tags: &["multithread"],
+        run: test_cycle_of_three,
+    },
+];
+
+type K = KMutexPrimitive;
+type F = FastMutexPrimitive;
+
+/// Thread body: take `outer`, a `P`, then `inner`, a `Q`, `iterations` times, counting each pass in
+/// both.
+fn nest<P: LockPrimitive, Q: LockPrimitive>(outer: &'static str, inner: &'static str, iterations: u32) {
+    let (m, n) = (P::grt_get::<u32>(outer).unwrap(), Q::grt_get::<u32>(inner).unwrap());
+
+    for _ in 0..iterations {
+        record_progress("locking the outer mutex");
+        let mut o = lock_tracking::lock(LockId::Key(outer), || P::lock(m)).unwrap();
+        record_progress("locking the inner mutex");
+        let mut i = lock_tracking::lock(LockId::Key(inner), || Q::lock(n)).unwrap();
+        *o += 1;
+        *i += 1;
+    }
+}
+
+/// Run [`nest`] on `ctx.threads` threads and join them.
+fn nested<P: LockPrimitive, Q: LockPrimitive>(ctx: &TestContext, outer: &'static str, inner: &'static str) -> TestResult {
+    let iterations = ctx.iterations;
+    let mut threads = SystemThreadGroup::new();
+    threads.spawn(ctx.threads, move || nest::<P, Q>(outer, inner, iterations))
+        .map_err(|s| TestError::status("spawning threads", s))?;
+    threads.join_all().map_err(|s| TestError::status("joining threads", s))?;
+    Ok(())
+}
+
+/// Fail unless the counter in `P` mutex `key` is `expected`.
+fn expect_count<P: LockPrimitive>(key: &'static str, expected: u32) -> TestResult {
+    let count = *P::lock(P::grt_get::<u32>(key)?)?;
+    if count != expected {
+        return Err(TestError::fail(format!("expected {key} to count {expected}, got {count}")));
+    }
+    Ok(())
+}
+
+/// Take the checker's report, failing unless it is exactly one inversion of `taking` while
+/// holding `holding`, through `through` other mutexes.
+fn expect_inversion(taking: &'static str, holding: &'static str, through: usize) -> TestResult {
+    let Some(report) = lock_order::take() else {
+        return Err(TestError::fail(format!("taking {taking} while holding {holding} was not reported")));
+    };
+
+    let first = report.first;
+    if report.count != 1 || first.taking != LockId::Key(taking) || first.holding != LockId::Key(holding) || first.through != through {
+        return Err(TestError::fail(format!(
+            "expected only {taking} taken while holding {holding} through {through} other mutex(es) to be reported, got: {report}",
+        )));
+    }
+    Ok(())
+}
+
+fn test_consistent(ctx: &TestContext) -> TestResult {
+    let (a, b) = (ctx.grt.key("a"), ctx.grt.key("b"));
+    K::grt_register(a, 0u32)?;
+    F::grt_register(b, 0u32)?;
+
+    nested::<K, F>(ctx, a, b)?;
+
+    if !lock_order::ordered(LockId::Key(a), LockId::Key(b)) {
+        return Err(TestError::fail(format!("{b} taken while holding {a} was not recorded")));
+    }
+    if let Some(report) = lock_order::take() {
+        return Err(TestError::fail(format!("unexpected lock order inversion: {report}")));
+    }
+
+    expect_count::<K>(a, ctx.expected_total())?;
+    expect_count::<F>(b, ctx.expected_total())
+}
+
+fn test_abba(ctx: &TestContext) -> TestResult {
+    let (a, b) = (ctx.grt.key("a"), ctx.grt.key("b"));
+    K::grt_register(a, 0u32)?;
+    F::grt_register(b, 0u32)?;
+
+    nested::<K, F>(ctx, a, b)?;
+    nested::<F, K>(ctx, b, a)?;
+    expect_inversion(a, b, 0)?;
+
+    // both orders still ran to completion
+    expect_count::<K>(a, MAX_ROUNDS * ctx.expected_total())?;
+    expect_count::<F>(b, MAX_ROUNDS * ctx.expected_total())
+}
+
+fn test_cycle_of_three(ctx: &TestContext) -> TestResult {
+    let (a, b, c) = (ctx.grt.key("a"), ctx.grt.key("b"), ctx.grt.key("c"));
+    K::grt_register(a, 0u32)?;
+    F::grt_register(b, 0u32)?;
+    K::grt_register(c, 0u32)?;
+
+    nested::<K, F>(ctx, a, b)?;
+    nested::<F, K>(ctx, b, c)?;
+    nested::<K, K>(ctx, c, a)?;
+    expect_inversion(a, c, 1)
+}
@@ -0,0 +1,289 @@
+//! Spawning and joining groups of system threads.
+//!
+//! Every multithreaded test follows the same pattern: start a few system threads on one closure,
+//! wait for all of them, then check the shared state. [`SystemThreadGroup`] owns that sequence so
+//! a test cannot silently run with fewer threads than it asked for, or return before its threads
+//! have finished with state it is about to free.
+//!
+//! Joins are bounded by the deadline the runner sets for each test, see [`set_join_deadline`].
+//! Threads still running past it are abandoned rather than waited for: the join fails with
+//! `STATUS_TIMEOUT`, what the threads last recorded is kept for the runner to report through
+//! [`take_timeout`], and the threads stay referenced until [`join_abandoned`] waits for them at
+//! unload. Whatever they share with the test is kept alive by the `Arc`s they hold.
+
+use core::{cell::UnsafeCell, ffi::c_void, fmt::Write, hint::spin_loop, mem, ptr::null_mut, sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, Ordering}};
+
+use alloc::{boxed::Box, format, string::String, sync::Arc, vec::Vec};
+
+use crate::{kernel::{self, nt_success, perf_counter_us, APC_LEVEL, HANDLE, NTSTATUS, PVOID, STATUS_INVALID_DEVICE_STATE, STATUS_TIMEOUT}, println, workers};
+
+/// Start context of every thread spawned by a [`SystemThreadGroup`], owned by the thread.
+struct ThreadStart {
+    body: Arc<dyn Fn() + Send + Sync>,
+    /// The thread's slot in [`workers`], if there was one free.
+    slot: Option<usize>,
+}
+
+/// Start routine of every group thread: takes ownership of its [`ThreadStart`], runs the body, and
+/// frees the context when the thread exits.
+unsafe extern "C" fn thread_start(context: *mut c_void) {
+    let start = unsafe { Box::from_raw(context as *mut ThreadStart) };
+    if let Some(slot) = start.slot {
+        workers::start(slot);
+    }
+
+    (start.body)();
+
+    if let Some(slot) = start.slot {
+        workers::exit(slot);
+    }
+}
+
+/// What a group holds a thread by.
+enum Waitable {
+    /// A referenced thread object, the handle closed once the reference was taken.
+    Object(PVOID),
+    /// The thread's kernel handle, kept when no reference could be taken through it.
+    Handle(HANDLE),
+}
+
+/// A thread of a group and its slot in [`workers`].
+struct GroupThread {
+    thread: Waitable,
+    slot: Option<usize>,
+}
+
+impl GroupThread {
+    /// Wait for the thread to exit, see [`kernel::ke_wait_for_single_object`].
+    fn wait(&self, timeout_us: Option<u64>) -> NTSTATUS {
+        match self.thread {
+            Waitable::Object(object) => unsafe { kernel::ke_wait_for_single_object(object, timeout_us) },
+            Waitable::Handle(handle) => unsafe { kernel::zw_wait_for_single_object(handle, timeout_us) },
+        }
+    }
+
+    /// Let go of a thread that has exited, or that can never be waited on.
+    fn release(self) {
+        match self.thread {
+            Waitable::Object(object) => unsafe { kernel::ob_dereference_object(object) },
+            Waitable::Handle(handle) => unsafe { let _ = kernel::zw_close(handle); },
+        }
+        if let Some(slot) = self.slot {
+            workers::release(slot);
+        }
+    }
+
+    /// What the thread last recorded, as a line for a report.
+    fn describe(&self) -> String {
+        match self.slot {
+            Some(slot) => format!("{}", workers::get(slot)),
+            None => String::from("a thread with no slot to record its progress in"),
+        }
+    }
+}
+
+/// A set of running system threads, joined when the group is dropped.
+///
+/// Each thread is held by a referenced thread object rather than its handle, the handle is closed
+/// as soon as the reference is taken. Should that fail, the kernel handle is kept instead, so the
+/// thread is still joined.
+pub struct SystemThreadGroup {
+    threads: Vec<GroupThread>,
+}
+
+impl SystemThreadGroup {
+    pub fn new() -> Self {
+        Self { threads: Vec::new() }
+    }
+
+    /// Start `n` system threads, each running `body` once.
+    ///
+    /// `body` is shared by the threads, so state they all work on is captured by the closure,
+    /// typically through an `Arc`. No test needs a global to reach its threads.
+    ///
+    /// Stops at the first thread that cannot be created and returns its status. Threads started
+    /// before the failure stay in the group and are joined as usual.
+    pub fn spawn<F>(&mut self, n: usize, body: F) -> Result<(), NTSTATUS>
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        let body: Arc<dyn Fn() + Send + Sync> = Arc::new(body);
+
+        for _ in 0..n {
+            let slot = workers::reserve();
+            let start = Box::into_raw(Box::new(ThreadStart { body: Arc::clone(&body), slot }));
+
+            let mut handle: HANDLE = null_mut();
+            let status = unsafe { kernel::ps_create_system_thread(&mut handle, thread_start, start as PVOID) };
+            if !nt_success(status) {
+                // the thread never ran, so the start context and the slot are still ours
+                drop(unsafe { Box::from_raw(start) });
+                if let Some(slot) = slot {
+                    workers::release(slot);
+                }
+                return Err(status);
+            }
+
+            let mut thread_obj: PVOID = null_mut();
+            let status = unsafe { kernel::ob_reference_object_by_handle(handle, &mut thread_obj) };
+            let thread = if nt_success(status) {
+                unsafe { let _ = kernel::zw_close(handle); };
+                Waitable::Object(thread_obj)
+            } else {
+                // the thread is running regardless, and a kernel handle can be waited on as well
+                println!("[wdk-mutex-test] [i] Holding a thread by its handle, referencing it failed with {status:#x}");
+                Waitable::Handle(handle)
+            };
+
+            self.threads.push(GroupThread { thread, slot });
+        }
+
+        Ok(())
+    }
+
+    /// Wait for every thread in the group to exit, returning how many were joined.
+    ///
+    /// Waiting is only legal at IRQL <= APC_LEVEL. Above that no thread is waited on and
+    /// `STATUS_INVALID_DEVICE_STATE` is returned, the threads stay in the group.
+    ///
+    /// Threads still running at the join deadline are abandoned, see the module documentation,
+    /// and `STATUS_TIMEOUT` is returned once the others have been joined.
+    pub fn join_all(&mut self) -> Result<usize, NTSTATUS> {
+        let irql = kernel::ke_get_current_irql();
+        if irql > APC_LEVEL {
+            return Err(STATUS_INVALID_DEVICE_STATE);
+        }
+
+        let deadline = JOIN_DEADLINE_US.load(Ordering::SeqCst);
+        let total = self.threads.len();
+        let mut hung = Vec::new();
+        for thread in self.threads.drain(..) {
+            // past the deadline the remaining threads are only checked, not waited for
+            let timeout = (deadline != 0).then(|| deadline.saturating_sub(perf_counter_us()));
+            let status = thread.wait(timeout);
+            if status == STATUS_TIMEOUT {
+                hung.push(thread);
+            } else {
+                thread.release();
+            }
+        }
+
+        if hung.is_empty() {
+            return Ok(total);
+        }
+
+        let mut reason = format!("{} of {total} thread(s) still running", hung.len());
+        for thread in &hung {
+            let worker = thread.describe();
+            println!("[wdk-mutex-test] [-] Abandoning {worker}");
+            let _ = write!(reason, "; {worker}");
+        }
+        store_timeout(reason);
+        with_abandoned(|abandoned| abandoned.append(&mut hung));
+
+        Err(STATUS_TIMEOUT)
+    }
+}
+
+impl Default for SystemThreadGroup {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for SystemThreadGroup {
+    fn drop(&mut self) {
+        if self.threads.is_empty() {
+            return;
+        }
+
+        // threads given up on at the deadline have already left the group
+        if self.join_all().is_err() && !self.threads.is_empty() {
+            // nothing can be waited on here, release the references and leave the threads running
+            println!(
+                "[wdk-mutex-test] [-] Dropping {} unjoined thread(s) at IRQL {}",
+                self.threads.len(),
+                kernel::ke_get_current_irql(),
+            );
+            for thread in self.threads.drain(..) {
+                thread.release();
+            }
+        }
+    }
+}
+
+/// When the running test's threads must have exited by, in [`perf_counter_us`] time, 0 for no
+/// deadline.
+static JOIN_DEADLINE_US: AtomicU64 = AtomicU64::new(0);
+
+/// Bound every join until the next call by `deadline_us`, in [`perf_counter_us`] time, or lift the
+/// bound with `None`. Set by the runner around each test.
+pub fn set_join_deadline(deadline_us: Option<u64>) {
+    JOIN_DEADLINE_US.store(deadline_us.unwrap_or(0), Ordering::SeqCst);
+}
+
+/// What the last join that gave up found, see [`take_timeout`].
+static TIMEOUT: AtomicPtr<String> = AtomicPtr::new(null_mut());
+
+fn store_timeout(reason: String) {
+    let old = TIMEOUT.swap(Box::into_raw(Box::new(reason)), Ordering::SeqCst);
+    if !old.is_null() {
+        drop(unsafe { Box::from_raw(old) });
+    }
+}
+
+/// Which threads the last join that gave up left running and what they last recorded, or `None`
+/// if no join has given up since the previous call.
+pub fn take_timeout() -> Option<String> {
+    let reason = TIMEOUT.swap(null_mut(), Ordering::SeqCst);
+    (!reason.is_null()).then(|| *unsafe { Box::from_raw(reason) })
+}
+
+/// Threads joins gave up on, waiting for [`join_abandoned`].
+struct Abandoned {
+    lock: AtomicBool,
+    threads: UnsafeCell<Vec<GroupThread>>,
+}
+
+// threads is only reached under lock
+unsafe impl Sync for Abandoned {}
+
+static ABANDONED: Abandoned = Abandoned {
+    lock: AtomicBool::new(false),
+    threads: UnsafeCell::new(Vec::new()),
+};
+
+/// Only ever held briefly and at PASSIVE_LEVEL or APC_LEVEL, like the report lock.
+fn with_abandoned<R>(f: impl FnOnce(&mut Vec<GroupThread>) -> R) -> R {
+    while ABANDONED.lock
+        .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+        spin_loop();
+    }
+    let r = f(unsafe { &mut *ABANDONED.threads.get() });
+    ABANDONED.lock.store(false, Ordering::Release);
+    r
+}
+
+/// Number of threads abandoned by joins and not yet waited for.
+pub fn abandoned() -> usize {
+    with_abandoned(|threads| threads.len())
+}
+
+/// Wait, with no deadline, for every abandoned thread, returning how many there were.
+///
+/// Called at unload, as the driver's code cannot go away under a thread still running it. A
+/// thread that never exits keeps unload waiting, which a debugger can still be attached to,
+/// rather than bugchecking the machine once it runs on into unloaded code.
+pub fn join_abandoned() -> usize {
+    let threads = with_abandoned(mem::take);
+    let count = threads.len();
+    for thread in threads {
+        println!("[wdk-mutex-test] [i] Waiting for abandoned {}", thread.describe());
+        let _ = thread.wait(None);
+        thread.release();
+    }
+
+    count
+}
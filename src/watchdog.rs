@@ -0,0 +1,217 @@
+//! Notices a run that has stopped making progress and prints what every worker is doing.
+//!
+//! Every [`PERIOD_MS`] a timer DPC queues a work item which, at PASSIVE_LEVEL, adds up the steps
+//! and lock acquisitions of every live worker, see [`crate::workers`]. When that total has not
+//! moved for `WatchdogSeconds` while workers are alive, it prints a snapshot: the running test,
+//! then for each worker its progress and the mutexes it is waiting for and holding, by Grt key or
+//! address. One snapshot is printed per stall, any progress re-arms it.
+//!
+//! The watchdog only reports, it never interrupts a test; giving up on hung threads is left to the
+//! join deadline, see [`crate::threads`]. Under host-sim a `std` thread stands in for the timer and
+//! the work item.
+
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use crate::{kernel::perf_counter_us, println, runner, workers};
+
+/// How often the workers are looked at.
+pub const PERIOD_MS: u32 = 1_000;
+
+/// How long the workers may go without progress before a snapshot is printed, 0 when stopped.
+static STALL_US: AtomicU64 = AtomicU64::new(0);
+
+/// Total progress of the live workers when last looked at.
+static LAST_TOTAL: AtomicU64 = AtomicU64::new(0);
+
+/// When [`LAST_TOTAL`] last changed, in [`perf_counter_us`] time.
+static LAST_CHANGE_US: AtomicU64 = AtomicU64::new(0);
+
+/// Set once the current stall has been reported.
+static REPORTED: AtomicBool = AtomicBool::new(false);
+
+/// Look at the workers once, printing a snapshot if they have stalled. Returns whether one was
+/// printed. Called from the work item, only ever one at a time.
+pub fn check() -> bool {
+    let stall_us = STALL_US.load(Ordering::SeqCst);
+    let now = perf_counter_us();
+
+    let (mut live, mut total) = (0usize, 0u64);
+    workers::for_each_live(|w| {
+        live += 1;
+        total += w.progress.steps + w.locks.acquired;
+    });
+
+    if live == 0 || LAST_TOTAL.swap(total, Ordering::SeqCst) != total {
+        LAST_CHANGE_US.store(now, Ordering::SeqCst);
+        REPORTED.store(false, Ordering::SeqCst);
+        return false;
+    }
+
+    let stalled_us = now.saturating_sub(LAST_CHANGE_US.load(Ordering::SeqCst));
+    if stall_us == 0 || stalled_us < stall_us || REPORTED.swap(true, Ordering::SeqCst) {
+        return false;
+    }
+
+    println!(
+        "[wdk-mutex-test] [-] Watchdog: no progress for {} s running {}, {live} worker(s):",
+        stalled_us / 1_000_000,
+        runner::current_test().map_or("no test", |t| t.name),
+    );
+    workers::for_each_live(|w| println!("[wdk-mutex-test] [-] Watchdog:   {w}"));
+    true
+}
+
+/// Arm the stall detection, starting from no progress seen.
+fn arm(stall_seconds: u32) {
+    LAST_TOTAL.store(0, Ordering::SeqCst);
+    LAST_CHANGE_US.store(perf_counter_us(), Ordering::SeqCst);
+    REPORTED.store(false, Ordering::SeqCst);
+    STALL_US.store(stall_seconds as u64 * 1_000_000, Ordering::SeqCst);
+}
+
+#[cfg(feature = "driver")]
+pub use self::driver::{start, stop};
+#[cfg(feature = "host-sim")]
+pub use self::sim::{start, stop};
+
+#[cfg(feature = "driver")]
+mod driver {
+    use core::{mem, ptr::null_mut, sync::atomic::{AtomicBool, AtomicPtr, Ordering}};
+
+    use alloc::boxed::Box;
+    use wdk_sys::{ntddk::{IoAllocateWorkItem, IoFreeWorkItem, IoQueueWorkItem, KeCancelTimer, KeDelayExecutionThread, KeFlushQueuedDpcs, KeInitializeDpc, KeInitializeTimerEx, KeSetTimerEx}, FALSE, KDPC, KTIMER, LARGE_INTEGER, NTSTATUS, PDEVICE_OBJECT, PIO_WORKITEM, PVOID, STATUS_INSUFFICIENT_RESOURCES, _MODE::KernelMode, _TIMER_TYPE::NotificationTimer, _WORK_QUEUE_TYPE::DelayedWorkQueue};
+
+    use super::{arm, check, PERIOD_MS, STALL_US};
+    use crate::println;
+
+    /// The timer, its DPC and the work item the DPC queues, in non paged memory for as long as
+    /// the timer may fire.
+    struct Watchdog {
+        timer: KTIMER,
+        dpc: KDPC,
+        item: PIO_WORKITEM,
+        /// Set while the work item is queued or running, so it is never queued twice.
+        queued: AtomicBool,
+    }
+
+    /// The running watchdog, see [`start`].
+    static WATCHDOG: AtomicPtr<Watchdog> = AtomicPtr::new(null_mut());
+
+    /// Start looking at the workers every [`PERIOD_MS`], reporting stalls of `stall_seconds`.
+    /// 0 leaves the watchdog off.
+    ///
+    /// # Safety
+    ///
+    /// `device` is the driver's device object, it must outlive [`stop`].
+    pub unsafe fn start(device: PDEVICE_OBJECT, stall_seconds: u32) -> Result<(), NTSTATUS> {
+        if stall_seconds == 0 {
+            println!("[wdk-mutex-test] [i] WatchdogSeconds is 0, running without the watchdog.");
+            return Ok(());
+        }
+
+        let item = unsafe { IoAllocateWorkItem(device) };
+        if item.is_null() {
+            return Err(STATUS_INSUFFICIENT_RESOURCES);
+        }
+
+        let watchdog = Box::into_raw(Box::new(Watchdog {
+            timer: unsafe { mem::zeroed() },
+            dpc: unsafe { mem::zeroed() },
+            item,
+            queued: AtomicBool::new(false),
+        }));
+        WATCHDOG.store(watchdog, Ordering::SeqCst);
+        arm(stall_seconds);
+
+        unsafe {
+            KeInitializeTimerEx(&mut (*watchdog).timer, NotificationTimer);
+            KeInitializeDpc(&mut (*watchdog).dpc, Some(tick), watchdog as PVOID);
+
+            // relative due times are negative, in 100 ns units
+            let mut due = LARGE_INTEGER::default();
+            due.QuadPart = -(PERIOD_MS as i64 * 10_000);
+            KeSetTimerEx(&mut (*watchdog).timer, due, PERIOD_MS as i32, &mut (*watchdog).dpc);
+        }
+
+        Ok(())
+    }
+
+    /// Stop the watchdog, waiting for a queued check to finish. Called on driver unload.
+    pub fn stop() {
+        let watchdog = WATCHDOG.swap(null_mut(), Ordering::SeqCst);
+        if watchdog.is_null() {
+            return;
+        }
+
+        unsafe {
+            KeCancelTimer(&mut (*watchdog).timer);
+            // a DPC that already started may still queue the work item
+            KeFlushQueuedDpcs();
+
+            let mut interval = LARGE_INTEGER::default();
+            interval.QuadPart = -10 * 10_000;
+            while (*watchdog).queued.load(Ordering::SeqCst) {
+                let _ = KeDelayExecutionThread(KernelMode as i8, FALSE as u8, &mut interval);
+            }
+
+            IoFreeWorkItem((*watchdog).item);
+            drop(Box::from_raw(watchdog));
+        }
+        STALL_US.store(0, Ordering::SeqCst);
+    }
+
+    /// The timer DPC, at DISPATCH_LEVEL: hand the check to the work item.
+    unsafe extern "C" fn tick(_dpc: *mut KDPC, context: PVOID, _: PVOID, _: PVOID) {
+        let watchdog = unsafe { &*(context as *const Watchdog) };
+        if !watchdog.queued.swap(true, Ordering::SeqCst) {
+            unsafe { IoQueueWorkItem(watchdog.item, Some(work), DelayedWorkQueue, context) };
+        }
+    }
+
+    /// The work item, at PASSIVE_LEVEL.
+    unsafe extern "C" fn work(_device: PDEVICE_OBJECT, context: PVOID) {
+        let watchdog = unsafe { &*(context as *const Watchdog) };
+        check();
+        watchdog.queued.store(false, Ordering::SeqCst);
+    }
+}
+
+#[cfg(feature = "host-sim")]
+mod sim {
+    use core::sync::atomic::{AtomicBool, Ordering};
+    use std::{sync::Mutex, thread::{self, JoinHandle}, time::Duration};
+
+    use super::{arm, check, PERIOD_MS, STALL_US};
+
+    /// How often the simulated timer fires, shorter than the driver's to keep tests quick.
+    const SIM_PERIOD: Duration = Duration::from_millis(PERIOD_MS as u64 / 10);
+
+    static STOP: AtomicBool = AtomicBool::new(false);
+    static THREAD: Mutex<Option<JoinHandle<()>>> = Mutex::new(None);
+
+    /// Start looking at the workers on a `std` thread, reporting stalls of `stall_seconds`.
+    pub fn start(stall_seconds: u32) {
+        stop();
+        if stall_seconds == 0 {
+            return;
+        }
+
+        arm(stall_seconds);
+        STOP.store(false, Ordering::SeqCst);
+        *THREAD.lock().unwrap() = Some(thread::spawn(|| {
+            while !STOP.load(Ordering::SeqCst) {
+                thread::sleep(SIM_PERIOD);
+                check();
+            }
+        }));
+    }
+
+    /// Stop the watchdog thread, waiting for it to exit.
+    pub fn stop() {
+        STOP.store(true, Ordering::SeqCst);
+        if let Some(thread) = THREAD.lock().unwrap().take() {
+            let _ = thread.join();
+        }
+        STALL_US.store(0, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,283 @@
+//! What each group thread last reported, readable from any thread.
+//!
+//! A thread started by a [`SystemThreadGroup`] is given a slot here for as long as it belongs to
+//! the group. Its body calls [`record_progress`] before each step that could block, and locks
+//! taken through [`crate::lock_tracking`] record which mutex it waits for and which it holds, so
+//! when a join gives up on it or the [`crate::watchdog`] sees it stall, the harness can say which
+//! threads are still running, how far they got and what they were about to do.
+//!
+//! The slots are a fixed table rather than a list, so recording never allocates and a thread that
+//! is never joined keeps its slot without anything having to be freed under it.
+//!
+//! [`SystemThreadGroup`]: crate::threads::SystemThreadGroup
+
+use core::{cell::UnsafeCell, fmt, hint::spin_loop, sync::atomic::{AtomicBool, AtomicUsize, Ordering}};
+
+use crate::{kernel::{self, perf_counter_us, DISPATCH_LEVEL}, lock_tracking::LockId};
+
+/// Most group threads with a slot at once, abandoned ones included. Threads started past it run
+/// as usual but record nothing.
+pub const MAX_WORKERS: usize = 256;
+
+/// The last step a worker recorded.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Progress {
+    /// Steps recorded so far.
+    pub steps: u64,
+    /// What the last step was, e.g. `locking`.
+    pub note: &'static str,
+    /// When it was recorded, in [`perf_counter_us`] time.
+    pub at_us: u64,
+}
+
+/// Most mutexes a worker can be reported holding at once, more are counted but not named.
+pub const MAX_HELD: usize = 4;
+
+/// The mutexes a worker waits for and holds.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Locks {
+    /// Acquisitions so far, counted as progress alongside the steps.
+    pub acquired: u64,
+    pub waiting: Option<LockId>,
+    /// Held mutexes in the order they were taken.
+    pub held: [Option<LockId>; MAX_HELD],
+    /// Held mutexes beyond [`MAX_HELD`].
+    pub unnamed: u32,
+}
+
+impl Locks {
+    /// Held mutexes in the order they were taken, up to [`MAX_HELD`].
+    pub fn held(&self) -> impl Iterator<Item = LockId> + '_ {
+        self.held.iter().flatten().copied()
+    }
+}
+
+/// A snapshot of one worker's slot.
+#[derive(Debug, Clone, Copy)]
+pub struct Worker {
+    /// Id of the thread, 0 if it has not started yet.
+    pub thread_id: usize,
+    pub exited: bool,
+    pub progress: Progress,
+    pub locks: Locks,
+}
+
+impl fmt::Display for Worker {
+    /// e.g. ``thread 0x1a2c, 42 step(s), last `locking` 5003 ms ago, waiting for `t3::counter`,
+    /// holding 0xffffa30c1e2f5b40``
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "thread {:#x}", self.thread_id)?;
+        if self.exited {
+            f.write_str(" (exited)")?;
+        }
+        write!(f, ", {} step(s)", self.progress.steps)?;
+        if self.progress.steps != 0 {
+            let ago_ms = perf_counter_us().saturating_sub(self.progress.at_us) / 1_000;
+            write!(f, ", last `{}` {ago_ms} ms ago", self.progress.note)?;
+        }
+
+        if let Some(id) = self.locks.waiting {
+            write!(f, ", waiting for {id}")?;
+        }
+        let mut held = self.locks.held().peekable();
+        if held.peek().is_some() || self.locks.unnamed != 0 {
+            f.write_str(", holding")?;
+            for (i, id) in held.enumerate() {
+                write!(f, "{} {id}", if i == 0 { "" } else { "," })?;
+            }
+            if self.locks.unnamed != 0 {
+                write!(f, " and {} more", self.locks.unnamed)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Slot {
+    /// Set by the group that reserved the slot, cleared once its thread is joined.
+    claimed: AtomicBool,
+    /// Id of the thread, 0 until it starts.
+    thread_id: AtomicUsize,
+    exited: AtomicBool,
+    /// Serialises access to `state`. Held at `DISPATCH_LEVEL`, as the log lock is, so a holder
+    /// cannot be preempted by a thread that then spins on it.
+    lock: AtomicBool,
+    state: UnsafeCell<State>,
+}
+
+/// What a worker recorded, see [`Slot::with_state`].
+#[derive(Clone, Copy, Default)]
+struct State {
+    progress: Progress,
+    locks: Locks,
+}
+
+// state is only reached under the slot's lock
+unsafe impl Sync for Slot {}
+
+impl Slot {
+    const fn new() -> Self {
+        Self {
+            claimed: AtomicBool::new(false),
+            thread_id: AtomicUsize::new(0),
+            exited: AtomicBool::new(false),
+            lock: AtomicBool::new(false),
+            state: UnsafeCell::new(State {
+                progress: Progress { steps: 0, note: "", at_us: 0 },
+                locks: Locks { acquired: 0, waiting: None, held: [None; MAX_HELD], unnamed: 0 },
+            }),
+        }
+    }
+
+    /// Whether the slot belongs to a thread that has started and not yet exited.
+    fn is_live(&self) -> bool {
+        self.claimed.load(Ordering::Relaxed)
+            && self.thread_id.load(Ordering::Relaxed) != 0
+            && !self.exited.load(Ordering::Relaxed)
+    }
+
+    fn with_state<R>(&self, f: impl FnOnce(&mut State) -> R) -> R {
+        let raised = kernel::raise_irql(DISPATCH_LEVEL);
+        while self.lock
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            spin_loop();
+        }
+
+        let r = f(unsafe { &mut *self.state.get() });
+
+        self.lock.store(false, Ordering::Release);
+        drop(raised);
+        r
+    }
+}
+
+static SLOTS: [Slot; MAX_WORKERS] = [const { Slot::new() }; MAX_WORKERS];
+
+/// One past the highest slot ever claimed. Slots are claimed lowest first, so this stays at the
+/// most threads that were in groups at once and lookups only scan the slots below it.
+static SLOTS_USED: AtomicUsize = AtomicUsize::new(0);
+
+/// The slots that have ever been claimed, see [`SLOTS_USED`].
+fn used_slots() -> &'static [Slot] {
+    &SLOTS[..SLOTS_USED.load(Ordering::SeqCst)]
+}
+
+/// Claim a free slot for a thread about to be started, or `None` if every slot is taken.
+pub fn reserve() -> Option<usize> {
+    let slot = SLOTS.iter().position(|s| {
+        s.claimed.compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst).is_ok()
+    })?;
+    SLOTS_USED.fetch_max(slot + 1, Ordering::SeqCst);
+
+    SLOTS[slot].thread_id.store(0, Ordering::SeqCst);
+    SLOTS[slot].exited.store(false, Ordering::SeqCst);
+    SLOTS[slot].with_state(|s| *s = State::default());
+    Some(slot)
+}
+
+/// Make `slot` the current thread's, called first thing by the thread it was reserved for.
+pub fn start(slot: usize) {
+    SLOTS[slot].thread_id.store(kernel::current_thread_id(), Ordering::SeqCst);
+}
+
+/// Mark the thread of `slot` as done, called last thing by the thread itself.
+pub fn exit(slot: usize) {
+    SLOTS[slot].exited.store(true, Ordering::SeqCst);
+}
+
+/// Give `slot` back once its thread has been joined.
+pub fn release(slot: usize) {
+    SLOTS[slot].claimed.store(false, Ordering::SeqCst);
+}
+
+/// What the thread of `slot` last recorded.
+pub fn get(slot: usize) -> Worker {
+    let s = &SLOTS[slot];
+    let state = s.with_state(|s| *s);
+    Worker {
+        thread_id: s.thread_id.load(Ordering::SeqCst),
+        exited: s.exited.load(Ordering::SeqCst),
+        progress: state.progress,
+        locks: state.locks,
+    }
+}
+
+/// Call `f` with every worker that has started and not exited, abandoned ones included. Nothing
+/// is allocated, so it can run alongside a test without being charged to its leak check.
+pub fn for_each_live(mut f: impl FnMut(&Worker)) {
+    for (slot, s) in used_slots().iter().enumerate() {
+        if s.is_live() {
+            f(&get(slot));
+        }
+    }
+}
+
+/// Update the current thread's slot with `f`. Does nothing on a thread that does not belong to a
+/// group, returning `None`. Called on every step and lock event, so only the used slots are
+/// scanned rather than all [`MAX_WORKERS`].
+fn with_current<R>(f: impl FnOnce(&mut State) -> R) -> Option<R> {
+    let id = kernel::current_thread_id();
+    used_slots()
+        .iter()
+        .find(|s| s.is_live() && s.thread_id.load(Ordering::Relaxed) == id)
+        .map(|slot| slot.with_state(f))
+}
+
+/// The mutexes the current thread waits for and holds, `None` on a thread that does not belong
+/// to a group.
+pub(crate) fn current_locks() -> Option<Locks> {
+    with_current(|s| s.locks)
+}
+
+/// Record that the current thread is about to take step `note`, e.g. `locking`. Does nothing on
+/// a thread that does not belong to a group.
+pub fn record_progress(note: &'static str) {
+    let now = perf_counter_us();
+    with_current(|s| {
+        s.progress.steps += 1;
+        s.progress.note = note;
+        s.progress.at_us = now;
+    });
+}
+
+/// Record that the current thread is waiting for `id`.
+pub(crate) fn record_waiting(id: LockId) {
+    with_current(|s| s.locks.waiting = Some(id));
+}
+
+/// Record that the current thread acquired `id`, which it was waiting for.
+pub(crate) fn record_acquired(id: LockId) {
+    with_current(|s| {
+        s.locks.waiting = None;
+        s.locks.acquired += 1;
+        match s.locks.held.iter_mut().find(|h| h.is_none()) {
+            Some(free) => *free = Some(id),
+            None => s.locks.unnamed += 1,
+        }
+    });
+}
+
+/// Record that the current thread gave up waiting for `id`, as locking it failed.
+pub(crate) fn record_not_acquired(id: LockId) {
+    with_current(|s| {
+        if s.locks.waiting == Some(id) {
+            s.locks.waiting = None;
+        }
+    });
+}
+
+/// Record that the current thread released `id`.
+pub(crate) fn record_released(id: LockId) {
+    with_current(|s| {
+        // the most recent acquisition of id, closing the gap so the rest stay in order
+        match s.locks.held.iter().rposition(|h| *h == Some(id)) {
+            Some(i) => {
+                s.locks.held.copy_within(i + 1.., i);
+                s.locks.held[MAX_HELD - 1] = None;
+            },
+            None => s.locks.unnamed = s.locks.unnamed.saturating_sub(1),
+        }
+    });
+}
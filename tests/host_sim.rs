@@ -0,0 +1,551 @@
+//! Runs the registered suite in-process against the host simulation of the kernel.
+//!
+//! cargo test --no-default-features --features host-sim
+
+use wdk_mutex_tests::host_sim::{self, record_progress, tracked_lock, Level, LockId, LogRecord, LogRing, SimIrp, Suite, SystemThreadGroup, TestCase, TestConfig, TestContext, TestError, TestResult, TimeoutPolicy};
+use std::{sync::{atomic::{AtomicBool, AtomicUsize, Ordering}, Mutex}, thread, time::Duration};
+
+use wdk_mutex_tests_protocol::{Event, Format, Request, Response, ResponseHeader, Status, TestFilter, RESPONSE_HEADER_LEN};
+
+/// The simulated Grt and the runner are process wide, like the driver's, so runs must not overlap.
+static SERIAL: Mutex<()> = Mutex::new(());
+
+#[test]
+fn registered_tests_pass_under_host_sim() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let outcomes = host_sim::run_suite(&TestConfig::default(), &TestFilter::default());
+
+    let failures: Vec<_> = outcomes
+        .iter()
+        .filter(|o| o.status == Status::Failed)
+        .map(|o| format!("{}: {}", o.name, o.reason))
+        .collect();
+
+    assert!(!outcomes.is_empty(), "no tests were registered");
+    assert!(failures.is_empty(), "failed tests: {failures:#?}");
+}
+
+#[test]
+fn expected_totals_follow_the_configured_contention() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let config = TestConfig { threads: 8, iterations: 50, ..TestConfig::default() };
+    let filter = TestFilter { tag: Some("multithread".into()), ..TestFilter::default() };
+    let outcomes = host_sim::run_suite(&config, &filter);
+
+    assert!(!outcomes.is_empty(), "no multithreaded tests were registered");
+    for o in &outcomes {
+        assert_eq!(o.status, Status::Passed, "{}: {}", o.name, o.reason);
+    }
+}
+
+#[test]
+fn clamping_leaves_room_for_every_round() {
+    let config = TestConfig { threads: u32::MAX, iterations: u32::MAX, ..TestConfig::default() }.clamped();
+    let passes = config.threads as u64 * config.iterations as u64 * host_sim::MAX_ROUNDS as u64;
+
+    assert_eq!(config.threads, host_sim::MAX_THREADS);
+    assert!(passes <= u32::MAX as u64, "{passes} passes overflow the counters");
+}
+
+#[test]
+fn soak_runs_for_the_configured_duration() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let config = TestConfig { soak_seconds: 1, ..TestConfig::default() };
+    let filter = TestFilter { tag: Some("soak".into()), ..TestFilter::default() };
+    let outcomes = host_sim::run_suite(&config, &filter);
+
+    assert!(!outcomes.is_empty(), "no soak tests were registered");
+    for o in &outcomes {
+        assert_eq!(o.status, Status::Passed, "{}: {}", o.name, o.reason);
+        assert!(o.duration_us >= 1_000_000, "{} finished after {} us", o.name, o.duration_us);
+    }
+}
+
+#[test]
+fn the_harness_copes_with_failed_pool_allocations() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let config = TestConfig { iterations: 50, ..TestConfig::default() };
+    let filter = TestFilter { tag: Some("alloc".into()), ..TestFilter::default() };
+    let outcomes = host_sim::run_suite(&config, &filter);
+
+    assert_eq!(outcomes.len(), 4, "{outcomes:?}");
+    for o in &outcomes {
+        assert_eq!(o.status, Status::Passed, "{}: {}", o.name, o.reason);
+    }
+}
+
+#[test]
+fn repeated_runs_do_not_collide_in_the_grt() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let config = TestConfig { repeat: 2, iterations: 50, ..TestConfig::default() };
+    let outcomes = host_sim::run_suite(&config, &TestFilter::default());
+    let once = outcomes.len() / 2;
+
+    assert!(once != 0, "no tests were registered");
+    for o in &outcomes {
+        assert_ne!(o.status, Status::Failed, "{}: {}", o.name, o.reason);
+    }
+    // every test ran both times, in the same order
+    for (first, second) in outcomes[..once].iter().zip(&outcomes[once..]) {
+        assert_eq!(first.name, second.name);
+        assert_eq!(first.status, second.status, "{}", first.name);
+    }
+}
+
+#[test]
+fn grt_benchmark_runs_when_enabled() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let config = TestConfig { benchmarks: true, ..TestConfig::default() };
+    let filter = TestFilter { tag: Some("bench".into()), ..TestFilter::default() };
+    let outcomes = host_sim::run_suite(&config, &filter);
+
+    assert!(!outcomes.is_empty(), "no benchmarks were registered");
+    for o in &outcomes {
+        assert_eq!(o.status, Status::Passed, "{}: {}", o.name, o.reason);
+    }
+}
+
+#[test]
+fn a_thread_that_cannot_be_referenced_is_still_joined() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    static FINISHED: AtomicUsize = AtomicUsize::new(0);
+
+    let mut threads = SystemThreadGroup::new();
+    host_sim::fail_next_thread_reference();
+    threads.spawn(3, || {
+        thread::sleep(Duration::from_millis(20));
+        FINISHED.fetch_add(1, Ordering::SeqCst);
+    }).unwrap();
+
+    assert_eq!(threads.join_all(), Ok(3));
+    assert_eq!(FINISHED.load(Ordering::SeqCst), 3);
+}
+
+/// Releases the thread [`hangs`] leaves running.
+static RELEASE_HUNG: AtomicBool = AtomicBool::new(false);
+static HUNG_STARTED: AtomicUsize = AtomicUsize::new(0);
+
+/// Joins two threads, the first of which does not exit until released.
+fn hangs(_: &TestContext) -> TestResult {
+    let mut threads = SystemThreadGroup::new();
+    threads.spawn(2, || {
+        if HUNG_STARTED.fetch_add(1, Ordering::SeqCst) == 0 {
+            record_progress("waiting to be released");
+            while !RELEASE_HUNG.load(Ordering::SeqCst) {
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+    }).map_err(|s| TestError::status("spawning threads", s))?;
+    threads.join_all().map_err(|s| TestError::status("joining threads", s))?;
+    Ok(())
+}
+
+fn passes(_: &TestContext) -> TestResult {
+    Ok(())
+}
+
+static HANGING: &[TestCase] = &[
+    TestCase { name: "harness::hangs", suite: Suite::KMutex, tags: &[], run: hangs },
+    TestCase { name: "harness::after_the_hang", suite: Suite::KMutex, tags: &[], run: passes },
+];
+
+/// Run [`HANGING`] under `policy`, then release and join the hung thread.
+fn run_hanging(policy: TimeoutPolicy) -> Vec<wdk_mutex_tests_protocol::TestOutcome> {
+    HUNG_STARTED.store(0, Ordering::SeqCst);
+    RELEASE_HUNG.store(false, Ordering::SeqCst);
+
+    let config = TestConfig { timeout_seconds: 1, on_timeout: policy, ..TestConfig::default() };
+    let outcomes = host_sim::run_cases(&config, HANGING);
+
+    assert_eq!(host_sim::abandoned_threads(), 1);
+    RELEASE_HUNG.store(true, Ordering::SeqCst);
+    assert_eq!(host_sim::join_abandoned_threads(), 1);
+    assert_eq!(host_sim::abandoned_threads(), 0);
+    outcomes
+}
+
+#[test]
+fn a_test_whose_threads_miss_the_deadline_times_out() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+
+    let outcomes = run_hanging(TimeoutPolicy::Continue);
+    assert_eq!(outcomes.len(), 2);
+    let hung = &outcomes[0];
+    assert_eq!(hung.status, Status::Failed);
+    assert!(hung.reason.starts_with("timed out: after 1 s, 1 of 2 thread(s) still running; thread 0x"), "{}", hung.reason);
+    assert!(hung.reason.contains(", 1 step(s), last `waiting to be released`"), "{}", hung.reason);
+    assert!(hung.duration_us >= 1_000_000, "{}", hung.duration_us);
+    // the run went on
+    assert_eq!(outcomes[1].status, Status::Passed);
+
+    let outcomes = run_hanging(TimeoutPolicy::Abort);
+    assert_eq!(outcomes.len(), 1, "{outcomes:?}");
+    assert!(outcomes[0].reason.starts_with("timed out: "), "{}", outcomes[0].reason);
+}
+
+static STALLED: Mutex<u32> = Mutex::new(0);
+static STALL_STARTED: AtomicUsize = AtomicUsize::new(0);
+static STALL_HOLDING: AtomicBool = AtomicBool::new(false);
+static STALL_RELEASE: AtomicBool = AtomicBool::new(false);
+
+/// One thread holds [`STALLED`] while another waits for it, long enough for the watchdog to fire.
+fn stalls(_: &TestContext) -> TestResult {
+    let mut threads = SystemThreadGroup::new();
+    threads.spawn(2, || {
+        if STALL_STARTED.fetch_add(1, Ordering::SeqCst) == 0 {
+            record_progress("holding until released");
+            let _guard = tracked_lock(LockId::Key("harness::stalled"), || STALLED.lock()).unwrap();
+            STALL_HOLDING.store(true, Ordering::SeqCst);
+            while !STALL_RELEASE.load(Ordering::SeqCst) {
+                thread::sleep(Duration::from_millis(1));
+            }
+        } else {
+            while !STALL_HOLDING.load(Ordering::SeqCst) {
+                thread::sleep(Duration::from_millis(1));
+            }
+            record_progress("locking");
+            let _guard = tracked_lock(LockId::Key("harness::stalled"), || STALLED.lock()).unwrap();
+        }
+    }).map_err(|s| TestError::status("spawning threads", s))?;
+
+    thread::sleep(Duration::from_millis(2_500));
+    STALL_RELEASE.store(true, Ordering::SeqCst);
+    threads.join_all().map_err(|s| TestError::status("joining threads", s))?;
+    Ok(())
+}
+
+static STALLING: &[TestCase] = &[TestCase { name: "harness::stalls", suite: Suite::KMutex, tags: &[], run: stalls }];
+
+#[test]
+fn the_watchdog_prints_who_waits_for_and_holds_what() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    read_log(4096);
+
+    host_sim::start_watchdog(1);
+    let outcomes = host_sim::run_cases(&TestConfig::default(), STALLING);
+    host_sim::stop_watchdog();
+    assert_eq!(outcomes[0].status, Status::Passed, "{outcomes:?}");
+
+    let log = read_log(4096);
+    let snapshot: Vec<_> = log.lines().filter(|l| l.contains("Watchdog:")).collect();
+    assert_eq!(snapshot.len(), 3, "one stall, two workers:\n{log}");
+    assert!(snapshot[0].contains("running harness::stalls, 2 worker(s):"), "{}", snapshot[0]);
+    let holder = snapshot.iter().find(|l| l.contains("`holding until released`")).expect(&log);
+    assert!(holder.ends_with(", holding `harness::stalled`"), "{holder}");
+    let waiter = snapshot.iter().find(|l| l.contains("`locking`")).expect(&log);
+    assert!(waiter.ends_with(", waiting for `harness::stalled`"), "{waiter}");
+}
+
+#[test]
+fn the_lock_order_tests_see_what_they_expect() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let config = TestConfig { threads: 4, iterations: 50, ..TestConfig::default() };
+    let filter = TestFilter { suite: Some(Suite::LockOrder), ..TestFilter::default() };
+    let outcomes = host_sim::run_suite(&config, &filter);
+
+    assert_eq!(outcomes.len(), 3, "{outcomes:?}");
+    for o in &outcomes {
+        assert_eq!(o.status, Status::Passed, "{}: {}", o.name, o.reason);
+    }
+}
+
+static FIRST: Mutex<()> = Mutex::new(());
+static SECOND: Mutex<()> = Mutex::new(());
+
+/// Takes [`SECOND`] inside [`FIRST`] on one thread, then the other way round on another once the
+/// first has exited, so the two orders never meet.
+fn inverts(_: &TestContext) -> TestResult {
+    for (outer, inner) in [(("harness::first", &FIRST), ("harness::second", &SECOND)), (("harness::second", &SECOND), ("harness::first", &FIRST))] {
+        let mut threads = SystemThreadGroup::new();
+        threads.spawn(1, move || {
+            let _outer = tracked_lock(LockId::Key(outer.0), || outer.1.lock()).unwrap();
+            let _inner = tracked_lock(LockId::Key(inner.0), || inner.1.lock()).unwrap();
+        }).map_err(|s| TestError::status("spawning threads", s))?;
+        threads.join_all().map_err(|s| TestError::status("joining threads", s))?;
+    }
+    Ok(())
+}
+
+static INVERTING: &[TestCase] = &[
+    TestCase { name: "harness::inverts", suite: Suite::KMutex, tags: &[], run: inverts },
+    TestCase { name: "harness::after_the_inversion", suite: Suite::KMutex, tags: &[], run: passes },
+];
+
+#[test]
+fn an_inversion_fails_the_test_without_a_deadlock() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    read_log(4096);
+
+    let outcomes = host_sim::run_cases(&TestConfig::default(), INVERTING);
+    assert_eq!(outcomes[0].status, Status::Failed, "{outcomes:?}");
+    let reason = &outcomes[0].reason;
+    assert!(reason.starts_with("lock order inversion: thread 0x"), "{reason}");
+    assert!(reason.contains(" took `harness::first` while holding `harness::second`, but thread 0x"), "{reason}");
+    assert!(reason.ends_with(" took `harness::second` while holding `harness::first`"), "{reason}");
+    // the graph does not outlive the test
+    assert_eq!(outcomes[1].status, Status::Passed, "{outcomes:?}");
+
+    // printed when the second order was seen, before the test ended
+    let log = read_log(4096);
+    let printed = log.find("[-] Lock order inversion: thread 0x").expect(&log);
+    let result = log.find("#WMT1 FAIL").expect(&log);
+    assert!(printed < result, "{log}");
+}
+
+#[test]
+fn open_and_close_requests_succeed() {
+    for major in [host_sim::IRP_MJ_CREATE, host_sim::IRP_MJ_CLEANUP, host_sim::IRP_MJ_CLOSE] {
+        let mut irp = SimIrp::new(major);
+        assert_eq!(host_sim::dispatch_irp(&mut irp), host_sim::STATUS_SUCCESS, "major function {major:#x}");
+        assert_eq!(irp.status(), Some(host_sim::STATUS_SUCCESS));
+        assert!(irp.output().is_empty());
+    }
+}
+
+#[test]
+fn unsupported_requests_are_completed_as_invalid() {
+    // IRP_MJ_WRITE, IRP_MJ_FLUSH_BUFFERS and IRP_MJ_SHUTDOWN
+    for major in [0x04, 0x09, 0x10] {
+        let mut irp = SimIrp::new(major);
+        assert_eq!(host_sim::dispatch_irp(&mut irp), host_sim::STATUS_INVALID_DEVICE_REQUEST);
+        assert_eq!(irp.status(), Some(host_sim::STATUS_INVALID_DEVICE_REQUEST), "major function {major:#x}");
+    }
+}
+
+#[test]
+fn device_control_lists_the_registered_tests() {
+    let request = Request::ListTests { format: Format::Binary };
+    let mut irp = SimIrp::device_control(request.ioctl_code(), &request.encode(), 64 * 1024);
+
+    assert_eq!(host_sim::dispatch_irp(&mut irp), host_sim::STATUS_SUCCESS);
+    let Response::Tests(tests) = Response::decode(irp.output()).unwrap() else {
+        panic!("expected a test list");
+    };
+    assert!(tests.iter().any(|t| t.name == "kmutex::to_owned"), "{tests:#?}");
+}
+
+#[test]
+fn device_control_reports_the_size_of_a_response_that_does_not_fit() {
+    let request = Request::ListTests { format: Format::Binary };
+    let mut irp = SimIrp::device_control(request.ioctl_code(), &request.encode(), RESPONSE_HEADER_LEN);
+
+    assert_eq!(host_sim::dispatch_irp(&mut irp), host_sim::STATUS_BUFFER_OVERFLOW);
+    let header = ResponseHeader::decode(irp.output()).unwrap();
+    assert!(header.total_len as usize > RESPONSE_HEADER_LEN);
+
+    let mut irp = SimIrp::device_control(request.ioctl_code(), &request.encode(), RESPONSE_HEADER_LEN - 1);
+    assert_eq!(host_sim::dispatch_irp(&mut irp), host_sim::STATUS_BUFFER_TOO_SMALL);
+    assert!(irp.output().is_empty());
+}
+
+#[test]
+fn device_control_rejects_malformed_requests() {
+    let request = Request::ListTests { format: Format::Binary };
+    let mut irp = SimIrp::device_control(request.ioctl_code(), b"garbage", 4096);
+
+    assert_eq!(host_sim::dispatch_irp(&mut irp), host_sim::STATUS_INVALID_PARAMETER);
+    assert!(irp.output().is_empty());
+}
+
+/// Drain the device's log through `IRP_MJ_READ`s of `chunk` bytes.
+fn read_log(chunk: usize) -> String {
+    let mut log = Vec::new();
+    loop {
+        let mut irp = SimIrp::read(chunk);
+        assert_eq!(host_sim::dispatch_irp(&mut irp), host_sim::STATUS_SUCCESS);
+        if irp.output().is_empty() {
+            break;
+        }
+        log.extend_from_slice(irp.output());
+    }
+
+    String::from_utf8(log).unwrap()
+}
+
+#[test]
+fn read_drains_what_the_run_printed() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    read_log(4096);
+
+    let filter = TestFilter { name: Some("kmutex::*".into()), ..TestFilter::default() };
+    let outcomes = host_sim::run_suite(&TestConfig::default(), &filter);
+
+    // small reads, so the log comes back over several of them
+    let log = read_log(200);
+    for o in &outcomes {
+        let start = format!("INFO {}: [wdk-mutex-test] [i] Running {}...", o.name, o.name);
+        assert!(log.contains(&start), "{} is missing from the log:\n{log}", o.name);
+    }
+    // printed after the last test, so it belongs to none
+    let summary = log.lines().find(|l| l.contains("Passed: ")).unwrap();
+    assert!(summary.contains(" INFO -: "), "{summary}");
+    assert!(summary.contains(", failed: 0,"), "{log}");
+
+    // drained, nothing is returned twice
+    assert_eq!(read_log(4096), "");
+}
+
+#[test]
+fn tiny_reads_still_make_progress() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    read_log(4096);
+
+    let filter = TestFilter { name: Some("kmutex::to_owned".into()), ..TestFilter::default() };
+    host_sim::run_suite(&TestConfig::default(), &filter);
+
+    // every record is cut to its first few bytes, which are its timestamp
+    let log = read_log(8);
+    assert!(!log.is_empty());
+    assert!(log.split("[ ").skip(1).all(|l| l.len() == 6), "{log}");
+}
+
+#[test]
+fn the_log_records_each_result_as_an_event() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    read_log(4096);
+
+    let filter = TestFilter { name: Some("kmutex::*".into()), ..TestFilter::default() };
+    let outcomes = host_sim::run_suite(&TestConfig::default(), &filter);
+
+    let events: Vec<_> = read_log(4096).lines().filter_map(Event::parse).collect();
+    assert_eq!(events.first(), Some(&Event::RunStart { tests: outcomes.len() as u32 }));
+    let results: Vec<_> = events
+        .iter()
+        .filter_map(|e| match e {
+            Event::Result { test, status, .. } => Some((test.as_str(), *status)),
+            _ => None,
+        })
+        .collect();
+    let expected: Vec<_> = outcomes.iter().map(|o| (o.name.as_str(), o.status)).collect();
+    assert_eq!(results, expected);
+    let passed = outcomes.iter().filter(|o| o.status == Status::Passed).count() as u32;
+    assert!(matches!(events.last(), Some(Event::RunEnd { passed: p, failed: 0, .. }) if *p == passed), "{events:?}");
+}
+
+#[test]
+fn log_ring_returns_records_in_order() {
+    let mut ring = LogRing::with_capacity(1024);
+    ring.push(1_500_000, 2, Level::Error, "kmutex::to_owned", "bad value");
+    ring.push(1_600_000, 0, Level::Info, "", "done");
+
+    let first = ring.pop().unwrap();
+    assert_eq!((first.seq, first.timestamp_us, first.cpu, first.level), (0, 1_500_000, 2, Level::Error));
+    assert_eq!((first.test.as_str(), first.message.as_str()), ("kmutex::to_owned", "bad value"));
+    assert_eq!(first.to_string(), "[    1.500000] cpu2 ERROR kmutex::to_owned: bad value");
+    assert_eq!(ring.pop().unwrap().to_string(), "[    1.600000] cpu0 INFO -: done");
+    assert_eq!(ring.pop(), None);
+    assert_eq!(ring.dropped(), 0);
+}
+
+#[test]
+fn log_ring_evicts_the_oldest_records_when_full() {
+    let mut ring = LogRing::with_capacity(host_sim::LOG_RECORD_HEADER_LEN * 4 + 40);
+    for i in 0..100 {
+        ring.push(i, 0, Level::Info, "t", &format!("{i:>9}"));
+    }
+
+    let kept: Vec<_> = std::iter::from_fn(|| ring.pop()).collect();
+    assert!(!kept.is_empty());
+    assert_eq!(kept.last().unwrap().seq, 99);
+    assert_eq!(ring.take_dropped() as usize, 100 - kept.len());
+    for (r, seq) in kept.iter().zip(100 - kept.len() as u64..) {
+        assert_eq!(r.seq, seq);
+        assert_eq!(r.message, format!("{seq:>9}"));
+    }
+}
+
+#[test]
+fn log_ring_cuts_records_larger_than_itself() {
+    let capacity = host_sim::LOG_RECORD_HEADER_LEN + 10;
+    let mut ring = LogRing::with_capacity(capacity);
+    ring.push(0, 0, Level::Info, "test", "é".repeat(100).as_str());
+
+    let r = ring.pop().unwrap();
+    assert_eq!(r.test, "test");
+    // six bytes of room, cut to whole characters
+    assert_eq!(r.message, "ééé");
+}
+
+#[test]
+fn log_ring_pops_raw_records_without_growing() {
+    let mut ring = LogRing::with_capacity(1024);
+    ring.push(1_500_000, 2, Level::Error, "kmutex::to_owned", "bad value");
+    ring.push(1_600_000, 0, Level::Info, "", "é".repeat(20).as_str());
+
+    let mut raw = Vec::with_capacity(1024);
+    assert!(ring.pop_raw(&mut raw));
+    let (first, len) = LogRecord::decode(&raw).unwrap();
+    assert_eq!(first.to_string(), "[    1.500000] cpu2 ERROR kmutex::to_owned: bad value");
+    assert_eq!(len, raw.len());
+
+    // room for the header and five bytes of the message, cut to whole characters
+    let mut raw = Vec::with_capacity(host_sim::LOG_RECORD_HEADER_LEN + 5);
+    assert!(ring.pop_raw(&mut raw));
+    assert_eq!(raw.capacity(), host_sim::LOG_RECORD_HEADER_LEN + 5);
+    assert_eq!(LogRecord::decode(&raw).unwrap().0.message, "éé");
+
+    assert!(!ring.pop_raw(&mut raw));
+}
+
+/// Random pushes and pops of random sized records, checked against a model that keeps every
+/// record and evicts from the front exactly when the ring has to.
+#[test]
+fn log_ring_matches_a_model_under_random_use() {
+    let mut rng = 0x2545_f491_4f6c_dd1d_u64;
+    let mut next = move || {
+        rng ^= rng << 13;
+        rng ^= rng >> 7;
+        rng ^= rng << 17;
+        rng
+    };
+
+    for capacity in [host_sim::LOG_RECORD_HEADER_LEN, 64, 100, 333, 4096] {
+        let mut ring = LogRing::with_capacity(capacity);
+        let mut model = std::collections::VecDeque::new();
+        let mut used = 0;
+        let mut dropped = 0;
+
+        for _ in 0..20_000 {
+            if next() % 3 == 0 {
+                let expected = model.pop_front().map(|(r, len): (LogRecord, usize)| {
+                    used -= len;
+                    r
+                });
+                assert_eq!(ring.pop(), expected, "capacity {capacity}");
+                continue;
+            }
+
+            let test = "abcdefgh"[..(next() % 9) as usize].to_string();
+            let message = "ü".repeat((next() % 80) as usize);
+            let (timestamp_us, cpu) = (next(), next() as u32);
+            let seq = ring.push(timestamp_us, cpu, Level::Success, &test, &message);
+
+            // what the ring must have kept of it
+            let room = capacity - host_sim::LOG_RECORD_HEADER_LEN;
+            let test = test[..test.len().min(room)].to_string();
+            let mut keep = (room - test.len()).min(message.len());
+            while !message.is_char_boundary(keep) {
+                keep -= 1;
+            }
+            let message = message[..keep].to_string();
+            let len = host_sim::LOG_RECORD_HEADER_LEN + test.len() + message.len();
+
+            while capacity - used < len {
+                let (_, evicted) = model.pop_front().unwrap();
+                used -= evicted;
+                dropped += 1;
+            }
+            let peeked = ring.peek().unwrap();
+            let record = LogRecord { seq, timestamp_us, cpu, level: Level::Success, test, message };
+            model.push_back((record, len));
+            used += len;
+
+            assert_eq!(ring.dropped(), dropped, "capacity {capacity}");
+            assert_eq!(peeked.seq, model.front().unwrap().0.seq, "capacity {capacity}");
+            assert_eq!(ring.peek_line_len(), Some(peeked.to_string().len()), "capacity {capacity}");
+        }
+
+        let rest: Vec<_> = std::iter::from_fn(|| ring.pop()).collect();
+        assert_eq!(rest, model.into_iter().map(|(r, _)| r).collect::<Vec<_>>(), "capacity {capacity}");
+    }
+}